@@ -89,6 +89,8 @@ docker run --rm -v "$MOUNT_DIR:/output" ubuntu:22.04 sh -c '
     
     # Create necessary directories
     mkdir -p /output/{tmp,proc,sys,dev,run}
+    # Mount point for the target's root (see $CONTAINER_ROOT)
+    mkdir -p /output/target
     
     # Set up minimal /etc files for the debugging environment
     echo "root:x:0:0:root:/root:/bin/bash" > /output/etc/passwd
@@ -111,11 +113,13 @@ if [ -z "$TARGET_PID" ] || [ -z "$COMMAND" ]; then
 fi
 
 # Enter target's PID namespace for process visibility
-# but keep our own mount namespace for tools
+# but keep our own mount namespace for tools; the target's
+# files stay reachable under /target
 exec nsenter -t "$TARGET_PID" -p -n -i -u -- \
     chroot /dev/crashcart \
     env PATH=/bin:/sbin:/usr/bin:/usr/sbin \
     LD_LIBRARY_PATH=/lib:/lib64:/usr/lib:/usr/lib64 \
+    CONTAINER_ROOT=/target \
     $COMMAND
 EOF
 
@@ -37,8 +37,9 @@ mkdir -p "$MOUNT_DIR"
 sudo mount "$LOOP_DEVICE" "$MOUNT_DIR"
 echo "Mounted image at $MOUNT_DIR"
 
-# Create directory structure
-sudo mkdir -p "$MOUNT_DIR"/{bin,sbin,lib,lib64,usr/{bin,sbin,lib,lib64},etc,tmp,var,dev,proc,sys}
+# Create directory structure, with /target as the mount point for the
+# target's root (see $CONTAINER_ROOT)
+sudo mkdir -p "$MOUNT_DIR"/{bin,sbin,lib,lib64,usr/{bin,sbin,lib,lib64},etc,tmp,var,dev,proc,sys,target}
 
 echo "=== Phase 1: Alpine tools (musl-based) ==="
 docker run --rm -v "$MOUNT_DIR:/output" alpine:latest sh -c '
@@ -37,8 +37,9 @@ mkdir -p "$MOUNT_DIR"
 sudo mount "$LOOP_DEVICE" "$MOUNT_DIR"
 echo "Mounted image at $MOUNT_DIR"
 
-# Create directory structure
-sudo mkdir -p "$MOUNT_DIR"/{bin,sbin,lib,usr/{bin,sbin},etc,tmp,var,static}
+# Create directory structure, with /target as the mount point for the
+# target's root (see $CONTAINER_ROOT)
+sudo mkdir -p "$MOUNT_DIR"/{bin,sbin,lib,usr/{bin,sbin},etc,tmp,var,static,target}
 
 echo "=== Phase 1: Static binaries (universal compatibility) ==="
 
@@ -93,6 +93,8 @@ docker run --rm -v "$MOUNT_DIR:/output" ubuntu:22.04 sh -c '
     mkdir -p /output/dev
     mkdir -p /output/proc
     mkdir -p /output/sys
+    # Mount point for the target's root (see $CONTAINER_ROOT)
+    mkdir -p /output/target
     
     # Copy essential binaries (with error handling)
     echo "Copying debugging binaries..."
@@ -216,11 +218,13 @@ if [ -z "$TARGET_PID" ] || [ -z "$COMMAND" ]; then
 fi
 
 # Enter target's PID namespace for process visibility
-# but keep our own mount namespace for tools
+# but keep our own mount namespace for tools; the target's
+# files stay reachable under /target
 exec nsenter -t "$TARGET_PID" -p -n -i -u -- \
     chroot /dev/crashcart \
     env PATH=/bin:/sbin:/usr/bin:/usr/sbin \
     LD_LIBRARY_PATH=/lib:/lib64:/usr/lib:/usr/lib64 \
+    CONTAINER_ROOT=/target \
     $COMMAND
 EOF
 
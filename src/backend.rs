@@ -0,0 +1,273 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::cgroup::CgroupBackend;
+use crate::config::Config;
+use crate::containerd::ContainerdBackend;
+use crate::cri::CriBackend;
+use crate::docker::DockerBackend;
+use crate::info::ContainerInfo;
+use crate::lxc::{LxcBackend, LxdBackend};
+use crate::podman::PodmanBackend;
+use crate::systemd::{MachineBackend, SystemdBackend};
+
+/// A container runtime crashcart can resolve targets against.
+///
+/// Implement this to teach crashcart about an in-house runtime and add it to
+/// a [`BackendRegistry`] passed to
+/// [`ContainerRuntime::detect_with`](crate::ContainerRuntime::detect_with).
+#[async_trait]
+pub trait RuntimeBackend: Send + Sync {
+    /// Short, stable name of the backend (e.g. `docker`).
+    fn name(&self) -> &str;
+
+    /// Whether the runtime's daemon or CLI is reachable on this host.
+    async fn is_available(&self) -> bool;
+
+    /// Whether a target given without a scheme may name one of this
+    /// backend's containers, so is worth probing for. `<scheme>://`
+    /// targets reach their backend either way.
+    fn claims(&self, target: &str) -> bool {
+        let _ = target;
+        true
+    }
+
+    /// Look up `target` and return the runtime's canonical container ID.
+    async fn detect(&self, target: &str) -> Result<String>;
+
+    /// PID of the container's init process, as seen from the host.
+    async fn resolve_pid(&self, id: &str) -> Result<u32>;
+
+    /// Runtime-specific metadata for the container.
+    async fn inspect(&self, id: &str) -> Result<Value>;
+
+    /// Typed metadata for the container. The default only knows what
+    /// `/proc/<pid>` shows; backends override it to parse their inspect
+    /// output.
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        let pid = self.resolve_pid(id).await?;
+        let mut info = ContainerInfo {
+            runtime: self.name().to_string(),
+            id: id.to_string(),
+            name: id.to_string(),
+            state: "running".to_string(),
+            ..Default::default()
+        };
+        info.complete_from_proc(Path::new("/proc"), pid);
+        Ok(info)
+    }
+
+    /// Run `command` inside the container through the runtime itself.
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let _ = (id, command);
+        Err(anyhow!("The {} backend does not support exec mode", self.name()))
+    }
+
+    /// Containers currently known to the runtime.
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        Ok(Vec::new())
+    }
+}
+
+/// One row of a container listing.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerSummary {
+    pub runtime: String,
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub state: String,
+    pub pid: Option<u32>,
+}
+
+/// Ordered set of runtime backends. [`ContainerRuntime::detect_with`]
+/// probes them in registration order and uses the first that knows the
+/// target.
+///
+/// [`ContainerRuntime::detect_with`]: crate::ContainerRuntime::detect_with
+#[derive(Clone)]
+pub struct BackendRegistry {
+    backends: Vec<Arc<dyn RuntimeBackend>>,
+    timeout: Duration,
+    backend_timeouts: HashMap<String, Duration>,
+}
+
+/// A probe started by [`BackendRegistry::probe_all`] and the backend it
+/// runs against.
+pub type Probe<T> = (Arc<dyn RuntimeBackend>, JoinHandle<Result<T>>);
+
+/// How long a backend may take to answer a probe unless configured
+/// otherwise.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl Default for BackendRegistry {
+    /// The built-in backends: Docker, Podman, containerd, CRI, systemd
+    /// units and machines, LXD, Incus and LXC, and the runtime-independent
+    /// cgroup scan as a last resort.
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register(Arc::new(DockerBackend::default()))
+            .register(Arc::new(PodmanBackend))
+            .register(Arc::new(ContainerdBackend))
+            .register(Arc::new(CriBackend))
+            .register(Arc::new(SystemdBackend::default()))
+            .register(Arc::new(MachineBackend::default()))
+            .register(Arc::new(LxdBackend::lxd()))
+            .register(Arc::new(LxdBackend::incus()))
+            .register(Arc::new(LxcBackend::default()))
+            .register(Arc::new(CgroupBackend));
+        registry
+    }
+}
+
+impl BackendRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self {
+            backends: Vec::new(),
+            timeout: DEFAULT_PROBE_TIMEOUT,
+            backend_timeouts: HashMap::new(),
+        }
+    }
+
+    /// The built-in backends with probe timeouts from `config`.
+    pub fn from_config(config: &Config) -> Self {
+        let mut registry = Self::default();
+        registry.set_timeout(config.probe.timeout());
+        for (name, timeout) in config.probe.backend_timeouts() {
+            registry.set_backend_timeout(name, timeout);
+        }
+        registry
+    }
+
+    /// Append a backend; it is probed after those already registered.
+    pub fn register(&mut self, backend: Arc<dyn RuntimeBackend>) -> &mut Self {
+        self.backends.push(backend);
+        self
+    }
+
+    /// Insert a backend ahead of all others.
+    pub fn register_first(&mut self, backend: Arc<dyn RuntimeBackend>) -> &mut Self {
+        self.backends.insert(0, backend);
+        self
+    }
+
+    /// Timeout for backends without their own.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn set_backend_timeout(&mut self, name: &str, timeout: Duration) -> &mut Self {
+        self.backend_timeouts.insert(name.to_string(), timeout);
+        self
+    }
+
+    pub fn timeout_for(&self, name: &str) -> Duration {
+        self.backend_timeouts.get(name).copied().unwrap_or(self.timeout)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn RuntimeBackend>> {
+        self.backends.iter().find(|b| b.name() == name).cloned()
+    }
+
+    pub fn backends(&self) -> impl Iterator<Item = &Arc<dyn RuntimeBackend>> {
+        self.backends.iter()
+    }
+
+    /// Run `f` against one backend, failing if the backend is unavailable
+    /// or does not finish within its timeout.
+    pub async fn probe<T, F, Fut>(&self, backend: Arc<dyn RuntimeBackend>, f: F) -> Result<T>
+    where
+        F: FnOnce(Arc<dyn RuntimeBackend>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let timeout = self.timeout_for(backend.name());
+        let name = backend.name().to_string();
+        let work = async {
+            if !backend.is_available().await {
+                return Err(anyhow!("The {} backend is not available on this host", name));
+            }
+            f(backend.clone()).await
+        };
+
+        tokio::time::timeout(timeout, work)
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("The {} backend did not answer within {:?}", name, timeout)))
+    }
+
+    /// Start [`BackendRegistry::probe`] on every backend concurrently. The
+    /// handles come back in registration order, so callers can honour
+    /// backend priority while slow backends are still running.
+    pub fn probe_all<T, F, Fut>(&self, f: F) -> Vec<Probe<T>>
+    where
+        T: Send + 'static,
+        F: Fn(Arc<dyn RuntimeBackend>) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+    {
+        self.backends
+            .iter()
+            .map(|backend| {
+                let registry = self.clone();
+                let work = f(backend.clone());
+                let probed = backend.clone();
+                let handle = tokio::spawn(async move { registry.probe(probed, |_| work).await });
+                (backend.clone(), handle)
+            })
+            .collect()
+    }
+
+    /// Containers known to every available backend, with PIDs resolved
+    /// where the listing left them out. A container seen by several
+    /// backends (say Docker and the cgroup scan) is reported once, by the
+    /// first backend in registration order.
+    pub async fn list_all(&self) -> Vec<ContainerSummary> {
+        let mut summaries = Vec::new();
+        let mut seen_ids = HashSet::new();
+        let mut seen_pids = HashSet::new();
+
+        let probes = self.probe_all(|backend| async move {
+            let mut listed = backend.list().await?;
+            for summary in &mut listed {
+                if summary.pid.is_none() && summary.state == "running" {
+                    summary.pid = backend.resolve_pid(&summary.id).await.ok();
+                }
+            }
+            Ok(listed)
+        });
+
+        for (backend, handle) in probes {
+            let listed = match handle.await {
+                Ok(Ok(listed)) => listed,
+                Ok(Err(e)) => {
+                    debug!("Not listing {} containers: {:#}", backend.name(), e);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Listing {} containers failed: {}", backend.name(), e);
+                    continue;
+                }
+            };
+
+            for summary in listed {
+                let duplicate = !seen_ids.insert(summary.id.clone())
+                    || summary.pid.is_some_and(|pid| !seen_pids.insert(pid));
+                if !duplicate {
+                    summaries.push(summary);
+                }
+            }
+        }
+
+        summaries
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+use crate::info::{self, ContainerInfo};
+
+/// Where resolutions are cached unless configured otherwise. `/run` is a
+/// tmpfs, so the cache never outlives a reboot.
+pub const DEFAULT_CACHE_DIR: &str = "/run/crashcart";
+
+const CACHE_FILE: &str = "targets.json";
+
+/// What a target string resolved to last time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResolution {
+    pub runtime: String,
+    pub id: String,
+    pub pid: u32,
+    /// Start time of `pid` in clock ticks since boot, so a reused PID is
+    /// not mistaken for the container.
+    pub start_ticks: u64,
+}
+
+/// Small on-disk map of target → (runtime, ID, PID, start time), letting
+/// repeat attaches skip the runtime probes entirely.
+pub struct ResolutionCache {
+    dir: PathBuf,
+    proc_root: PathBuf,
+}
+
+impl ResolutionCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self::with_proc_root(dir, "/proc")
+    }
+
+    /// A cache validating PIDs against an alternative `/proc`.
+    pub fn with_proc_root(dir: impl Into<PathBuf>, proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            proc_root: proc_root.into(),
+        }
+    }
+
+    /// The cached resolution of `target`, if its process is still the one
+    /// that was cached.
+    pub fn lookup(&self, target: &str) -> Option<CachedResolution> {
+        let entry = self.load().remove(target)?;
+        if self.is_current(&entry) {
+            Some(entry)
+        } else {
+            debug!("Cached resolution of {} is stale (PID {})", target, entry.pid);
+            None
+        }
+    }
+
+    /// Remember that `target` resolved to container `id` of `runtime`
+    /// running as `pid`. Entries whose process has gone are dropped.
+    pub fn store(&self, target: &str, runtime: &str, id: &str, pid: u32) -> Result<()> {
+        let start_ticks = info::process_start_ticks(&self.proc_root, pid)
+            .with_context(|| format!("Cannot read the start time of PID {}", pid))?;
+
+        let mut entries = self.load();
+        entries.retain(|_, entry| self.is_current(entry));
+        entries.insert(
+            target.to_string(),
+            CachedResolution {
+                runtime: runtime.to_string(),
+                id: id.to_string(),
+                pid,
+                start_ticks,
+            },
+        );
+        self.save(&entries)
+    }
+
+    /// Forget `target`, e.g. after its cached runtime could not use it.
+    pub fn remove(&self, target: &str) -> Result<()> {
+        let mut entries = self.load();
+        if entries.remove(target).is_some() {
+            self.save(&entries)?;
+        }
+        Ok(())
+    }
+
+    fn is_current(&self, entry: &CachedResolution) -> bool {
+        info::process_start_ticks(&self.proc_root, entry.pid) == Some(entry.start_ticks)
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(CACHE_FILE)
+    }
+
+    fn load(&self) -> BTreeMap<String, CachedResolution> {
+        fs::read_to_string(self.path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Replace the cache file atomically so concurrent crashcarts never
+    /// read a half-written one.
+    fn save(&self, entries: &BTreeMap<String, CachedResolution>) -> Result<()> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+
+        let temp = self.dir.join(format!("{}.{}", CACHE_FILE, std::process::id()));
+        fs::write(&temp, serde_json::to_vec(entries)?)
+            .with_context(|| format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, self.path()).with_context(|| format!("Failed to replace {}", self.path().display()))
+    }
+}
+
+/// A backend whose container was resolved from the cache: the cached PID
+/// answers [`RuntimeBackend::resolve_pid`] without asking the runtime,
+/// everything else goes to the real backend.
+pub(crate) struct CachedBackend {
+    inner: Arc<dyn RuntimeBackend>,
+    id: String,
+    pid: u32,
+}
+
+impl CachedBackend {
+    pub(crate) fn new(inner: Arc<dyn RuntimeBackend>, id: &str, pid: u32) -> Self {
+        Self {
+            inner,
+            id: id.to_string(),
+            pid,
+        }
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for CachedBackend {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn is_available(&self) -> bool {
+        self.inner.is_available().await
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        self.inner.detect(target).await
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        if id == self.id {
+            return Ok(self.pid);
+        }
+        self.inner.resolve_pid(id).await
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        self.inner.inspect(id).await
+    }
+
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        self.inner.info(id).await
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        self.inner.exec(id, command).await
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        self.inner.list().await
+    }
+}
+
@@ -0,0 +1,349 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+
+pub(crate) const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// How deep below the cgroup root to search for a named cgroup, enough for
+/// user units (`user.slice/user-1000.slice/user@1000.service/app.slice/...`).
+const MAX_SEARCH_DEPTH: usize = 6;
+
+/// Locates named cgroups (systemd units, LXC payloads) in the cgroup tree
+/// and reads their processes. Works on the unified (v2) hierarchy and the
+/// v1 `name=systemd` one.
+#[derive(Clone)]
+pub(crate) struct CgroupTree {
+    root: PathBuf,
+    resolver: CgroupResolver,
+}
+
+impl CgroupTree {
+    pub(crate) fn new(root: PathBuf, proc_root: PathBuf) -> Self {
+        Self {
+            root,
+            resolver: CgroupResolver::with_proc_root(proc_root),
+        }
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Hierarchy roots to search: v2 at the top, v1 under `systemd/`.
+    pub(crate) fn hierarchies(&self) -> Vec<PathBuf> {
+        let v1 = self.root.join("systemd");
+        if v1.is_dir() {
+            vec![v1, self.root.clone()]
+        } else {
+            vec![self.root.clone()]
+        }
+    }
+
+    /// The first existing directory among `candidates` (relative paths).
+    pub(crate) fn find_relative(&self, candidates: &[String]) -> Option<PathBuf> {
+        self.hierarchies().into_iter().find_map(|hierarchy| {
+            candidates
+                .iter()
+                .map(|c| hierarchy.join(c))
+                .find(|dir| dir.join("cgroup.procs").is_file())
+        })
+    }
+
+    /// Search the tree for a cgroup directory called `name`.
+    pub(crate) fn find_named(&self, name: &str) -> Option<PathBuf> {
+        self.hierarchies()
+            .into_iter()
+            .find_map(|hierarchy| find_dir(&hierarchy, name, MAX_SEARCH_DEPTH))
+    }
+
+    pub(crate) fn procs(&self, dir: &Path) -> Result<Vec<u32>> {
+        let procs = fs::read_to_string(dir.join("cgroup.procs"))
+            .with_context(|| format!("Failed to read {}/cgroup.procs", dir.display()))?;
+        Ok(procs.lines().filter_map(|l| l.trim().parse().ok()).collect())
+    }
+
+    pub(crate) fn init_process(&self, dir: &Path) -> Result<u32> {
+        let pids = self.procs(dir)?;
+        self.resolver
+            .init_process_of(&pids)
+            .ok_or_else(|| anyhow!("No processes in cgroup {}", dir.display()))
+    }
+}
+
+fn find_dir(dir: &Path, name: &str, depth: usize) -> Option<PathBuf> {
+    let entries = fs::read_dir(dir).ok()?;
+    let subdirs: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .map(|e| e.path())
+        .collect();
+
+    if let Some(found) = subdirs.iter().find(|d| d.file_name().is_some_and(|n| n == name)) {
+        return Some(found.clone());
+    }
+    if depth == 0 {
+        return None;
+    }
+    subdirs.iter().find_map(|d| find_dir(d, name, depth - 1))
+}
+
+/// Scope name prefixes used by the systemd cgroup driver of each runtime.
+const SCOPE_PREFIXES: &[&str] = &["docker-", "libpod-", "cri-containerd-", "crio-"];
+
+/// Conmon monitor scopes sit next to the container's scope and must not be
+/// mistaken for it.
+const MONITOR_PREFIXES: &[&str] = &["libpod-conmon-", "crio-conmon-"];
+
+/// Resolves container IDs to PIDs by scanning `/proc/*/cgroup`, without
+/// talking to any container runtime.
+#[derive(Clone)]
+pub struct CgroupResolver {
+    proc_root: PathBuf,
+}
+
+/// A container found in the process table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupContainer {
+    pub id: String,
+    pub pid: u32,
+}
+
+struct ProcessEntry {
+    pid: u32,
+    ppid: u32,
+    start_time: u64,
+    ns_init: bool,
+}
+
+impl Default for CgroupResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CgroupResolver {
+    pub fn new() -> Self {
+        Self::with_proc_root("/proc")
+    }
+
+    /// Use an alternative procfs mount (e.g. a host `/proc` bind-mounted
+    /// into a debugging container).
+    pub fn with_proc_root(proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            proc_root: proc_root.into(),
+        }
+    }
+
+    /// Find the container whose ID equals or starts with `target` and
+    /// return its init process.
+    pub fn resolve(&self, target: &str) -> Result<CgroupContainer> {
+        if target.is_empty() || !target.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("Not a container ID: {}", target));
+        }
+        let target = target.to_ascii_lowercase();
+
+        let containers = self.scan()?;
+        let matches: Vec<&String> = containers
+            .keys()
+            .filter(|id| id.starts_with(&target))
+            .collect();
+
+        let id = match matches.as_slice() {
+            [id] => (*id).clone(),
+            [] => return Err(anyhow!("No process found in a cgroup for container {}", target)),
+            _ => {
+                return Err(anyhow!(
+                    "Container ID prefix {} is ambiguous ({} matches)",
+                    target,
+                    matches.len()
+                ))
+            }
+        };
+
+        let pid = pick_init_process(&containers[&id])
+            .ok_or_else(|| anyhow!("Could not determine init process of container {}", id))?;
+
+        debug!("Resolved container {} to PID {} from cgroups", id, pid);
+        Ok(CgroupContainer { id, pid })
+    }
+
+    /// All containers visible in the process table, each with its init PID.
+    pub fn list(&self) -> Result<Vec<CgroupContainer>> {
+        Ok(self
+            .scan()?
+            .into_iter()
+            .filter_map(|(id, processes)| {
+                pick_init_process(&processes).map(|pid| CgroupContainer { id, pid })
+            })
+            .collect())
+    }
+
+    /// Group every process by the container ID found in its cgroup paths.
+    fn scan(&self) -> Result<BTreeMap<String, Vec<ProcessEntry>>> {
+        let mut containers: BTreeMap<String, Vec<ProcessEntry>> = BTreeMap::new();
+
+        let entries = fs::read_dir(&self.proc_root)
+            .with_context(|| format!("Failed to read {}", self.proc_root.display()))?;
+
+        for entry in entries.flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+                continue;
+            };
+
+            // Processes can exit while we scan; skip anything unreadable
+            let Ok(cgroups) = fs::read_to_string(entry.path().join("cgroup")) else {
+                continue;
+            };
+            let Some(id) = cgroups
+                .lines()
+                .filter_map(|line| line.splitn(3, ':').nth(2))
+                .find_map(container_id_from_cgroup_path)
+            else {
+                continue;
+            };
+
+            if let Some(process) = self.read_process(pid) {
+                containers.entry(id).or_default().push(process);
+            }
+        }
+
+        Ok(containers)
+    }
+
+    /// Init process among `pids`, e.g. the contents of a `cgroup.procs`
+    /// file.
+    pub fn init_process_of(&self, pids: &[u32]) -> Option<u32> {
+        let processes: Vec<ProcessEntry> = pids
+            .iter()
+            .filter_map(|pid| self.read_process(*pid))
+            .collect();
+        pick_init_process(&processes)
+    }
+
+    fn read_process(&self, pid: u32) -> Option<ProcessEntry> {
+        let dir = self.proc_root.join(pid.to_string());
+
+        // Fields after the parenthesised comm: state, ppid, ..., starttime (22nd)
+        let stat = fs::read_to_string(dir.join("stat")).ok()?;
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+        let ppid = fields.get(1)?.parse().ok()?;
+        let start_time = fields.get(19)?.parse().ok()?;
+
+        // PID 1 of its own PID namespace is the container's init
+        let status = fs::read_to_string(dir.join("status")).unwrap_or_default();
+        let ns_init = status
+            .lines()
+            .find_map(|line| line.strip_prefix("NSpid:"))
+            .map(|ids| {
+                let ids: Vec<&str> = ids.split_whitespace().collect();
+                ids.len() > 1 && ids.last() == Some(&"1")
+            })
+            .unwrap_or(false);
+
+        Some(ProcessEntry {
+            pid,
+            ppid,
+            start_time,
+            ns_init,
+        })
+    }
+}
+
+/// Extract a 64-character container ID from a cgroup v1 or v2 path.
+///
+/// Recognises systemd scopes (`docker-<id>.scope`, `libpod-<id>.scope`,
+/// `cri-containerd-<id>.scope`, `crio-<id>.scope`) and cgroupfs layouts
+/// (`/docker/<id>`, `/kubepods/.../pod<uid>/<id>`). The deepest match wins.
+pub fn container_id_from_cgroup_path(path: &str) -> Option<String> {
+    path.split('/').rev().find_map(|segment| {
+        if MONITOR_PREFIXES.iter().any(|p| segment.starts_with(p)) {
+            return None;
+        }
+
+        let candidate = match segment.strip_suffix(".scope") {
+            Some(scope) => SCOPE_PREFIXES.iter().find_map(|p| scope.strip_prefix(p))?,
+            None => segment,
+        };
+
+        is_container_id(candidate).then(|| candidate.to_ascii_lowercase())
+    })
+}
+
+fn is_container_id(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Pick the container's init process: the PID namespace init if the
+/// container has its own PID namespace, otherwise the oldest process whose
+/// parent lives outside the container (i.e. was started by the runtime).
+fn pick_init_process(processes: &[ProcessEntry]) -> Option<u32> {
+    if let Some(init) = processes.iter().find(|p| p.ns_init) {
+        return Some(init.pid);
+    }
+
+    let pids: HashSet<u32> = processes.iter().map(|p| p.pid).collect();
+    processes
+        .iter()
+        .filter(|p| !pids.contains(&p.ppid))
+        .min_by_key(|p| (p.start_time, p.pid))
+        .map(|p| p.pid)
+}
+
+/// Runtime-independent backend that finds containers in the process table.
+/// Registered last, it works even when every runtime daemon is wedged.
+pub struct CgroupBackend;
+
+impl CgroupBackend {
+    /// Resolve `target` off the async runtime, as it reads all of `/proc`.
+    async fn resolve(&self, target: &str) -> Result<CgroupContainer> {
+        let target = target.to_string();
+        tokio::task::spawn_blocking(move || CgroupResolver::new().resolve(&target)).await?
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for CgroupBackend {
+    fn name(&self) -> &str {
+        "cgroup"
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let container = self.resolve(target).await?;
+        info!("Detected container {} from cgroups", container.id);
+        Ok(container.id)
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        Ok(self.resolve(id).await?.pid)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        let container = self.resolve(id).await?;
+        Ok(json!({ "id": container.id, "pid": container.pid }))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        Ok(tokio::task::spawn_blocking(|| CgroupResolver::new().list())
+            .await??
+            .into_iter()
+            .map(|c| ContainerSummary {
+                runtime: self.name().to_string(),
+                name: c.id[..12].to_string(),
+                id: c.id,
+                image: String::new(),
+                state: "running".to_string(),
+                pid: Some(c.pid),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Config file read when `CRASHCART_CONFIG` is not set.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/crashcart/config.json";
+
+/// Host-wide crashcart settings, read from a JSON file. Every field is
+/// optional; a missing file means all defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub probe: ProbeConfig,
+    pub cache: CacheConfig,
+    pub recording: RecordingConfig,
+    pub sessions: SessionsConfig,
+}
+
+/// How long runtime backends may take to answer during target detection.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProbeConfig {
+    /// Timeout for each backend, in milliseconds.
+    pub timeout_ms: u64,
+    /// Per-backend overrides by backend name (`{"cri": 5000}`).
+    pub backend_timeouts_ms: BTreeMap<String, u64>,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 3000,
+            backend_timeouts_ms: BTreeMap::new(),
+        }
+    }
+}
+
+impl ProbeConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn backend_timeouts(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.backend_timeouts_ms
+            .iter()
+            .map(|(name, ms)| (name.as_str(), Duration::from_millis(*ms)))
+    }
+}
+
+/// The target resolution cache (see [`crate::cache::ResolutionCache`]).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: PathBuf::from(crate::cache::DEFAULT_CACHE_DIR),
+        }
+    }
+}
+
+/// Session recording enforced for every interactive session.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    /// Record every session, even without `--record`.
+    pub always: bool,
+    /// Where enforced recordings are written.
+    pub dir: PathBuf,
+    /// Record keystrokes as well as output. Off by default, as they
+    /// include any password typed.
+    pub input: bool,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            always: false,
+            dir: PathBuf::from(crate::recording::DEFAULT_RECORDING_DIR),
+            input: false,
+        }
+    }
+}
+
+/// Where detached sessions keep their state (see [`crate::SessionStore`]).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionsConfig {
+    pub dir: PathBuf,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from(crate::session::DEFAULT_SESSIONS_DIR),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from `$CRASHCART_CONFIG` or the default path. An
+    /// override can't lift recording the default config enforces.
+    pub fn load() -> Result<Self> {
+        match std::env::var_os("CRASHCART_CONFIG") {
+            Some(path) => Self::load_override(Path::new(&path), Path::new(DEFAULT_CONFIG_PATH)),
+            None => Ok(Self::read(Path::new(DEFAULT_CONFIG_PATH))?.unwrap_or_default()),
+        }
+    }
+
+    /// Load `path` in place of the host config at `host`, keeping the
+    /// host's recording settings when it enforces recording. Unlike the
+    /// host config, `path` must exist.
+    pub fn load_override(path: &Path, host: &Path) -> Result<Self> {
+        let mut config = Self::read(path)?
+            .ok_or_else(|| anyhow!("Crashcart config {} does not exist", path.display()))?;
+        if let Some(host) = Self::read(host)? {
+            if host.recording.always {
+                config.recording = host.recording;
+            }
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .with_context(|| format!("Invalid crashcart config {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+}
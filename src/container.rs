@@ -1,150 +1,277 @@
 use anyhow::{anyhow, Context, Result};
 use serde_json::Value;
-use std::process::Stdio;
-use tokio::process::Command;
-use tracing::{debug, info};
+use std::fmt;
+use std::sync::Arc;
+use tracing::debug;
 
-#[derive(Debug, Clone)]
+use crate::backend::{BackendRegistry, RuntimeBackend};
+use crate::cache::{CachedBackend, ResolutionCache};
+use crate::info::ContainerInfo;
+use crate::plugin::{PluginTarget, ResolverPlugin};
+use crate::selector::Selector;
+
+/// A resolved debugging target: either a container known to one of the
+/// registered runtime backends, or a bare host PID.
+#[derive(Clone)]
 pub enum ContainerRuntime {
-    Docker { id: String },
-    Containerd { id: String },
-    Podman { id: String },
+    Container {
+        backend: Arc<dyn RuntimeBackend>,
+        id: String,
+    },
     Pid { pid: u32 },
 }
 
+impl fmt::Debug for ContainerRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerRuntime::Container { backend, id } => f
+                .debug_struct("Container")
+                .field("backend", &backend.name())
+                .field("id", id)
+                .finish(),
+            ContainerRuntime::Pid { pid } => f.debug_struct("Pid").field("pid", pid).finish(),
+        }
+    }
+}
+
+/// Formats as a target string that resolves back to the same container
+/// (`docker://4f1b...`, `pid://1234`).
+impl fmt::Display for ContainerRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerRuntime::Container { backend, id } => write!(f, "{}://{}", backend.name(), id),
+            ContainerRuntime::Pid { pid } => write!(f, "pid://{}", pid),
+        }
+    }
+}
+
 impl ContainerRuntime {
+    /// Resolve `target` against the built-in runtime backends.
     pub async fn detect(target: &str) -> Result<Self> {
+        Self::detect_with(&BackendRegistry::default(), target).await
+    }
+
+    /// Resolve `target` against the backends in `registry`.
+    ///
+    /// A `<scheme>://<reference>` target goes straight to the backend of
+    /// that name (`docker://web`, `cri://prod/api/app`, `pid://1234`), then
+    /// to a resolver plugin for the scheme. `compose://project/service`
+    /// selects by compose labels. Bare targets are probed against every
+    /// backend in registration order.
+    pub async fn detect_with(registry: &BackendRegistry, target: &str) -> Result<Self> {
+        if let Some((scheme, reference)) = target.split_once("://") {
+            return Self::detect_scheme(registry, scheme, reference, target).await;
+        }
+
         // First try to parse as PID
         if let Ok(pid) = target.parse::<u32>() {
             return Ok(ContainerRuntime::Pid { pid });
         }
 
-        // Try Docker first
-        if let Ok(runtime) = Self::try_docker(target).await {
-            return Ok(runtime);
-        }
+        // Probe every backend at once so a hung daemon only costs its
+        // timeout, but take the answer of the earliest registered backend
+        let owned = target.to_string();
+        let mut probes = registry
+            .probe_all(|backend| {
+                let target = owned.clone();
+                async move {
+                    if !backend.claims(&target) {
+                        return Err(anyhow!("Not probing {} backend for {}", backend.name(), target));
+                    }
+                    backend.detect(&target).await
+                }
+            })
+            .into_iter();
 
-        // Try Podman
-        if let Ok(runtime) = Self::try_podman(target).await {
-            return Ok(runtime);
+        let mut found = None;
+        for (backend, handle) in probes.by_ref() {
+            match handle.await {
+                Ok(Ok(id)) => {
+                    found = Some(ContainerRuntime::Container { backend, id });
+                    break;
+                }
+                Ok(Err(e)) => debug!("{} backend did not find {}: {:#}", backend.name(), target, e),
+                Err(e) => debug!("{} backend probe failed: {}", backend.name(), e),
+            }
+        }
+        for (_, handle) in probes {
+            handle.abort();
         }
 
-        // Try containerd
-        if let Ok(runtime) = Self::try_containerd(target).await {
+        if let Some(runtime) = found {
             return Ok(runtime);
         }
-
         Err(anyhow!("Could not find container or process with ID: {}", target))
     }
 
-    async fn try_docker(id: &str) -> Result<Self> {
-        let output = Command::new("docker")
-            .args(["inspect", id])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .output()
-            .await?;
-
-        if output.status.success() {
-            info!("Detected Docker container: {}", id);
-            Ok(ContainerRuntime::Docker { id: id.to_string() })
-        } else {
-            Err(anyhow!("Docker container not found"))
+    /// Like [`ContainerRuntime::detect_with`], but reuse the resolution
+    /// cached for `target` while its process is still running, and cache
+    /// fresh container resolutions for next time.
+    pub async fn detect_cached(registry: &BackendRegistry, cache: &ResolutionCache, target: &str) -> Result<Self> {
+        if let Some(entry) = cache.lookup(target) {
+            if let Some(backend) = registry.get(&entry.runtime) {
+                debug!("Using cached resolution of {}: {}://{}", target, entry.runtime, entry.id);
+                return Ok(ContainerRuntime::Container {
+                    backend: Arc::new(CachedBackend::new(backend, &entry.id, entry.pid)),
+                    id: entry.id,
+                });
+            }
         }
-    }
 
-    async fn try_podman(id: &str) -> Result<Self> {
-        let output = Command::new("podman")
-            .args(["inspect", id])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .output()
-            .await?;
-
-        if output.status.success() {
-            info!("Detected Podman container: {}", id);
-            Ok(ContainerRuntime::Podman { id: id.to_string() })
-        } else {
-            Err(anyhow!("Podman container not found"))
+        let runtime = Self::detect_with(registry, target).await?;
+        let ContainerRuntime::Container { backend, id } = &runtime else {
+            return Ok(runtime);
+        };
+
+        // Plugin-resolved targets only borrow a registered backend's name,
+        // so a cached entry would send them to that backend instead
+        let registered = registry.get(backend.name());
+        if !registered.is_some_and(|registered| Arc::ptr_eq(&registered, backend)) {
+            return Ok(runtime);
         }
-    }
 
-    async fn try_containerd(id: &str) -> Result<Self> {
-        let output = Command::new("ctr")
-            .args(["container", "info", id])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .output()
-            .await?;
-
-        if output.status.success() {
-            info!("Detected containerd container: {}", id);
-            Ok(ContainerRuntime::Containerd { id: id.to_string() })
-        } else {
-            Err(anyhow!("Containerd container not found"))
+        let pid = backend.resolve_pid(id).await?;
+        if let Err(e) = cache.store(target, backend.name(), id, pid) {
+            debug!("Not caching resolution of {}: {:#}", target, e);
         }
+        Ok(ContainerRuntime::Container {
+            backend: Arc::new(CachedBackend::new(backend.clone(), id, pid)),
+            id: id.clone(),
+        })
     }
 
-    pub async fn get_pid(&self) -> Result<u32> {
-        match self {
-            ContainerRuntime::Pid { pid } => Ok(*pid),
-            ContainerRuntime::Docker { id } => self.get_docker_pid(id).await,
-            ContainerRuntime::Podman { id } => self.get_podman_pid(id).await,
-            ContainerRuntime::Containerd { id } => self.get_containerd_pid(id).await,
+    async fn detect_scheme(
+        registry: &BackendRegistry,
+        scheme: &str,
+        reference: &str,
+        target: &str,
+    ) -> Result<Self> {
+        if scheme == "pid" {
+            let pid = reference
+                .parse::<u32>()
+                .with_context(|| format!("Invalid PID in target {}", target))?;
+            return Ok(ContainerRuntime::Pid { pid });
         }
-    }
 
-    async fn get_docker_pid(&self, id: &str) -> Result<u32> {
-        let output = Command::new("docker")
-            .args(["inspect", "--format", "{{.State.Pid}}", id])
-            .output()
-            .await
-            .context("Failed to get Docker container PID")?;
+        if scheme == "compose" {
+            return Self::select_with(registry, &Selector::parse_compose(reference)?).await;
+        }
 
-        let pid_str = String::from_utf8(output.stdout)?;
-        let pid = pid_str.trim().parse::<u32>()
-            .context("Failed to parse Docker PID")?;
+        if let Some(backend) = registry.get(scheme) {
+            let id = registry
+                .probe(backend.clone(), |backend| async move { backend.detect(reference).await })
+                .await
+                .with_context(|| format!("{} backend could not resolve {}", scheme, reference))?;
+            return Ok(ContainerRuntime::Container { backend, id });
+        }
 
-        debug!("Docker container {} has PID {}", id, pid);
-        Ok(pid)
+        // `<scheme>://...` targets without a built-in backend go to a
+        // crashcart-resolver-<scheme> plugin
+        if let Some(plugin) = ResolverPlugin::find(scheme) {
+            let resolved = PluginTarget::resolve(&plugin, target, registry.timeout_for(scheme)).await?;
+            return Ok(ContainerRuntime::Container {
+                backend: Arc::new(resolved),
+                id: target.to_string(),
+            });
+        }
+
+        Err(anyhow!(
+            "Unknown target scheme {}:// (no backend or crashcart-resolver-{} plugin)",
+            scheme,
+            scheme
+        ))
     }
 
-    async fn get_podman_pid(&self, id: &str) -> Result<u32> {
-        let output = Command::new("podman")
-            .args(["inspect", "--format", "{{.State.Pid}}", id])
-            .output()
-            .await
-            .context("Failed to get Podman container PID")?;
+    /// Resolve the one running container whose labels match `selector`.
+    pub async fn select(selector: &Selector) -> Result<Self> {
+        Self::select_with(&BackendRegistry::default(), selector).await
+    }
 
-        let pid_str = String::from_utf8(output.stdout)?;
-        let pid = pid_str.trim().parse::<u32>()
-            .context("Failed to parse Podman PID")?;
+    /// Resolve the one running container, across the backends in
+    /// `registry`, whose labels match `selector`. Matching several
+    /// containers is an error rather than a guess.
+    pub async fn select_with(registry: &BackendRegistry, selector: &Selector) -> Result<Self> {
+        let mut matches = Self::select_all_with(registry, selector).await;
 
-        debug!("Podman container {} has PID {}", id, pid);
-        Ok(pid)
+        match matches.len() {
+            0 => Err(anyhow!("No running container matches selector {}", selector)),
+            1 => {
+                let (runtime, info) = matches.remove(0);
+                debug!("Selector {} matched {}://{}", selector, info.runtime, info.id);
+                Ok(runtime)
+            }
+            n => {
+                let names: Vec<String> = matches
+                    .iter()
+                    .map(|(runtime, _)| runtime.to_string())
+                    .collect();
+                Err(anyhow!(
+                    "Selector {} matches {} containers, narrow it down: {}",
+                    selector,
+                    n,
+                    names.join(", ")
+                ))
+            }
+        }
     }
 
-    async fn get_containerd_pid(&self, id: &str) -> Result<u32> {
-        let output = Command::new("ctr")
-            .args(["task", "list", "--format", "json"])
-            .output()
-            .await
-            .context("Failed to list containerd tasks")?;
-
-        let tasks: Vec<Value> = serde_json::from_slice(&output.stdout)?;
-        
-        for task in tasks {
-            if let Some(task_id) = task.get("ID").and_then(|v| v.as_str()) {
-                if task_id.starts_with(id) {
-                    if let Some(pid) = task.get("Pid").and_then(|v| v.as_u64()) {
-                        debug!("Containerd container {} has PID {}", id, pid);
-                        return Ok(pid as u32);
-                    }
+    /// Every running container across the backends in `registry` whose
+    /// labels match `selector`, with the metadata the match was made on.
+    pub async fn select_all_with(
+        registry: &BackendRegistry,
+        selector: &Selector,
+    ) -> Vec<(Self, ContainerInfo)> {
+        let mut matches = Vec::new();
+
+        for summary in registry.list_all().await {
+            if summary.state != "running" {
+                continue;
+            }
+            let Some(backend) = registry.get(&summary.runtime) else {
+                continue;
+            };
+
+            match backend.info(&summary.id).await {
+                Ok(info) if selector.matches(&info.labels) => {
+                    let id = summary.id;
+                    matches.push((ContainerRuntime::Container { backend, id }, info));
                 }
+                Ok(_) => {}
+                Err(e) => debug!("Could not read labels of {}://{}: {:#}", summary.runtime, summary.id, e),
             }
         }
 
-        Err(anyhow!("Could not find PID for containerd container {}", id))
+        matches
+    }
+
+    /// Name of the backend that resolved the target (`pid` for bare PIDs).
+    pub fn runtime_name(&self) -> &str {
+        match self {
+            ContainerRuntime::Container { backend, .. } => backend.name(),
+            ContainerRuntime::Pid { .. } => "pid",
+        }
+    }
+
+    pub async fn get_pid(&self) -> Result<u32> {
+        match self {
+            ContainerRuntime::Pid { pid } => Ok(*pid),
+            ContainerRuntime::Container { backend, id } => backend.resolve_pid(id).await,
+        }
+    }
+
+    pub async fn inspect(&self) -> Result<Value> {
+        match self {
+            ContainerRuntime::Container { backend, id } => backend.inspect(id).await,
+            ContainerRuntime::Pid { .. } => Err(anyhow!("Cannot inspect a raw PID")),
+        }
+    }
+
+    /// Typed metadata for the target; bare PIDs get what `/proc` shows.
+    pub async fn info(&self) -> Result<ContainerInfo> {
+        match self {
+            ContainerRuntime::Container { backend, id } => backend.info(id).await,
+            ContainerRuntime::Pid { pid } => Ok(ContainerInfo::for_pid(*pid)),
+        }
     }
 
     pub async fn exec_command(&self, command: &[String]) -> Result<i32> {
@@ -160,46 +287,10 @@ impl ContainerRuntime {
         };
 
         match self {
-            ContainerRuntime::Docker { id } => {
-                let mut args = vec!["exec".to_string(), "-it".to_string(), id.clone()];
-                args.extend(cmd);
-                
-                let status = Command::new("docker")
-                    .args(&args)
-                    .status()
-                    .await
-                    .context("Failed to execute docker exec")?;
-
-                Ok(status.code().unwrap_or(-1))
-            }
-            ContainerRuntime::Podman { id } => {
-                let mut args = vec!["exec".to_string(), "-it".to_string(), id.clone()];
-                args.extend(cmd);
-                
-                let status = Command::new("podman")
-                    .args(&args)
-                    .status()
-                    .await
-                    .context("Failed to execute podman exec")?;
-
-                Ok(status.code().unwrap_or(-1))
-            }
-            ContainerRuntime::Containerd { id } => {
-                let mut args = vec!["task".to_string(), "exec".to_string(), "--exec-id".to_string(), 
-                                   format!("crashcart-{}", std::process::id()), id.clone()];
-                args.extend(cmd);
-                
-                let status = Command::new("ctr")
-                    .args(&args)
-                    .status()
-                    .await
-                    .context("Failed to execute ctr task exec")?;
-
-                Ok(status.code().unwrap_or(-1))
-            }
+            ContainerRuntime::Container { backend, id } => backend.exec(id, &cmd).await,
             ContainerRuntime::Pid { .. } => {
                 Err(anyhow!("Cannot use exec mode with raw PID"))
             }
         }
     }
-}
\ No newline at end of file
+}
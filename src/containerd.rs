@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::path::Path;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+use crate::info::{string_map, ContainerInfo};
+
+const DEFAULT_NAMESPACE: &str = "default";
+
+/// containerd backend, driven through the `ctr` CLI.
+///
+/// Container IDs are `<namespace>/<id>`; a bare ID refers to ctr's
+/// `default` namespace (Kubernetes containers live in `k8s.io`).
+pub struct ContainerdBackend;
+
+struct Task {
+    id: String,
+    pid: u32,
+    status: String,
+}
+
+/// Split a `namespace/id` reference into its parts.
+fn split_reference(reference: &str) -> (&str, &str) {
+    reference
+        .split_once('/')
+        .unwrap_or((DEFAULT_NAMESPACE, reference))
+}
+
+impl ContainerdBackend {
+    fn ctr(namespace: &str) -> Command {
+        let mut cmd = Command::new("ctr");
+        cmd.args(["--namespace", namespace]);
+        cmd
+    }
+
+    async fn namespaces(&self) -> Result<Vec<String>> {
+        let output = Command::new("ctr")
+            .args(["namespaces", "list", "--quiet"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to list containerd namespaces")?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|ns| !ns.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Tasks in `namespace`, parsed from the `TASK PID STATUS` table.
+    async fn tasks(&self, namespace: &str) -> Result<Vec<Task>> {
+        let output = Self::ctr(namespace)
+            .args(["task", "list"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to list containerd tasks")?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some(Task {
+                    id: fields.next()?.to_string(),
+                    pid: fields.next()?.parse().ok()?,
+                    status: fields.next().unwrap_or_default().to_string(),
+                })
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for ContainerdBackend {
+    fn name(&self) -> &str {
+        "containerd"
+    }
+
+    async fn is_available(&self) -> bool {
+        which::which("ctr").is_ok()
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let (namespace, id) = split_reference(target);
+        let id = format!("{}/{}", namespace, id);
+
+        self.inspect(&id).await?;
+        info!("Detected containerd container: {}", id);
+        Ok(id)
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        let (namespace, container) = split_reference(id);
+
+        for task in self.tasks(namespace).await? {
+            if task.id.starts_with(container) && task.pid > 0 {
+                debug!("Containerd container {} has PID {}", id, task.pid);
+                return Ok(task.pid);
+            }
+        }
+
+        Err(anyhow!("Could not find PID for containerd container {}", id))
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        let (namespace, container) = split_reference(id);
+        let output = Self::ctr(namespace)
+            .args(["container", "info", container])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run ctr container info")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Containerd container not found"));
+        }
+
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        let (namespace, container) = split_reference(id);
+        let inspect = self.inspect(id).await?;
+        let task = self
+            .tasks(namespace)
+            .await?
+            .into_iter()
+            .find(|t| t.id == container);
+
+        let mut info = ContainerInfo {
+            runtime: self.name().to_string(),
+            id: id.to_string(),
+            name: container.to_string(),
+            image: inspect["Image"].as_str().unwrap_or_default().to_string(),
+            labels: string_map(inspect.get("Labels")),
+            state: task
+                .as_ref()
+                .map(|t| t.status.to_lowercase())
+                .unwrap_or_else(|| "created".to_string()),
+            ..Default::default()
+        };
+        info.apply_oci_spec(&inspect["Spec"]);
+        if let Some(task) = task.filter(|t| t.pid > 0) {
+            info.complete_from_proc(Path::new("/proc"), task.pid);
+        }
+        Ok(info)
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let (namespace, container) = split_reference(id);
+        let status = Self::ctr(namespace)
+            .args(["task", "exec", "--exec-id"])
+            .arg(format!("crashcart-{}", std::process::id()))
+            .arg(container)
+            .args(command)
+            .status()
+            .await
+            .context("Failed to execute ctr task exec")?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        let mut summaries = Vec::new();
+
+        for namespace in self.namespaces().await? {
+            for task in self.tasks(&namespace).await? {
+                summaries.push(ContainerSummary {
+                    runtime: self.name().to_string(),
+                    id: format!("{}/{}", namespace, task.id),
+                    name: task.id,
+                    image: String::new(),
+                    state: task.status.to_lowercase(),
+                    pid: Some(task.pid),
+                });
+            }
+        }
+
+        Ok(summaries)
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::info::IdMap;
+use crate::namespace::UserNamespace;
+
+/// A user given on the command line: `uid[:gid]` or `name[:group]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserSpec {
+    pub user: String,
+    pub group: Option<String>,
+}
+
+impl FromStr for UserSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        if user.is_empty() || group == Some("") {
+            return Err(anyhow!("Invalid user {:?} (expected uid[:gid] or name[:group])", s));
+        }
+        Ok(Self {
+            user: user.to_string(),
+            group: group.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for UserSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.group {
+            Some(group) => write!(f, "{}:{}", self.user, group),
+            None => f.write_str(&self.user),
+        }
+    }
+}
+
+/// Capability sets as the bitmasks shown in `/proc/<pid>/status`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilitySets {
+    pub inheritable: u64,
+    pub permitted: u64,
+    pub effective: u64,
+    pub bounding: u64,
+    pub ambient: u64,
+}
+
+/// Real, effective and saved IDs, in the order `/proc/<pid>/status` lists
+/// them. They differ in a setuid or setgid program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ids {
+    pub real: u32,
+    pub effective: u32,
+    pub saved: u32,
+}
+
+impl Ids {
+    /// The same ID for all three.
+    pub fn all(id: u32) -> Self {
+        Self {
+            real: id,
+            effective: id,
+            saved: id,
+        }
+    }
+
+    /// Each ID passed through `f`.
+    pub fn try_map(self, mut f: impl FnMut(u32) -> Result<u32>) -> Result<Self> {
+        Ok(Self {
+            real: f(self.real)?,
+            effective: f(self.effective)?,
+            saved: f(self.saved)?,
+        })
+    }
+}
+
+impl fmt::Display for Ids {
+    /// The effective ID, followed by the real and saved ones if they
+    /// differ (`101 (real 0, saved 101)`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.effective)?;
+        if self.real != self.effective || self.saved != self.effective {
+            write!(f, " (real {}, saved {})", self.real, self.saved)?;
+        }
+        Ok(())
+    }
+}
+
+/// Credentials for the command, with IDs as seen inside the target's user
+/// namespace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub uid: Ids,
+    pub gid: Ids,
+    pub groups: Vec<u32>,
+    /// Exact capability sets to install. Without them, switching to a
+    /// non-root user drops every capability as usual.
+    pub capabilities: Option<CapabilitySets>,
+}
+
+impl Credentials {
+    /// The credentials of process `pid`: real, effective and saved IDs,
+    /// supplementary groups and capability sets.
+    pub fn of_process(pid: u32) -> Result<Self> {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid))
+            .with_context(|| format!("Failed to read credentials of PID {}", pid))?;
+        let credentials = Self::from_status(&status)?;
+
+        // `status` shows IDs as the host sees them
+        let user = UserNamespace::read(pid)?;
+        if user.is_initial() {
+            return Ok(credentials);
+        }
+        let uid = |id| {
+            IdMap::from_host(&user.uid_map, id)
+                .ok_or_else(|| anyhow!("Target UID {} is not mapped in its user namespace", id))
+        };
+        let gid = |id| {
+            IdMap::from_host(&user.gid_map, id)
+                .ok_or_else(|| anyhow!("Target GID {} is not mapped in its user namespace", id))
+        };
+        let (groups, unmapped): (Vec<_>, Vec<_>) = credentials.groups.iter().partition(|&&g| gid(g).is_ok());
+        if !unmapped.is_empty() {
+            // Such groups can't be set from inside the namespace
+            warn!("Leaving out supplementary groups {:?}, not mapped in the target's user namespace", unmapped);
+        }
+        Ok(Self {
+            uid: credentials.uid.try_map(uid)?,
+            gid: credentials.gid.try_map(gid)?,
+            groups: groups.into_iter().map(gid).collect::<Result<_>>()?,
+            capabilities: credentials.capabilities,
+        })
+    }
+
+    /// Parse the credentials out of a `/proc/<pid>/status` file.
+    pub fn from_status(status: &str) -> Result<Self> {
+        let field = |key: &str| {
+            status
+                .lines()
+                .find_map(|l| l.strip_prefix(key))
+                .map(str::trim)
+                .ok_or_else(|| anyhow!("No {} line in process status", key))
+        };
+        let ids = |key: &str| -> Result<Ids> {
+            let ids: Vec<u32> = field(key)?
+                .split_whitespace()
+                .map(|id| id.parse())
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("Invalid {} line in process status", key))?;
+            match ids[..] {
+                // The fourth is the filesystem ID, which follows the effective one
+                [real, effective, saved, ..] => Ok(Ids { real, effective, saved }),
+                _ => Err(anyhow!("Invalid {} line in process status", key)),
+            }
+        };
+        let mask = |key: &str| -> Result<u64> {
+            u64::from_str_radix(field(key)?, 16).with_context(|| format!("Invalid {} line in process status", key))
+        };
+
+        Ok(Self {
+            uid: ids("Uid:")?,
+            gid: ids("Gid:")?,
+            groups: field("Groups:")?.split_whitespace().filter_map(|g| g.parse().ok()).collect(),
+            capabilities: Some(CapabilitySets {
+                inheritable: mask("CapInh:")?,
+                permitted: mask("CapPrm:")?,
+                effective: mask("CapEff:")?,
+                bounding: mask("CapBnd:")?,
+                // Kernels before 4.3 have no ambient set
+                ambient: mask("CapAmb:").unwrap_or(0),
+            }),
+        })
+    }
+
+    /// Resolve `user` against the `/etc/passwd` and `/etc/group` under
+    /// `root`, the target's root directory. Numeric IDs need no entry; like
+    /// `docker exec --user`, an unknown UID gets GID 0.
+    pub fn for_user(root: &Path, user: &UserSpec) -> Result<Self> {
+        let passwd = fs::read_to_string(root.join("etc/passwd")).unwrap_or_default();
+        let group_file = fs::read_to_string(root.join("etc/group")).unwrap_or_default();
+        let passwd: Vec<Vec<&str>> = passwd.lines().map(|l| l.split(':').collect()).filter(|f: &Vec<&str>| f.len() >= 4).collect();
+        let groups: Vec<Vec<&str>> = group_file.lines().map(|l| l.split(':').collect()).filter(|f: &Vec<&str>| f.len() >= 3).collect();
+
+        let entry = match user.user.parse::<u32>() {
+            Ok(uid) => passwd.iter().find(|f| f[2].parse() == Ok(uid)),
+            Err(_) => Some(
+                passwd
+                    .iter()
+                    .find(|f| f[0] == user.user)
+                    .ok_or_else(|| anyhow!("No user {:?} in the target's /etc/passwd", user.user))?,
+            ),
+        };
+        let uid = match entry {
+            Some(fields) => fields[2].parse().with_context(|| format!("Invalid passwd entry for {}", fields[0]))?,
+            None => user.user.parse()?,
+        };
+
+        let gid = match &user.group {
+            Some(group) => match group.parse::<u32>() {
+                Ok(gid) => gid,
+                Err(_) => groups
+                    .iter()
+                    .find(|f| f[0] == group)
+                    .and_then(|f| f[2].parse().ok())
+                    .ok_or_else(|| anyhow!("No group {:?} in the target's /etc/group", group))?,
+            },
+            None => entry.and_then(|f| f[3].parse().ok()).unwrap_or(0),
+        };
+
+        // Supplementary groups listing the user by name, as initgroups does
+        let supplementary = match (entry, &user.group) {
+            (Some(fields), None) => groups
+                .iter()
+                .filter(|f| f.len() >= 4 && f[3].split(',').any(|member| member == fields[0]))
+                .filter_map(|f| f[2].parse().ok())
+                .filter(|&g| g != gid)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            uid: Ids::all(uid),
+            gid: Ids::all(gid),
+            groups: supplementary,
+            capabilities: None,
+        })
+    }
+
+    /// The same credentials with IDs from `user` translated to the IDs
+    /// that namespace's parent sees, for when it is not joined.
+    pub fn to_parent_namespace(&self, user: &UserNamespace) -> Result<Self> {
+        let uid = |id| IdMap::to_host(&user.uid_map, id).ok_or_else(|| anyhow!("UID {} is not mapped", id));
+        let gid = |id| IdMap::to_host(&user.gid_map, id).ok_or_else(|| anyhow!("GID {} is not mapped", id));
+        Ok(Self {
+            uid: self.uid.try_map(uid)?,
+            gid: self.gid.try_map(gid)?,
+            groups: self.groups.iter().map(|&g| gid(g)).collect::<Result<_>>()?,
+            capabilities: self.capabilities,
+        })
+    }
+
+    /// Switch the calling process to these credentials. Call it in the
+    /// forked command just before exec, while it still has every
+    /// capability.
+    pub(crate) fn apply(&self) -> Result<()> {
+        let os_error = |what: &str| Err(std::io::Error::last_os_error()).context(what.to_string());
+
+        let groups: Vec<libc::gid_t> = self.groups.clone();
+        if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } != 0 {
+            return os_error("setgroups failed");
+        }
+
+        if let Some(caps) = self.capabilities {
+            // Shrinking the bounding set needs CAP_SETPCAP, so do it before
+            // the UID switch takes effect
+            drop_bounding_set(!caps.bounding)?;
+            if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } != 0 {
+                return os_error("Failed to keep capabilities");
+            }
+        }
+
+        if unsafe { libc::setresgid(self.gid.real, self.gid.effective, self.gid.saved) } != 0 {
+            return os_error("setresgid failed");
+        }
+        if unsafe { libc::setresuid(self.uid.real, self.uid.effective, self.uid.saved) } != 0 {
+            return os_error("setresuid failed");
+        }
+
+        if let Some(caps) = self.capabilities {
+            set_capabilities(caps.effective, caps.permitted, caps.inheritable)?;
+            raise_ambient(caps.ambient)?;
+            unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) };
+        }
+        Ok(())
+    }
+}
+
+/// Highest capability number the running kernel knows.
+pub(crate) fn last_capability() -> u32 {
+    fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|c| c.trim().parse().ok())
+        .unwrap_or(40)
+}
+
+/// Remove the capabilities in `mask` from the bounding set. Ones already
+/// gone are skipped, so nothing needs CAP_SETPCAP unless the set shrinks.
+pub(crate) fn drop_bounding_set(mask: u64) -> Result<()> {
+    let bounded = |cap: &u32| unsafe { libc::prctl(libc::PR_CAPBSET_READ, *cap, 0, 0, 0) } == 1;
+    for cap in (0..=last_capability()).filter(|cap| mask & (1 << cap) != 0).filter(bounded) {
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to drop capability {} from the bounding set", cap));
+        }
+    }
+    Ok(())
+}
+
+/// Add the capabilities in `mask` to the ambient set. They must already be
+/// permitted and inheritable.
+pub(crate) fn raise_ambient(mask: u64) -> Result<()> {
+    for cap in (0..=last_capability()).filter(|cap| mask & (1 << cap) != 0) {
+        if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, cap, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to raise ambient capability {}", cap));
+        }
+    }
+    Ok(())
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+fn cap_header() -> CapHeader {
+    CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    }
+}
+
+/// The calling thread's effective, permitted and inheritable sets.
+pub(crate) fn get_capabilities() -> Result<(u64, u64, u64)> {
+    let mut header = cap_header();
+    let mut data = [CapData::default(); 2];
+    if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("capget failed");
+    }
+
+    let join = |low: u32, high: u32| (high as u64) << 32 | low as u64;
+    Ok((
+        join(data[0].effective, data[1].effective),
+        join(data[0].permitted, data[1].permitted),
+        join(data[0].inheritable, data[1].inheritable),
+    ))
+}
+
+/// Replace the effective, permitted and inheritable sets with `capset`.
+pub(crate) fn set_capabilities(effective: u64, permitted: u64, inheritable: u64) -> Result<()> {
+    let mut header = cap_header();
+    let half = |mask: u64, high: bool| if high { (mask >> 32) as u32 } else { mask as u32 };
+    let data = [false, true].map(|high| CapData {
+        effective: half(effective, high),
+        permitted: half(permitted, high),
+        inheritable: half(inheritable, high),
+    });
+
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("capset failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,625 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use prost::Message;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::net::UnixStream;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+use crate::info::ContainerInfo;
+use crate::terminal;
+use api::*;
+
+/// Well-known CRI runtime sockets, tried in order when
+/// `CONTAINER_RUNTIME_ENDPOINT` is not set.
+const DEFAULT_CRI_ENDPOINTS: &[&str] = &[
+    "/run/containerd/containerd.sock",
+    "/run/crio/crio.sock",
+    "/var/run/cri-dockerd.sock",
+];
+
+const RUNTIME_SERVICE: &str = "/runtime.v1.RuntimeService";
+
+/// Client for the Kubernetes CRI runtime service (containerd's CRI plugin,
+/// CRI-O, cri-dockerd) speaking gRPC over the runtime's unix socket.
+#[derive(Debug, Clone)]
+pub struct CriClient {
+    endpoint: PathBuf,
+}
+
+impl CriClient {
+    pub fn new(endpoint: impl Into<PathBuf>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+        }
+    }
+
+    /// Candidate clients for this node: `CONTAINER_RUNTIME_ENDPOINT` if set,
+    /// otherwise every well-known runtime socket that exists.
+    pub fn discover() -> Vec<Self> {
+        if let Ok(endpoint) = std::env::var("CONTAINER_RUNTIME_ENDPOINT") {
+            let path = endpoint.strip_prefix("unix://").unwrap_or(&endpoint);
+            return vec![Self::new(path)];
+        }
+
+        DEFAULT_CRI_ENDPOINTS
+            .iter()
+            .filter(|path| Path::new(path).exists())
+            .map(|path| Self::new(*path))
+            .collect()
+    }
+
+    pub fn endpoint(&self) -> &Path {
+        &self.endpoint
+    }
+
+    /// Resolve a `namespace/pod/container` triple or a container ID prefix
+    /// to a full container ID.
+    pub async fn resolve(&self, target: &str) -> Result<String> {
+        match target.split('/').collect::<Vec<_>>().as_slice() {
+            [namespace, pod, container] => self.find_pod_container(namespace, pod, container).await,
+            [prefix] => self.find_container_by_prefix(prefix).await,
+            _ => Err(anyhow!(
+                "CRI target must be namespace/pod/container or a container ID: {}",
+                target
+            )),
+        }
+    }
+
+    async fn find_pod_container(&self, namespace: &str, pod: &str, container: &str) -> Result<String> {
+        let sandboxes: ListPodSandboxResponse = self
+            .call("ListPodSandbox", &ListPodSandboxRequest::default())
+            .await?;
+
+        let mut candidates: Vec<&PodSandbox> = sandboxes
+            .items
+            .iter()
+            .filter(|s| {
+                s.metadata
+                    .as_ref()
+                    .is_some_and(|m| m.namespace == namespace && m.name == pod)
+            })
+            .collect();
+        // Prefer the newest sandbox when a pod has been recreated
+        candidates.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+
+        for sandbox in candidates {
+            let status: PodSandboxStatusResponse = self
+                .call(
+                    "PodSandboxStatus",
+                    &PodSandboxStatusRequest {
+                        pod_sandbox_id: sandbox.id.clone(),
+                        verbose: false,
+                    },
+                )
+                .await?;
+            if status.status.map(|s| s.state) != Some(SANDBOX_READY) {
+                debug!("Skipping pod sandbox {} that is not ready", sandbox.id);
+                continue;
+            }
+
+            let containers: ListContainersResponse = self
+                .call(
+                    "ListContainers",
+                    &ListContainersRequest {
+                        filter: Some(ContainerFilter {
+                            pod_sandbox_id: sandbox.id.clone(),
+                            ..Default::default()
+                        }),
+                    },
+                )
+                .await?;
+
+            if let Some(found) = containers
+                .containers
+                .iter()
+                .filter(|c| c.metadata.as_ref().is_some_and(|m| m.name == container))
+                .find(|c| c.state == CONTAINER_RUNNING)
+            {
+                return Ok(found.id.clone());
+            }
+        }
+
+        Err(anyhow!(
+            "No running container {} in pod {}/{}",
+            container,
+            namespace,
+            pod
+        ))
+    }
+
+    async fn find_container_by_prefix(&self, prefix: &str) -> Result<String> {
+        let containers: ListContainersResponse = self
+            .call("ListContainers", &ListContainersRequest::default())
+            .await?;
+
+        let matches: Vec<&Container> = containers
+            .containers
+            .iter()
+            .filter(|c| c.id.starts_with(prefix))
+            .collect();
+
+        match matches.as_slice() {
+            [container] => Ok(container.id.clone()),
+            [] => Err(anyhow!("No CRI container with ID prefix {}", prefix)),
+            _ => Err(anyhow!(
+                "CRI container ID prefix {} is ambiguous ({} matches)",
+                prefix,
+                matches.len()
+            )),
+        }
+    }
+
+    /// Verbose `ContainerStatus` for a container, including the runtime's
+    /// `info` JSON blob.
+    pub async fn container_status(&self, id: &str) -> Result<ContainerStatusResponse> {
+        self.call(
+            "ContainerStatus",
+            &ContainerStatusRequest {
+                container_id: id.to_string(),
+                verbose: true,
+            },
+        )
+        .await
+    }
+
+    pub async fn list_containers(&self) -> Result<Vec<Container>> {
+        let response: ListContainersResponse = self
+            .call("ListContainers", &ListContainersRequest::default())
+            .await?;
+        Ok(response.containers)
+    }
+
+    pub async fn list_pod_sandboxes(&self) -> Result<Vec<PodSandbox>> {
+        let response: ListPodSandboxResponse = self
+            .call("ListPodSandbox", &ListPodSandboxRequest::default())
+            .await?;
+        Ok(response.items)
+    }
+
+    pub async fn get_pid(&self, id: &str) -> Result<u32> {
+        let status = self.container_status(id).await?;
+
+        if status.status.as_ref().map(|s| s.state) != Some(CONTAINER_RUNNING) {
+            return Err(anyhow!("CRI container {} is not running", id));
+        }
+
+        // containerd and CRI-O both report the init PID in the verbose info
+        let info: Value = status
+            .info
+            .get("info")
+            .map(|raw| serde_json::from_str(raw))
+            .transpose()
+            .context("Failed to parse CRI verbose info")?
+            .ok_or_else(|| anyhow!("CRI runtime returned no verbose info for {}", id))?;
+
+        let pid = info
+            .get("pid")
+            .and_then(|v| v.as_u64())
+            .filter(|pid| *pid > 0)
+            .ok_or_else(|| anyhow!("CRI verbose info has no pid for {}", id))?;
+
+        debug!("CRI container {} has PID {}", id, pid);
+        Ok(pid as u32)
+    }
+
+    /// Issue a unary gRPC call on the runtime service.
+    async fn call<Req, Resp>(&self, method: &str, request: &Req) -> Result<Resp>
+    where
+        Req: Message,
+        Resp: Message + Default,
+    {
+        let stream = UnixStream::connect(&self.endpoint)
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.endpoint.display()))?;
+
+        let (client, connection) = h2::client::handshake(stream)
+            .await
+            .context("HTTP/2 handshake with CRI runtime failed")?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("CRI connection closed: {}", e);
+            }
+        });
+
+        let mut client = client.ready().await?;
+        let http_request = http::Request::builder()
+            .method("POST")
+            .uri(format!("http://localhost{}/{}", RUNTIME_SERVICE, method))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .body(())?;
+
+        let (response, mut send) = client.send_request(http_request, false)?;
+        send.send_data(encode_grpc_frame(request), true)?;
+
+        let (parts, mut body) = response.await?.into_parts();
+        check_grpc_status(&parts.headers, method)?;
+
+        let mut data = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            let _ = body.flow_control().release_capacity(chunk.len());
+            data.extend_from_slice(&chunk);
+        }
+        if let Some(trailers) = body.trailers().await? {
+            check_grpc_status(&trailers, method)?;
+        }
+
+        decode_grpc_frame(data.freeze()).with_context(|| format!("Invalid {} response", method))
+    }
+}
+
+/// CRI backend for Kubernetes nodes. Targets are `namespace/pod/container`
+/// or a container ID prefix.
+pub struct CriBackend;
+
+impl CriBackend {
+    /// The first discovered runtime that knows container `id`.
+    async fn client_for(&self, id: &str) -> Result<CriClient> {
+        for client in CriClient::discover() {
+            if client.container_status(id).await.is_ok() {
+                return Ok(client);
+            }
+        }
+        Err(anyhow!("No CRI runtime knows container {}", id))
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for CriBackend {
+    fn name(&self) -> &str {
+        "cri"
+    }
+
+    async fn is_available(&self) -> bool {
+        !CriClient::discover().is_empty()
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        for client in CriClient::discover() {
+            match client.resolve(target).await {
+                Ok(id) => {
+                    info!("Detected CRI container via {}: {}", client.endpoint().display(), id);
+                    return Ok(id);
+                }
+                Err(e) => debug!("CRI lookup via {} failed: {:#}", client.endpoint().display(), e),
+            }
+        }
+
+        Err(anyhow!("CRI container not found"))
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        self.client_for(id).await?.get_pid(id).await
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        let response = self.client_for(id).await?.container_status(id).await?;
+        let status = response.status.unwrap_or_default();
+        let info: Value = response
+            .info
+            .get("info")
+            .and_then(|raw| serde_json::from_str(raw).ok())
+            .unwrap_or(Value::Null);
+
+        Ok(json!({
+            "id": status.id,
+            "name": status.metadata.map(|m| m.name).unwrap_or_default(),
+            "image": status.image.map(|i| i.image).unwrap_or_default(),
+            "state": status.state,
+            "labels": status.labels,
+            "info": info,
+        }))
+    }
+
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        let response = self.client_for(id).await?.container_status(id).await?;
+        let status = response.status.unwrap_or_default();
+        let verbose: Value = response
+            .info
+            .get("info")
+            .and_then(|raw| serde_json::from_str(raw).ok())
+            .unwrap_or(Value::Null);
+
+        // CRI-O reports `privileged` at the top level, containerd inside the
+        // container config it was created with
+        let privileged = verbose
+            .get("privileged")
+            .or_else(|| verbose.pointer("/config/linux/security_context/privileged"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let mut info = ContainerInfo {
+            runtime: self.name().to_string(),
+            id: status.id,
+            name: status.metadata.map(|m| m.name).unwrap_or_default(),
+            image: status.image.map(|i| i.image).unwrap_or_default(),
+            labels: status.labels.into_iter().collect(),
+            state: container_state_name(status.state).to_string(),
+            started_at: (status.started_at > 0)
+                .then(|| chrono::DateTime::from_timestamp_nanos(status.started_at)),
+            privileged,
+            ..Default::default()
+        };
+        info.apply_oci_spec(&verbose["runtimeSpec"]);
+        if let Some(pid) = verbose.get("pid").and_then(|v| v.as_u64()).filter(|pid| *pid > 0) {
+            info.complete_from_proc(Path::new("/proc"), pid as u32);
+        }
+        Ok(info)
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        // CRI exec hands back a streaming URL; crictl already speaks it
+        let client = self.client_for(id).await?;
+        let status = Command::new("crictl")
+            .arg(format!("--runtime-endpoint=unix://{}", client.endpoint().display()))
+            .args(["exec", terminal::exec_flags(), id])
+            .args(command)
+            .status()
+            .await
+            .context("Failed to execute crictl exec")?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        let mut summaries = Vec::new();
+
+        for client in CriClient::discover() {
+            let pods: HashMap<String, PodSandboxMetadata> = client
+                .list_pod_sandboxes()
+                .await?
+                .into_iter()
+                .filter_map(|s| Some((s.id, s.metadata?)))
+                .collect();
+
+            for container in client.list_containers().await? {
+                if container.state != CONTAINER_RUNNING {
+                    continue;
+                }
+
+                let name = container.metadata.map(|m| m.name).unwrap_or_default();
+                let name = match pods.get(&container.pod_sandbox_id) {
+                    Some(pod) => format!("{}/{}/{}", pod.namespace, pod.name, name),
+                    None => name,
+                };
+
+                summaries.push(ContainerSummary {
+                    runtime: self.name().to_string(),
+                    id: container.id,
+                    name,
+                    image: container.image.map(|i| i.image).unwrap_or_default(),
+                    state: "running".to_string(),
+                    pid: None,
+                });
+            }
+        }
+
+        Ok(summaries)
+    }
+}
+
+fn container_state_name(state: i32) -> &'static str {
+    match state {
+        CONTAINER_CREATED => "created",
+        CONTAINER_RUNNING => "running",
+        CONTAINER_EXITED => "exited",
+        _ => "unknown",
+    }
+}
+
+pub fn encode_grpc_frame<M: Message>(message: &M) -> Bytes {
+    let mut frame = BytesMut::with_capacity(5 + message.encoded_len());
+    frame.put_u8(0);
+    frame.put_u32(message.encoded_len() as u32);
+    message
+        .encode(&mut frame)
+        .expect("buffer has capacity for message");
+    frame.freeze()
+}
+
+pub fn decode_grpc_frame<M: Message + Default>(mut data: Bytes) -> Result<M> {
+    if data.len() < 5 {
+        return Err(anyhow!("Truncated gRPC frame"));
+    }
+    if data.get_u8() != 0 {
+        return Err(anyhow!("Compressed gRPC messages are not supported"));
+    }
+
+    let len = data.get_u32() as usize;
+    if data.len() < len {
+        return Err(anyhow!("Truncated gRPC message"));
+    }
+
+    Ok(M::decode(data.slice(..len))?)
+}
+
+fn check_grpc_status(headers: &http::HeaderMap, method: &str) -> Result<()> {
+    match headers.get("grpc-status").and_then(|v| v.to_str().ok()) {
+        None | Some("0") => Ok(()),
+        Some(code) => {
+            let message = headers
+                .get("grpc-message")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            Err(anyhow!("CRI {} failed with gRPC status {}: {}", method, code, message))
+        }
+    }
+}
+
+/// The subset of the `runtime.v1` CRI protobuf messages crashcart needs.
+/// Field tags match `k8s.io/cri-api/pkg/apis/runtime/v1/api.proto`.
+pub mod api {
+    use std::collections::HashMap;
+
+    pub const SANDBOX_READY: i32 = 0;
+    pub const CONTAINER_CREATED: i32 = 0;
+    pub const CONTAINER_RUNNING: i32 = 1;
+    pub const CONTAINER_EXITED: i32 = 2;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PodSandboxMetadata {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub uid: String,
+        #[prost(string, tag = "3")]
+        pub namespace: String,
+        #[prost(uint32, tag = "4")]
+        pub attempt: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PodSandbox {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(message, optional, tag = "2")]
+        pub metadata: Option<PodSandboxMetadata>,
+        #[prost(int32, tag = "3")]
+        pub state: i32,
+        #[prost(int64, tag = "4")]
+        pub created_at: i64,
+        #[prost(map = "string, string", tag = "5")]
+        pub labels: HashMap<String, String>,
+        #[prost(map = "string, string", tag = "6")]
+        pub annotations: HashMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListPodSandboxRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListPodSandboxResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub items: Vec<PodSandbox>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PodSandboxStatusRequest {
+        #[prost(string, tag = "1")]
+        pub pod_sandbox_id: String,
+        #[prost(bool, tag = "2")]
+        pub verbose: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PodSandboxStatus {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(message, optional, tag = "2")]
+        pub metadata: Option<PodSandboxMetadata>,
+        #[prost(int32, tag = "3")]
+        pub state: i32,
+        #[prost(int64, tag = "4")]
+        pub created_at: i64,
+        #[prost(map = "string, string", tag = "7")]
+        pub labels: HashMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PodSandboxStatusResponse {
+        #[prost(message, optional, tag = "1")]
+        pub status: Option<PodSandboxStatus>,
+        #[prost(map = "string, string", tag = "2")]
+        pub info: HashMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ContainerMetadata {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(uint32, tag = "2")]
+        pub attempt: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ImageSpec {
+        #[prost(string, tag = "1")]
+        pub image: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ContainerFilter {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "3")]
+        pub pod_sandbox_id: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListContainersRequest {
+        #[prost(message, optional, tag = "1")]
+        pub filter: Option<ContainerFilter>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Container {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub pod_sandbox_id: String,
+        #[prost(message, optional, tag = "3")]
+        pub metadata: Option<ContainerMetadata>,
+        #[prost(message, optional, tag = "4")]
+        pub image: Option<ImageSpec>,
+        #[prost(string, tag = "5")]
+        pub image_ref: String,
+        #[prost(int32, tag = "6")]
+        pub state: i32,
+        #[prost(int64, tag = "7")]
+        pub created_at: i64,
+        #[prost(map = "string, string", tag = "8")]
+        pub labels: HashMap<String, String>,
+        #[prost(map = "string, string", tag = "9")]
+        pub annotations: HashMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListContainersResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub containers: Vec<Container>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ContainerStatusRequest {
+        #[prost(string, tag = "1")]
+        pub container_id: String,
+        #[prost(bool, tag = "2")]
+        pub verbose: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ContainerStatus {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(message, optional, tag = "2")]
+        pub metadata: Option<ContainerMetadata>,
+        #[prost(int32, tag = "3")]
+        pub state: i32,
+        #[prost(int64, tag = "4")]
+        pub created_at: i64,
+        #[prost(int64, tag = "5")]
+        pub started_at: i64,
+        #[prost(message, optional, tag = "8")]
+        pub image: Option<ImageSpec>,
+        #[prost(string, tag = "9")]
+        pub image_ref: String,
+        #[prost(map = "string, string", tag = "12")]
+        pub labels: HashMap<String, String>,
+        #[prost(map = "string, string", tag = "13")]
+        pub annotations: HashMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ContainerStatusResponse {
+        #[prost(message, optional, tag = "1")]
+        pub status: Option<ContainerStatus>,
+        #[prost(map = "string, string", tag = "2")]
+        pub info: HashMap<String, String>,
+    }
+}
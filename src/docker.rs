@@ -0,0 +1,428 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::OnceCell;
+use tracing::{debug, info};
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+use crate::http::{self, UnixHttpClient};
+use crate::info::ContainerInfo;
+use crate::terminal::{self, RawModeGuard};
+
+const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Client for the Docker Engine API served on the daemon's unix socket.
+#[derive(Debug, Clone)]
+pub struct DockerClient {
+    http: UnixHttpClient,
+}
+
+impl DockerClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            http: UnixHttpClient::new(socket),
+        }
+    }
+
+    /// Build a client from `DOCKER_HOST`, defaulting to the standard socket.
+    /// Returns `None` when `DOCKER_HOST` points at a non-unix endpoint.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("DOCKER_HOST") {
+            Ok(host) if !host.is_empty() => host.strip_prefix("unix://").map(Self::new),
+            _ => Some(Self::new(DEFAULT_DOCKER_SOCKET)),
+        }
+    }
+
+    pub fn socket(&self) -> &Path {
+        self.http.socket()
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        let response = self.http.get("/_ping").await?;
+        if !response.is_success() {
+            return Err(anyhow!("Docker API ping failed: {}", response.error_message()));
+        }
+        Ok(())
+    }
+
+    pub async fn inspect(&self, id: &str) -> Result<Value> {
+        let response = self.http.get(&format!("/containers/{}/json", http::path_segment(id))).await?;
+        if !response.is_success() {
+            return Err(anyhow!("Docker container {} not found: {}", id, response.error_message()));
+        }
+        response.json()
+    }
+
+    /// Running containers, as returned by `GET /containers/json`.
+    pub async fn list(&self) -> Result<Vec<Value>> {
+        let response = self.http.get("/containers/json").await?;
+        if !response.is_success() {
+            return Err(anyhow!("Failed to list Docker containers: {}", response.error_message()));
+        }
+        response.json()
+    }
+
+    pub async fn get_pid(&self, id: &str) -> Result<u32> {
+        let info = self.inspect(id).await?;
+        let pid = info
+            .pointer("/State/Pid")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow!("Docker inspect output has no State.Pid"))?;
+
+        if pid == 0 {
+            return Err(anyhow!("Docker container {} is not running", id));
+        }
+
+        debug!("Docker container {} has PID {}", id, pid);
+        Ok(pid as u32)
+    }
+
+    /// Run `command` in the container attached to crashcart's own stdio.
+    /// A TTY is requested when stdin and stdout are terminals.
+    pub async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let tty = terminal::stdin_is_tty() && terminal::stdout_is_tty();
+        let _raw_mode = if tty { Some(RawModeGuard::enable()?) } else { None };
+
+        self.exec_with_io(
+            id,
+            command,
+            tty,
+            tokio::io::stdin(),
+            tokio::io::stdout(),
+            tokio::io::stderr(),
+        )
+        .await
+    }
+
+    /// Create an exec instance, hijack its attach stream and pump the given
+    /// streams through it until the process exits. Returns the exit code.
+    pub async fn exec_with_io<I, O, E>(
+        &self,
+        id: &str,
+        command: &[String],
+        tty: bool,
+        mut stdin: I,
+        mut stdout: O,
+        mut stderr: E,
+    ) -> Result<i32>
+    where
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+        E: AsyncWrite + Unpin,
+    {
+        let response = self
+            .http
+            .post(
+                &format!("/containers/{}/exec", http::path_segment(id)),
+                Some(&json!({
+                    "AttachStdin": true,
+                    "AttachStdout": true,
+                    "AttachStderr": true,
+                    "Tty": tty,
+                    "Cmd": command,
+                })),
+            )
+            .await?;
+        if !response.is_success() {
+            return Err(anyhow!("Failed to create Docker exec: {}", response.error_message()));
+        }
+
+        let exec_id = response
+            .json::<Value>()?
+            .get("Id")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Docker exec create response has no Id"))?;
+        debug!("Created Docker exec {} in {}", exec_id, id);
+
+        let upgraded = self
+            .http
+            .upgrade(
+                "POST",
+                &format!("/exec/{}/start", exec_id),
+                Some(&json!({ "Detach": false, "Tty": tty })),
+            )
+            .await?;
+        if upgraded.response.status != 101 && upgraded.response.status != 200 {
+            return Err(anyhow!(
+                "Failed to start Docker exec: HTTP status {}",
+                upgraded.response.status
+            ));
+        }
+
+        if tty {
+            if let Some((rows, cols)) = terminal::window_size() {
+                self.resize_exec(&exec_id, rows, cols).await?;
+            }
+        }
+
+        let (read_half, mut write_half) = upgraded.stream.into_split();
+        let mut reader = (&upgraded.buffered[..]).chain(read_half);
+
+        let input = async {
+            let _ = tokio::io::copy(&mut stdin, &mut write_half).await;
+            let _ = write_half.shutdown().await;
+            std::future::pending::<()>().await
+        };
+
+        let output = async {
+            if tty {
+                tokio::io::copy(&mut reader, &mut stdout).await?;
+                stdout.flush().await?;
+                Ok(())
+            } else {
+                demux_stream(&mut reader, &mut stdout, &mut stderr).await
+            }
+        };
+
+        tokio::select! {
+            result = output => result.context("Docker exec stream failed")?,
+            _ = input => unreachable!(),
+        }
+
+        self.exec_exit_code(&exec_id).await
+    }
+
+    pub async fn resize_exec(&self, exec_id: &str, rows: u16, cols: u16) -> Result<()> {
+        let response = self
+            .http
+            .post(&format!("/exec/{}/resize?h={}&w={}", exec_id, rows, cols), None)
+            .await?;
+        if !response.is_success() {
+            return Err(anyhow!("Failed to resize Docker exec: {}", response.error_message()));
+        }
+        Ok(())
+    }
+
+    async fn exec_exit_code(&self, exec_id: &str) -> Result<i32> {
+        // The daemon can report the exec as running for a moment after the
+        // attach stream closes.
+        for _ in 0..50 {
+            let response = self.http.get(&format!("/exec/{}/json", exec_id)).await?;
+            if !response.is_success() {
+                return Err(anyhow!("Failed to inspect Docker exec: {}", response.error_message()));
+            }
+
+            let info: Value = response.json()?;
+            if let Some(code) = info.get("ExitCode").and_then(|v| v.as_i64()) {
+                if !info.get("Running").and_then(|v| v.as_bool()).unwrap_or(false) {
+                    return Ok(code as i32);
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Err(anyhow!("Docker exec {} did not report an exit code", exec_id))
+    }
+}
+
+/// Docker backend: talks to the Engine API on the daemon socket and only
+/// falls back to the `docker` CLI when the API is unreachable.
+#[derive(Default)]
+pub struct DockerBackend {
+    api: OnceCell<Option<DockerClient>>,
+}
+
+impl DockerBackend {
+    /// Docker Engine API client, if the daemon answers on its unix socket.
+    /// The daemon is only pinged the first time.
+    async fn api(&self) -> Option<DockerClient> {
+        self.api
+            .get_or_init(|| async {
+                let client = DockerClient::from_env()?;
+                match client.ping().await {
+                    Ok(()) => Some(client),
+                    Err(e) => {
+                        debug!("Docker API unavailable, falling back to CLI: {:#}", e);
+                        None
+                    }
+                }
+            })
+            .await
+            .clone()
+    }
+
+    async fn cli_inspect(&self, id: &str) -> Result<Value> {
+        let output = Command::new("docker")
+            .args(["inspect", id])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run docker inspect")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Docker container not found"));
+        }
+
+        let mut entries: Vec<Value> = serde_json::from_slice(&output.stdout)?;
+        entries
+            .pop()
+            .ok_or_else(|| anyhow!("docker inspect returned no entries for {}", id))
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for DockerBackend {
+    fn name(&self) -> &str {
+        "docker"
+    }
+
+    async fn is_available(&self) -> bool {
+        self.api().await.is_some() || which::which("docker").is_ok()
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let info = match self.api().await {
+            Some(client) => {
+                let info = client.inspect(target).await?;
+                info!("Detected Docker container via {}: {}", client.socket().display(), target);
+                info
+            }
+            None => {
+                let info = self.cli_inspect(target).await?;
+                info!("Detected Docker container: {}", target);
+                info
+            }
+        };
+
+        Ok(info
+            .get("Id")
+            .and_then(|v| v.as_str())
+            .unwrap_or(target)
+            .to_string())
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        if let Some(client) = self.api().await {
+            return client.get_pid(id).await;
+        }
+
+        let pid = self
+            .inspect(id)
+            .await?
+            .pointer("/State/Pid")
+            .and_then(|v| v.as_u64())
+            .context("Failed to parse Docker PID")?;
+        if pid == 0 {
+            return Err(anyhow!("Docker container {} is not running", id));
+        }
+
+        debug!("Docker container {} has PID {}", id, pid);
+        Ok(pid as u32)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        match self.api().await {
+            Some(client) => client.inspect(id).await,
+            None => self.cli_inspect(id).await,
+        }
+    }
+
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        let inspect = self.inspect(id).await?;
+        let mut info = ContainerInfo::from_docker_inspect(self.name(), &inspect);
+        if let Some(pid) = info.pid {
+            info.complete_from_proc(Path::new("/proc"), pid);
+        }
+        Ok(info)
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        if let Some(client) = self.api().await {
+            return client.exec(id, command).await;
+        }
+
+        let status = Command::new("docker")
+            .args(["exec", terminal::exec_flags(), id])
+            .args(command)
+            .status()
+            .await
+            .context("Failed to execute docker exec")?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        let containers = match self.api().await {
+            Some(client) => client.list().await?,
+            None => {
+                let output = Command::new("docker")
+                    .args(["ps", "--no-trunc", "--format", "{{json .}}"])
+                    .kill_on_drop(true)
+                    .output()
+                    .await
+                    .context("Failed to run docker ps")?;
+                output
+                    .stdout
+                    .split(|b| *b == b'\n')
+                    .filter(|line| !line.is_empty())
+                    .map(serde_json::from_slice)
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        // The API and `docker ps` use slightly different field names
+        Ok(containers
+            .iter()
+            .map(|c| {
+                let field = |keys: &[&str]| {
+                    keys.iter()
+                        .find_map(|k| c.get(*k).and_then(|v| v.as_str()))
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let name = c
+                    .pointer("/Names/0")
+                    .and_then(|v| v.as_str())
+                    .map(|n| n.trim_start_matches('/').to_string())
+                    .unwrap_or_else(|| field(&["Names"]));
+
+                ContainerSummary {
+                    runtime: self.name().to_string(),
+                    id: field(&["Id", "ID"]),
+                    name,
+                    image: field(&["Image"]),
+                    state: field(&["State"]),
+                    pid: None,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Split Docker's multiplexed attach stream (8-byte frame headers carrying
+/// the stream type and payload length) into stdout and stderr.
+async fn demux_stream<R, O, E>(reader: &mut R, stdout: &mut O, stderr: &mut E) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    O: AsyncWrite + Unpin,
+    E: AsyncWrite + Unpin,
+{
+    let mut header = [0u8; 8];
+    let mut payload = Vec::new();
+
+    loop {
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        payload.resize(len, 0);
+        reader.read_exact(&mut payload).await?;
+
+        if header[0] == 2 {
+            stderr.write_all(&payload).await?;
+            stderr.flush().await?;
+        } else {
+            stdout.write_all(&payload).await?;
+            stdout.flush().await?;
+        }
+    }
+}
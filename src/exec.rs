@@ -0,0 +1,712 @@
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::{fork, ForkResult};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::credentials::Credentials;
+use crate::namespace::{Namespace, TargetNamespaces, UserNamespace};
+use crate::privileges::{self, PrivilegeProfile};
+use crate::pty::{self, Proxy, Pty};
+use crate::recording::{Recorder, Recording};
+use crate::security::SecurityContext;
+use crate::terminal::{self, RawModeGuard};
+
+/// A command to run inside a target's namespaces.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecSpec {
+    /// Program and arguments; the program is looked up on `PATH` unless it
+    /// is a path.
+    pub argv: Vec<String>,
+    /// Variables set on top of crashcart's own environment.
+    pub env: Vec<(String, String)>,
+    /// Directory to start in, opened before joining the namespaces, so
+    /// `/proc/<pid>/cwd` names the target's working directory. Without one
+    /// the command starts at the root.
+    pub cwd: Option<PathBuf>,
+    /// Namespaces of the target to join.
+    pub namespaces: Vec<Namespace>,
+    /// Who to run as; by default root in the target's user namespace.
+    pub credentials: Option<Credentials>,
+    /// Capabilities the command keeps.
+    pub privileges: PrivilegeProfile,
+    /// Confinement to copy from the target, instead of always setting
+    /// no_new_privs.
+    pub security: Option<SecurityContext>,
+    /// Record the session's terminal as asciicast.
+    pub recording: Option<Recording>,
+}
+
+impl ExecSpec {
+    /// Run `argv` in all of the target's namespaces.
+    pub fn new(argv: Vec<String>) -> Self {
+        Self {
+            argv,
+            env: Vec::new(),
+            cwd: None,
+            namespaces: Namespace::ALL.to_vec(),
+            credentials: None,
+            privileges: PrivilegeProfile::default(),
+            security: None,
+            recording: None,
+        }
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Run `spec` in the namespaces of `pid` with crashcart's stdio and return
+/// its exit code, or 128 + the signal number if it was killed. When
+/// crashcart runs on a terminal the command gets its own pty, allocated in
+/// the target and proxied in raw mode; otherwise it uses crashcart's stdin,
+/// stdout and stderr directly, so it can be piped. A recorded command's
+/// output is passed on through pipes instead, to be recorded too.
+pub async fn run(pid: u32, spec: &ExecSpec) -> Result<i32> {
+    let output = execute(pid, spec, false).await?;
+    Ok(exit_code(output.status))
+}
+
+/// Run `spec` in the namespaces of `pid` with stdin closed and stdout and
+/// stderr captured (and recorded, if the spec says so).
+pub async fn output(pid: u32, spec: &ExecSpec) -> Result<Output> {
+    execute(pid, spec, true).await
+}
+
+/// Shell-style exit code for `status`.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(-1)
+}
+
+/// Argument and environment arrays built before forking, so the children
+/// don't have to build them. The children still allocate when setting
+/// credentials and security contexts and when reporting errors (see
+/// [`start`]).
+struct Program {
+    argv: Vec<CString>,
+    envp: Vec<CString>,
+    cwd: Option<OwnedFd>,
+    credentials: Option<Credentials>,
+    capabilities: u64,
+    security: Option<SecurityContext>,
+}
+
+impl Program {
+    fn new(spec: &ExecSpec) -> Result<Self> {
+        if spec.argv.is_empty() {
+            return Err(anyhow!("No command to execute"));
+        }
+        let argv = spec
+            .argv
+            .iter()
+            .map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<_, _>>()
+            .context("Command contains a NUL byte")?;
+
+        let mut envp = Vec::new();
+        for (key, value) in std::env::vars_os() {
+            if spec.env.iter().any(|(k, _)| k.as_bytes() == key.as_bytes()) {
+                continue;
+            }
+            let mut entry = key.as_bytes().to_vec();
+            entry.push(b'=');
+            entry.extend_from_slice(value.as_bytes());
+            envp.extend(CString::new(entry).ok());
+        }
+        for (key, value) in &spec.env {
+            envp.push(CString::new(format!("{}={}", key, value)).context("Environment contains a NUL byte")?);
+        }
+
+        // A target that is exiting may have no working directory left
+        let cwd = spec.cwd.as_ref().and_then(|path| match open_path(path) {
+            Ok(fd) => Some(fd),
+            Err(e) => {
+                debug!("Not starting in {}: {}", path.display(), e);
+                None
+            }
+        });
+
+        Ok(Self {
+            argv,
+            envp,
+            cwd,
+            credentials: spec.credentials.clone(),
+            capabilities: spec.privileges.capabilities(),
+            security: spec.security.clone(),
+        })
+    }
+
+    /// Replace the current process with the program; only returns on error.
+    fn exec(&self) -> std::io::Error {
+        let argv: Vec<*const libc::c_char> =
+            self.argv.iter().map(|a| a.as_ptr()).chain(std::iter::once(std::ptr::null())).collect();
+        let envp: Vec<*const libc::c_char> =
+            self.envp.iter().map(|e| e.as_ptr()).chain(std::iter::once(std::ptr::null())).collect();
+        unsafe { libc::execvpe(argv[0], argv.as_ptr(), envp.as_ptr()) };
+        std::io::Error::last_os_error()
+    }
+}
+
+/// Pipes wired to the command's stdout and stderr when capturing or
+/// recording them. Only a captured command has its stdin closed.
+struct Capture {
+    stdin: Option<OwnedFd>,
+    stdout: (OwnedFd, OwnedFd),
+    stderr: (OwnedFd, OwnedFd),
+}
+
+impl Capture {
+    fn new(keep_stdin: bool) -> Result<Self> {
+        let stdin = if keep_stdin {
+            None
+        } else {
+            Some(OwnedFd::from(File::open("/dev/null").context("Failed to open /dev/null")?))
+        };
+        Ok(Self {
+            stdin,
+            stdout: cloexec_pipe()?,
+            stderr: cloexec_pipe()?,
+        })
+    }
+}
+
+/// Socket the helper passes the pty master back over, and the size to
+/// give the pty.
+struct TerminalRequest {
+    socket: OwnedFd,
+    size: Option<(u16, u16)>,
+}
+
+/// Where the command's stdin, stdout and stderr go.
+enum Stdio {
+    /// crashcart's own.
+    Inherit,
+    /// stdin closed, stdout and stderr collected.
+    Capture,
+    /// A new pty of the given size.
+    Terminal(Option<(u16, u16)>),
+}
+
+// Readers of a captured command's stdout and stderr
+type CapturedOutput = (JoinHandle<std::io::Result<Vec<u8>>>, JoinHandle<std::io::Result<Vec<u8>>>);
+
+/// A command started by [`start`], not yet waited for.
+struct Started {
+    pid: u32,
+    helper: nix::unistd::Pid,
+    master: Option<Arc<OwnedFd>>,
+    output: Option<CapturedOutput>,
+    command_pid: Arc<AtomicI32>,
+    messages: mpsc::UnboundedReceiver<String>,
+}
+
+/// A command running on a pty of its own, for sessions that outlive the
+/// terminal crashcart was started from.
+pub struct TerminalCommand {
+    started: Started,
+    master: Arc<OwnedFd>,
+}
+
+impl TerminalCommand {
+    pub fn master(&self) -> Arc<OwnedFd> {
+        self.master.clone()
+    }
+
+    /// Wait for the command and return its exit code.
+    pub async fn wait(self) -> Result<i32> {
+        Ok(exit_code(self.started.wait().await?.status))
+    }
+}
+
+/// Start `spec` in the namespaces of `pid` on a new `(rows, cols)` pty that
+/// the caller proxies.
+pub async fn spawn_on_pty(pid: u32, spec: &ExecSpec, size: (u16, u16)) -> Result<TerminalCommand> {
+    let started = start(pid, spec, Stdio::Terminal(Some(size)), None).await?;
+    match started.master.clone() {
+        Some(master) => Ok(TerminalCommand { started, master }),
+        None => Err(started.wait().await.err().unwrap_or_else(|| anyhow!("Exec helper sent no terminal"))),
+    }
+}
+
+/// Run the command with crashcart's stdio, or captured. On a terminal, the
+/// command gets a pty that is proxied in raw mode and sized like crashcart's.
+async fn execute(pid: u32, spec: &ExecSpec, capture: bool) -> Result<Output> {
+    let interactive = terminal::stdin_is_tty();
+    let size = terminal::window_size();
+    let stdio = if capture {
+        Stdio::Capture
+    } else if interactive && terminal::stdout_is_tty() {
+        Stdio::Terminal(size)
+    } else {
+        Stdio::Inherit
+    };
+    let recorder = match &spec.recording {
+        Some(recording) => Some(Arc::new(Mutex::new(Recorder::create(recording, size.unwrap_or((24, 80)))?))),
+        None => None,
+    };
+
+    let started = start(pid, spec, stdio, recorder.clone()).await?;
+    let raw_mode = match started.master {
+        Some(_) => Some(RawModeGuard::enable()?),
+        None => None,
+    };
+    let proxy = started.master.clone().map(|master| Proxy::start(master, recorder.clone())).transpose()?;
+    let forwarding = forward_signals(started.command_pid.clone(), interactive, started.master.clone(), recorder);
+
+    let output = started.wait().await;
+    drop(forwarding);
+    if let Some(proxy) = proxy {
+        proxy.finish().await;
+    }
+    drop(raw_mode);
+    output
+}
+
+/// Join the namespaces of `pid` and start the command.
+///
+/// Joining happens in a forked helper, since a multi-threaded process may
+/// not change its user or mount namespace. The helper forks once more so
+/// the command is created inside the target's PID namespace, reports the
+/// command's host PID so crashcart can forward signals to it, and finally
+/// reports its wait status. All of this travels over a status pipe as
+/// `P<pid>`, `S<status>` and `E<error>` lines. A pty is allocated by the
+/// helper once it is in the target's mount namespace, and its master is
+/// sent back over a socket. Output that doesn't go through a pty goes to
+/// `recorder` as it is read.
+async fn start(pid: u32, spec: &ExecSpec, stdio: Stdio, recorder: Option<Arc<Mutex<Recorder>>>) -> Result<Started> {
+    let namespaces = TargetNamespaces::open(pid, &spec.namespaces)?;
+    let mut program = Program::new(spec)?;
+    if let (Some(credentials), None) = (&program.credentials, namespaces.user_namespace()) {
+        // The IDs are the target's view; without its user namespace they
+        // have to be the host's
+        let user = UserNamespace::read(pid)?;
+        if !user.is_initial() {
+            program.credentials = Some(credentials.to_parent_namespace(&user)?);
+        }
+    }
+    let (terminal_socket, terminal_request) = match stdio {
+        Stdio::Terminal(size) => {
+            let (ours, theirs) = cloexec_socketpair()?;
+            (Some(ours), Some(TerminalRequest { socket: theirs, size }))
+        }
+        _ => (None, None),
+    };
+    let echo = matches!(stdio, Stdio::Inherit);
+    let capture = match (&stdio, &recorder) {
+        (Stdio::Capture, _) => Some(Capture::new(false)?),
+        // Recorded on the way through to crashcart's stdout and stderr
+        (Stdio::Inherit, Some(_)) => Some(Capture::new(true)?),
+        _ => None,
+    };
+    let (status_read, status_write) = cloexec_pipe()?;
+    debug!(
+        "Executing {:?} in the {:?} namespaces of PID {}",
+        spec.argv,
+        namespaces.namespaces().map(|ns| ns.name()).collect::<Vec<_>>(),
+        pid
+    );
+
+    // Don't let the helper hold on to pipes of other commands crashcart
+    // runs concurrently
+    let mut keep: Vec<RawFd> = namespaces.raw_fds().chain([status_write.as_raw_fd()]).collect();
+    keep.extend(program.cwd.as_ref().map(|cwd| cwd.as_raw_fd()));
+    if let Some(capture) = &capture {
+        keep.extend(capture.stdin.as_ref().map(|stdin| stdin.as_raw_fd()));
+        keep.extend([capture.stdout.1.as_raw_fd(), capture.stderr.1.as_raw_fd()]);
+    }
+    keep.extend(terminal_request.as_ref().map(|request| request.socket.as_raw_fd()));
+    keep.sort_unstable();
+
+    // SAFETY: crashcart is multi-threaded, so only this thread survives in
+    // the children, and any lock another thread held stays held. Both
+    // children exec or `_exit` without returning into the async runtime
+    // and take no locks of their own: no logging, no stdio locks. They do
+    // allocate (formatting errors, building credential, seccomp and LSM
+    // state), which relies on the C library's malloc being usable after
+    // fork, as glibc and recent musl make it by taking its locks around
+    // fork.
+    let helper = match unsafe { fork() }.context("Failed to fork exec helper")? {
+        ForkResult::Child => {
+            drop(status_read);
+            helper_main(&namespaces, &program, capture.as_ref(), terminal_request.as_ref(), status_write, &keep)
+        }
+        ForkResult::Parent { child } => child,
+    };
+    drop(status_write);
+    drop(terminal_request);
+    drop(namespaces);
+
+    let (tx, messages) = mpsc::unbounded_channel();
+    tokio::task::spawn_blocking(move || {
+        for line in BufReader::new(File::from(status_read)).lines().map_while(Result::ok) {
+            let _ = tx.send(line);
+        }
+    });
+
+    // No master arrives if the helper failed first; its error follows on
+    // the status pipe
+    let master = match terminal_socket {
+        Some(socket) => tokio::task::spawn_blocking(move || pty::recv_fd(&socket))
+            .await?
+            .context("Failed to receive the terminal from the exec helper")?
+            .map(Arc::new),
+        None => None,
+    };
+
+    Ok(Started {
+        pid,
+        helper,
+        master,
+        output: capture.map(|Capture { stdout, stderr, .. }| {
+            (
+                read_output(stdout.0, echo.then_some(libc::STDOUT_FILENO), recorder.clone()),
+                read_output(stderr.0, echo.then_some(libc::STDERR_FILENO), recorder),
+            )
+        }),
+        command_pid: Arc::new(AtomicI32::new(0)),
+        messages,
+    })
+}
+
+impl Started {
+    /// Follow the helper's reports until it exits.
+    async fn wait(mut self) -> Result<Output> {
+        let mut status = None;
+        let mut error = None;
+        while let Some(line) = self.messages.recv().await {
+            let (kind, value) = line.split_at(line.len().min(1));
+            match kind {
+                "P" => self.command_pid.store(value.parse().unwrap_or(0), Ordering::SeqCst),
+                "S" => status = value.parse::<i32>().ok().map(ExitStatus::from_raw),
+                "E" => error = Some(value.to_string()),
+                _ => debug!("Unexpected exec helper message {:?}", line),
+            }
+        }
+
+        let helper = self.helper;
+        tokio::task::spawn_blocking(move || nix::sys::wait::waitpid(helper, None))
+            .await?
+            .context("Failed to wait for exec helper")?;
+
+        let (stdout, stderr) = match self.output {
+            Some((stdout, stderr)) => (stdout.await??, stderr.await??),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        if let Some(error) = error {
+            return Err(anyhow!(error));
+        }
+        let status = status.ok_or_else(|| anyhow!("Exec helper for PID {} exited without a status", self.pid))?;
+        Ok(Output { status, stdout, stderr })
+    }
+}
+
+/// Body of the forked helper: close all descriptors but `keep`, join the
+/// namespaces, fork the command and wait for it.
+fn helper_main(
+    namespaces: &TargetNamespaces,
+    program: &Program,
+    capture: Option<&Capture>,
+    terminal: Option<&TerminalRequest>,
+    status: OwnedFd,
+    keep: &[RawFd],
+) -> ! {
+    close_fds_except(keep);
+    let mut status = File::from(status);
+
+    if let Err(e) = namespaces.join() {
+        let _ = writeln!(status, "E{:#}", e);
+        unsafe { libc::_exit(126) }
+    }
+    // Joining the mount namespace moved us to its root
+    if let Some(cwd) = &program.cwd {
+        unsafe { libc::fchdir(cwd.as_raw_fd()) };
+    }
+
+    let pty = terminal.map(|request| {
+        Pty::open(request.size)
+            .and_then(|pty| pty::send_fd(&request.socket, pty.master.as_raw_fd()).map(|_| pty))
+            .unwrap_or_else(|e| {
+                let _ = writeln!(status, "EFailed to allocate a terminal in the target: {}", e);
+                unsafe { libc::_exit(126) }
+            })
+    });
+
+    let command = match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            if let Some(capture) = capture {
+                unsafe {
+                    if let Some(stdin) = &capture.stdin {
+                        libc::dup2(stdin.as_raw_fd(), 0);
+                    }
+                    libc::dup2(capture.stdout.1.as_raw_fd(), 1);
+                    libc::dup2(capture.stderr.1.as_raw_fd(), 2);
+                }
+            }
+            if let Err(e) = pty.as_ref().map_or(Ok(()), |pty| pty::make_controlling(&pty.slave)) {
+                let _ = writeln!(status, "EFailed to attach the terminal: {}", e);
+                unsafe { libc::_exit(126) }
+            }
+            // Seccomp goes on while the command still has CAP_SYS_ADMIN, so
+            // the filters must allow dropping privileges, as with runc
+            if let Some(security) = &program.security {
+                if let Err(e) = security.apply_lsm().and_then(|_| security.install_seccomp()) {
+                    let _ = writeln!(status, "EFailed to match the target's security context: {:#}", e);
+                    unsafe { libc::_exit(126) }
+                }
+            }
+            let no_new_privs = program.security.as_ref().is_none_or(|security| security.no_new_privs);
+            if let Err(e) = privileges::restrict(program.capabilities, program.credentials.as_ref(), no_new_privs) {
+                let _ = writeln!(status, "EFailed to drop privileges: {:#}", e);
+                unsafe { libc::_exit(126) }
+            }
+            let e = program.exec();
+            let _ = writeln!(status, "EFailed to execute {}: {}", program.argv[0].to_string_lossy(), e);
+            unsafe { libc::_exit(127) }
+        }
+        Ok(ForkResult::Parent { child }) => child,
+        Err(e) => {
+            let _ = writeln!(status, "EFailed to fork in the target namespaces: {}", e);
+            unsafe { libc::_exit(126) }
+        }
+    };
+    drop(pty);
+    let _ = writeln!(status, "P{}", command);
+
+    // Signals meant for the command reach it directly or through
+    // crashcart's forwarding; the helper must outlive it to report back
+    for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM, libc::SIGHUP, libc::SIGUSR1, libc::SIGUSR2] {
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+
+    let mut wait_status = 0;
+    loop {
+        let ret = unsafe { libc::waitpid(command.as_raw(), &mut wait_status, 0) };
+        if ret >= 0 || std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            break;
+        }
+    }
+    let _ = writeln!(status, "S{}", wait_status);
+    unsafe { libc::_exit(0) }
+}
+
+/// What to do with a signal crashcart receives.
+#[derive(Clone, Copy)]
+enum Route {
+    Command,
+    /// To the foreground process group of the command's terminal, as if
+    /// typed there.
+    Foreground,
+    /// Resize the command's terminal to match crashcart's.
+    Resize,
+    Swallow,
+}
+
+/// Signals [`forward_signals`] may take over from their default action.
+const FORWARDED_SIGNALS: [libc::c_int; 8] = [
+    libc::SIGTERM,
+    libc::SIGHUP,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGWINCH,
+];
+
+/// How many commands signals are being forwarded to, and tokio's handlers
+/// set aside while there are none.
+static FORWARDING: Mutex<(usize, Vec<(libc::c_int, libc::sigaction)>)> = Mutex::new((0, Vec::new()));
+
+/// Signal forwarding for one command; dropping it stops forwarding. Once
+/// no command is left, the signals get their default actions back, since
+/// tokio's handlers stay installed for good and would swallow them, so
+/// crashcart can still be interrupted while it cleans up.
+struct SignalForwarding {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SignalForwarding {
+    fn new() -> Self {
+        let mut forwarding = FORWARDING.lock().unwrap();
+        forwarding.0 += 1;
+        for (signal, handler) in forwarding.1.drain(..) {
+            unsafe { libc::sigaction(signal, &handler, std::ptr::null_mut()) };
+        }
+        Self { tasks: Vec::new() }
+    }
+}
+
+impl Drop for SignalForwarding {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        let mut forwarding = FORWARDING.lock().unwrap();
+        forwarding.0 -= 1;
+        if forwarding.0 > 0 {
+            return;
+        }
+        for signal in FORWARDED_SIGNALS {
+            let mut default: libc::sigaction = unsafe { std::mem::zeroed() };
+            default.sa_sigaction = libc::SIG_DFL;
+            let mut handler: libc::sigaction = unsafe { std::mem::zeroed() };
+            if unsafe { libc::sigaction(signal, &default, &mut handler) } == 0 {
+                forwarding.1.push((signal, handler));
+            }
+        }
+    }
+}
+
+/// Forward termination and user signals sent to crashcart to the command
+/// once its PID is known. With a pty, job control signals go to its
+/// foreground group and window changes resize it. Without one, a command
+/// sharing crashcart's terminal already gets ^C and ^\ through the
+/// foreground process group, so SIGINT and SIGQUIT are only swallowed, not
+/// forwarded twice.
+fn forward_signals(
+    command_pid: Arc<AtomicI32>,
+    interactive: bool,
+    terminal: Option<Arc<OwnedFd>>,
+    recorder: Option<Arc<Mutex<Recorder>>>,
+) -> SignalForwarding {
+    let mut forwarding = SignalForwarding::new();
+    let job_control = match (&terminal, interactive) {
+        (Some(_), _) => Route::Foreground,
+        (None, true) => Route::Swallow,
+        (None, false) => Route::Command,
+    };
+    let mut kinds = vec![
+        (SignalKind::terminate(), Route::Command),
+        (SignalKind::hangup(), Route::Command),
+        (SignalKind::user_defined1(), Route::Command),
+        (SignalKind::user_defined2(), Route::Command),
+        (SignalKind::interrupt(), job_control),
+        (SignalKind::quit(), job_control),
+    ];
+    if terminal.is_some() {
+        kinds.push((SignalKind::from_raw(libc::SIGTSTP), Route::Foreground));
+        kinds.push((SignalKind::window_change(), Route::Resize));
+    }
+
+    forwarding.tasks = kinds
+        .into_iter()
+        .filter_map(|(kind, route)| {
+            let mut stream = signal(kind).ok()?;
+            let command_pid = command_pid.clone();
+            let terminal = terminal.clone();
+            let recorder = recorder.clone();
+            Some(tokio::spawn(async move {
+                while stream.recv().await.is_some() {
+                    let pid = command_pid.load(Ordering::SeqCst);
+                    let target = match route {
+                        Route::Command => pid,
+                        Route::Foreground => terminal.as_deref().and_then(pty::foreground_group).map_or(pid, |group| -group),
+                        Route::Resize => {
+                            if let (Some(master), Some((rows, cols))) = (&terminal, terminal::window_size()) {
+                                pty::set_window_size(master, rows, cols);
+                                if let Some(recorder) = &recorder {
+                                    recorder.lock().unwrap().resize(rows, cols);
+                                }
+                            }
+                            continue;
+                        }
+                        Route::Swallow => continue,
+                    };
+                    if pid > 0 {
+                        debug!("Forwarding signal {} to {}", kind.as_raw_value(), target);
+                        unsafe { libc::kill(target, kind.as_raw_value()) };
+                    }
+                }
+            }))
+        })
+        .collect();
+    forwarding
+}
+
+/// Close every descriptor above stderr that is not in `keep`, which must be
+/// sorted.
+fn close_fds_except(keep: &[RawFd]) {
+    let mut first = 3;
+    for &fd in keep.iter().filter(|&&fd| fd >= 3) {
+        if fd > first {
+            unsafe { libc::syscall(libc::SYS_close_range, first as libc::c_uint, (fd - 1) as libc::c_uint, 0) };
+        }
+        first = first.max(fd + 1);
+    }
+    unsafe { libc::syscall(libc::SYS_close_range, first as libc::c_uint, libc::c_uint::MAX, 0) };
+}
+
+fn cloexec_pipe() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create pipe");
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn open_path(path: &Path) -> std::io::Result<OwnedFd> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn cloexec_socketpair() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create socket pair");
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Read a command's stdout or stderr to the end, recording it as it comes.
+/// With `echo` it is passed on to that descriptor, otherwise returned.
+fn read_output(
+    fd: OwnedFd,
+    echo: Option<RawFd>,
+    recorder: Option<Arc<Mutex<Recorder>>>,
+) -> JoinHandle<std::io::Result<Vec<u8>>> {
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::from(fd);
+        let mut kept = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match file.read(&mut buf) {
+                Ok(0) => return Ok(kept),
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(recorder) = &recorder {
+                recorder.lock().unwrap().output(&buf[..n]);
+            }
+            match echo {
+                // A closed stdout doesn't stop the recording
+                Some(fd) => {
+                    let _ = pty::write_all(fd, &buf[..n]);
+                }
+                None => kept.extend_from_slice(&buf[..n]),
+            }
+        }
+    })
+}
@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use crate::container::ContainerRuntime;
+use crate::image::ImageManager;
+use crate::info::ContainerInfo;
+use crate::listing;
+use crate::mount::MountManager;
+use crate::exec::{self, ExecSpec};
+use crate::recording::{self, Recording, SessionInfo};
+use crate::session::SessionStore;
+
+/// Concurrency used by `crashcart run` when none is given.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Outcome of running the command in one container.
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetResult {
+    pub target: String,
+    pub name: String,
+    pub pid: Option<u32>,
+    /// Exit code of the command; `None` if it never ran or was killed.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Why the command could not be run (mount or exec failure).
+    pub error: Option<String>,
+}
+
+impl FleetResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && self.exit_code == Some(0)
+    }
+}
+
+/// Captured output of one command run.
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    pub pid: u32,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Mount the image into every target, run `spec` there with its output
+/// captured and unmount again, with at most `concurrency` targets in
+/// flight. Each target is held in `sessions` while its command runs, and
+/// the image stays mounted where it already was or where sessions or other
+/// crashcarts still use it. With `record_to`, each target's run is
+/// recorded in a file of its own there. Results come back in target order.
+pub async fn run(
+    targets: Vec<(ContainerRuntime, ContainerInfo)>,
+    spec: &ExecSpec,
+    image_manager: &mut ImageManager,
+    sessions: &SessionStore,
+    concurrency: usize,
+    record_to: Option<&Path>,
+) -> Result<Vec<FleetResult>> {
+    if spec.argv.is_empty() {
+        return Err(anyhow!("Fleet mode needs a command to run"));
+    }
+
+    // One loop device serves every mount
+    image_manager.verify_image()?;
+    let image_digest = match record_to {
+        Some(_) => Some(image_manager.digest().await?),
+        None => None,
+    };
+    let record_to = record_to.map(Path::to_path_buf);
+    let loop_device = Arc::new(image_manager.setup_loop_device().await?);
+    let spec = Arc::new(spec.clone());
+    let sessions = Arc::new(sessions.clone());
+    info!("Running {:?} in {} containers", spec.argv, targets.len());
+
+    let results = run_each(targets, concurrency, move |runtime| {
+        let loop_device = loop_device.clone();
+        let spec = spec.clone();
+        let record_to = record_to.clone();
+        let image_digest = image_digest.clone();
+        let sessions = sessions.clone();
+        async move {
+            let pid = runtime.get_pid().await?;
+            let mount_manager = MountManager::new();
+            // Another session's mount stays for that session to remove
+            let lock = sessions.lock_images().await?;
+            let hold = sessions.hold(pid)?;
+            let mounted_before = mount_manager.is_mounted_in(pid).unwrap_or(false);
+            if !mounted_before {
+                mount_manager.mount_loop_device(pid, &loop_device).await?;
+            }
+            drop(lock);
+
+            let mut spec = (*spec)
+                .clone()
+                .env("CRASHCART_TARGET_PID", &pid.to_string())
+                .env("CONTAINER_ROOT", mount_manager.container_root(pid));
+            spec.cwd = Some(format!("/proc/{}/cwd", pid).into());
+            if let Some(dir) = &record_to {
+                let session = SessionInfo {
+                    target: runtime.to_string(),
+                    runtime: runtime.runtime_name().to_string(),
+                    user: recording::operator(),
+                    image_digest,
+                    command: spec.argv.clone(),
+                };
+                spec.recording = Some(Recording::in_dir(dir, session, pid));
+            }
+            let output = exec::output(pid, &spec).await;
+            let lock = sessions.lock_images().await;
+            drop(hold);
+            match lock {
+                Ok(_lock) if !mounted_before && !sessions.image_in_use(pid) => mount_manager.unmount_from(pid).await,
+                Ok(_) => debug!("Leaving crashcart mounted in PID {}", pid),
+                Err(e) => warn!("Leaving crashcart mounted in PID {}: {:#}", pid, e),
+            }
+
+            let output = output?;
+            Ok(CommandOutput {
+                pid,
+                exit_code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            })
+        }
+    })
+    .await;
+
+    image_manager.cleanup_loop_device().await?;
+    Ok(results)
+}
+
+/// Run `f` for every target with at most `concurrency` running at once and
+/// collect one [`FleetResult`] per target, in target order.
+pub async fn run_each<F, Fut>(
+    targets: Vec<(ContainerRuntime, ContainerInfo)>,
+    concurrency: usize,
+    f: F,
+) -> Vec<FleetResult>
+where
+    F: Fn(ContainerRuntime) -> Fut,
+    Fut: Future<Output = Result<CommandOutput>> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    // Which target each task runs, to report those that panic
+    let mut spawned = HashMap::new();
+
+    for (index, (runtime, info)) in targets.into_iter().enumerate() {
+        let target = runtime.to_string();
+        let name = if info.name.is_empty() { info.id.clone() } else { info.name.clone() };
+        let semaphore = semaphore.clone();
+        let work = f(runtime);
+        let failed = FleetResult {
+            target: target.clone(),
+            name: name.clone(),
+            pid: info.pid,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: None,
+        };
+
+        let handle = tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            debug!("Running fleet command in {}", target);
+
+            let result = match work.await {
+                Ok(output) => FleetResult {
+                    target,
+                    name,
+                    pid: Some(output.pid),
+                    exit_code: output.exit_code,
+                    stdout: output.stdout,
+                    stderr: output.stderr,
+                    error: None,
+                },
+                Err(e) => FleetResult {
+                    target,
+                    name,
+                    pid: info.pid,
+                    exit_code: None,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    error: Some(format!("{:#}", e)),
+                },
+            };
+            (index, result)
+        });
+        spawned.insert(handle.id(), (index, failed));
+    }
+
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next_with_id().await {
+        match joined {
+            Ok((_, result)) => results.push(result),
+            Err(e) => {
+                warn!("Fleet task failed: {}", e);
+                if let Some((index, mut failed)) = spawned.remove(&e.id()) {
+                    failed.error = Some(format!("Fleet task failed: {}", e));
+                    results.push((index, failed));
+                }
+            }
+        }
+    }
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Format results as a table. Each output line gets its own row under
+/// OUTPUT, stderr lines marked `[stderr]`.
+pub fn table_rows(results: &[FleetResult]) -> Vec<String> {
+    const HEADER: [&str; 5] = ["TARGET", "NAME", "PID", "EXIT", "OUTPUT"];
+
+    let mut rows: Vec<[String; 5]> = vec![HEADER.map(str::to_string)];
+    for result in results {
+        let mut output: Vec<String> = result.stdout.lines().map(str::to_string).collect();
+        output.extend(result.stderr.lines().map(|l| format!("[stderr] {}", l)));
+        if let Some(error) = &result.error {
+            output.push(format!("[error] {}", error));
+        }
+
+        let mut output = output.into_iter();
+        rows.push([
+            result.target.clone(),
+            result.name.clone(),
+            result.pid.map(|p| p.to_string()).unwrap_or_else(|| "-".to_string()),
+            result.exit_code.map(|c| c.to_string()).unwrap_or_else(|| "-".to_string()),
+            output.next().unwrap_or_default(),
+        ]);
+        for line in output {
+            rows.push([String::new(), String::new(), String::new(), String::new(), line]);
+        }
+    }
+
+    listing::format_table(&rows)
+}
@@ -0,0 +1,252 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tracing::debug;
+
+/// Minimal HTTP/1.1 client for daemons that listen on a unix socket
+/// (Docker, LXD, Incus).
+#[derive(Debug, Clone)]
+pub struct UnixHttpClient {
+    socket: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).context("Failed to parse JSON response body")
+    }
+
+    /// Error message returned by the daemon, falling back to the status code.
+    pub fn error_message(&self) -> String {
+        serde_json::from_slice::<Value>(&self.body)
+            .ok()
+            .and_then(|v| {
+                // Docker uses `message`, LXD and Incus use `error`
+                let message = v.get("message").or_else(|| v.get("error"))?;
+                message.as_str().map(str::to_string)
+            })
+            .unwrap_or_else(|| format!("HTTP status {}", self.status))
+    }
+}
+
+/// Percent-encode `value` for use as one segment of a request path, so
+/// names can't add segments or a query.
+pub fn path_segment(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// A connection that was switched away from HTTP (e.g. Docker's hijacked
+/// attach stream). `buffered` holds bytes read past the response head.
+pub struct UpgradedStream {
+    pub response: HttpResponse,
+    pub buffered: Vec<u8>,
+    pub stream: UnixStream,
+}
+
+impl UnixHttpClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub async fn get(&self, path: &str) -> Result<HttpResponse> {
+        self.request("GET", path, None).await
+    }
+
+    pub async fn post(&self, path: &str, body: Option<&Value>) -> Result<HttpResponse> {
+        self.request("POST", path, body).await
+    }
+
+    pub async fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<HttpResponse> {
+        let mut stream = self.connect().await?;
+        self.send_request(&mut stream, method, path, body, &[("Connection", "close")])
+            .await?;
+
+        let (mut response, mut rest) = read_response_head(&mut stream).await?;
+        stream
+            .read_to_end(&mut rest)
+            .await
+            .context("Failed to read HTTP response body")?;
+
+        response.body = if response
+            .header("Transfer-Encoding")
+            .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+        {
+            decode_chunked(&rest)?
+        } else if let Some(len) = response.header("Content-Length") {
+            let len: usize = len.trim().parse().context("Invalid Content-Length")?;
+            rest.truncate(len);
+            rest
+        } else {
+            rest
+        };
+
+        debug!("{} {} -> {}", method, path, response.status);
+        Ok(response)
+    }
+
+    /// Send a request asking the daemon to hijack the connection and return
+    /// the raw stream once the response head has been read.
+    pub async fn upgrade(&self, method: &str, path: &str, body: Option<&Value>) -> Result<UpgradedStream> {
+        let mut stream = self.connect().await?;
+        self.send_request(
+            &mut stream,
+            method,
+            path,
+            body,
+            &[("Connection", "Upgrade"), ("Upgrade", "tcp")],
+        )
+        .await?;
+
+        let (response, buffered) = read_response_head(&mut stream).await?;
+        debug!("{} {} (upgrade) -> {}", method, path, response.status);
+
+        Ok(UpgradedStream {
+            response,
+            buffered,
+            stream,
+        })
+    }
+
+    async fn connect(&self) -> Result<UnixStream> {
+        UnixStream::connect(&self.socket)
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.socket.display()))
+    }
+
+    async fn send_request(
+        &self,
+        stream: &mut UnixStream,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<()> {
+        let body = match body {
+            Some(value) => serde_json::to_vec(value)?,
+            None => Vec::new(),
+        };
+
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        for (key, value) in extra_headers {
+            request.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        if !body.is_empty() {
+            request.push_str("Content-Type: application/json\r\n");
+        }
+        request.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(&body).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Read the status line and headers, returning any bytes that were read
+/// past the end of the head.
+async fn read_response_head(stream: &mut UnixStream) -> Result<(HttpResponse, Vec<u8>)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let head_end = loop {
+        if let Some(pos) = find_subsequence(&buffer, b"\r\n\r\n") {
+            break pos;
+        }
+        let n = stream
+            .read(&mut chunk)
+            .await
+            .context("Failed to read HTTP response")?;
+        if n == 0 {
+            return Err(anyhow!("Connection closed before HTTP response head"));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    let rest = buffer[head_end + 4..].to_vec();
+
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("Malformed HTTP status line: {}", status_line))?;
+
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    Ok((
+        HttpResponse {
+            status,
+            headers,
+            body: Vec::new(),
+        },
+        rest,
+    ))
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let line_end = find_subsequence(data, b"\r\n")
+            .ok_or_else(|| anyhow!("Truncated chunked response"))?;
+        let size_str = String::from_utf8_lossy(&data[..line_end]);
+        let size_str = size_str.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_str, 16)
+            .with_context(|| format!("Invalid chunk size: {}", size_str))?;
+        data = &data[line_end + 2..];
+
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size {
+            return Err(anyhow!("Truncated chunked response"));
+        }
+
+        body.extend_from_slice(&data[..size]);
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+}
+
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
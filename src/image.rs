@@ -1,4 +1,5 @@
 use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
@@ -102,10 +103,33 @@ impl ImageManager {
         self.loop_device.as_deref()
     }
 
+    /// Hand the loop device over to another process, which becomes
+    /// responsible for detaching it.
+    pub fn take_loop_device(&mut self) -> Option<String> {
+        self.loop_device.take()
+    }
+
+    /// Take responsibility for a loop device set up by another process.
+    pub fn adopt_loop_device(&mut self, device: String) {
+        self.loop_device = Some(device);
+    }
+
     pub fn image_path(&self) -> &Path {
         &self.image_path
     }
 
+    /// SHA-256 of the image file, as `sha256:<hex>`.
+    pub async fn digest(&self) -> Result<String> {
+        let path = self.image_path.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut file = File::open(&path).context("Failed to open image file")?;
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut file, &mut hasher).context("Failed to read image file")?;
+            Ok(format!("sha256:{:x}", hasher.finalize()))
+        })
+        .await?
+    }
+
     /// Verify the image is a valid filesystem image
     pub fn verify_image(&self) -> Result<()> {
         let mut file = File::open(&self.image_path)
@@ -129,11 +153,9 @@ impl ImageManager {
             .context("Failed to seek to ext magic")?;
         
         let mut magic = [0u8; 2];
-        if file.read_exact(&mut magic).is_ok() {
-            if magic == [0x53, 0xEF] {
-                debug!("Detected ext2/3/4 filesystem in image");
-                return Ok(());
-            }
+        if file.read_exact(&mut magic).is_ok() && magic == [0x53, 0xEF] {
+            debug!("Detected ext2/3/4 filesystem in image");
+            return Ok(());
         }
 
         // If not ext, assume it's a tar.gz or other archive format
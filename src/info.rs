@@ -0,0 +1,386 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Capability names indexed by capability number, as in `capability.h`.
+pub const CAPABILITY_NAMES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Names of the capabilities set in `mask` (a `CapEff:`-style bitmask).
+pub fn capability_names(mask: u64) -> Vec<String> {
+    CAPABILITY_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// One line of a user namespace `uid_map`/`gid_map`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdMap {
+    pub container_id: u32,
+    pub host_id: u32,
+    pub size: u32,
+}
+
+impl IdMap {
+    /// The identity map of the initial user namespace.
+    pub fn is_identity(maps: &[IdMap]) -> bool {
+        matches!(maps, [IdMap { container_id: 0, host_id: 0, size: u32::MAX }])
+    }
+
+    /// Host ID that `id` inside the namespace maps to.
+    pub fn to_host(maps: &[IdMap], id: u32) -> Option<u32> {
+        maps.iter()
+            .find(|m| id >= m.container_id && id - m.container_id < m.size)
+            .map(|m| m.host_id + (id - m.container_id))
+    }
+
+    /// ID inside the namespace that `host_id` maps to.
+    pub fn from_host(maps: &[IdMap], host_id: u32) -> Option<u32> {
+        maps.iter()
+            .find(|m| host_id >= m.host_id && host_id - m.host_id < m.size)
+            .map(|m| m.container_id + (host_id - m.host_id))
+    }
+}
+
+/// Parse the contents of `/proc/<pid>/uid_map` or `gid_map`.
+pub fn parse_id_map(contents: &str) -> Vec<IdMap> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().map(|f| f.parse().ok());
+            Some(IdMap {
+                container_id: fields.next()??,
+                host_id: fields.next()??,
+                size: fields.next()??,
+            })
+        })
+        .collect()
+}
+
+/// Runtime-independent view of a container, parsed from each backend's
+/// inspect output and completed from `/proc/<pid>` where the runtime is
+/// silent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContainerInfo {
+    pub runtime: String,
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub labels: BTreeMap<String, String>,
+    pub state: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub pid: Option<u32>,
+    /// The container's root filesystem as seen from the host (the overlay
+    /// merged dir, or `/proc/<pid>/root`).
+    pub rootfs: Option<PathBuf>,
+    pub uid_map: Vec<IdMap>,
+    pub gid_map: Vec<IdMap>,
+    pub privileged: bool,
+    /// Effective capabilities of the container's init process.
+    pub capabilities: Vec<String>,
+    /// The runtime's profile name when known (`default`, `unconfined`, a
+    /// path), otherwise `filter` when the kernel reports one is loaded.
+    pub seccomp_profile: Option<String>,
+    pub apparmor_profile: Option<String>,
+    pub selinux_label: Option<String>,
+    pub cgroup_path: Option<String>,
+}
+
+impl ContainerInfo {
+    /// Info for a bare host process, taken entirely from `/proc`.
+    pub fn for_pid(pid: u32) -> Self {
+        let mut info = Self {
+            runtime: "pid".to_string(),
+            id: pid.to_string(),
+            name: pid.to_string(),
+            state: "running".to_string(),
+            ..Default::default()
+        };
+        info.complete_from_proc(Path::new("/proc"), pid);
+        info
+    }
+
+    /// Parse `docker inspect` / `podman inspect` output (the two share
+    /// most of their layout).
+    pub fn from_docker_inspect(runtime: &str, inspect: &Value) -> Self {
+        let str_at = |pointer: &str| {
+            inspect
+                .pointer(pointer)
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+
+        let security_opts: Vec<&str> = inspect
+            .pointer("/HostConfig/SecurityOpt")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+            .collect();
+        let security_opt = |key: &str| {
+            security_opts.iter().find_map(|opt| {
+                opt.strip_prefix(key)
+                    .and_then(|rest| rest.strip_prefix('=').or_else(|| rest.strip_prefix(':')))
+                    .map(str::to_string)
+            })
+        };
+
+        let privileged = inspect
+            .pointer("/HostConfig/Privileged")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        // Both runtimes apply their default seccomp profile unless told otherwise
+        let seccomp_profile = security_opt("seccomp").or_else(|| {
+            Some(if privileged { "unconfined" } else { "default" }.to_string())
+        });
+
+        Self {
+            runtime: runtime.to_string(),
+            id: str_at("/Id").unwrap_or_default(),
+            name: str_at("/Name")
+                .map(|n| n.trim_start_matches('/').to_string())
+                .unwrap_or_default(),
+            image: str_at("/ImageName")
+                .or_else(|| str_at("/Config/Image"))
+                .unwrap_or_default(),
+            labels: string_map(inspect.pointer("/Config/Labels")),
+            state: str_at("/State/Status").unwrap_or_default(),
+            started_at: str_at("/State/StartedAt").and_then(|s| parse_timestamp(&s)),
+            pid: inspect
+                .pointer("/State/Pid")
+                .and_then(|v| v.as_u64())
+                .filter(|pid| *pid > 0)
+                .map(|pid| pid as u32),
+            rootfs: str_at("/GraphDriver/Data/MergedDir").map(PathBuf::from),
+            privileged,
+            capabilities: inspect
+                .get("EffectiveCaps")
+                .and_then(|v| v.as_array())
+                .map(|caps| caps.iter().filter_map(|c| c.as_str().map(str::to_string)).collect())
+                .unwrap_or_default(),
+            seccomp_profile,
+            apparmor_profile: str_at("/AppArmorProfile"),
+            selinux_label: str_at("/ProcessLabel"),
+            cgroup_path: str_at("/State/CgroupPath"),
+            ..Default::default()
+        }
+    }
+
+    /// Fill fields still unset from an OCI runtime spec (`config.json`).
+    pub fn apply_oci_spec(&mut self, spec: &Value) {
+        let str_at = |pointer: &str| {
+            spec.pointer(pointer)
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+
+        if self.rootfs.is_none() {
+            // Relative roots are relative to the bundle, which we don't know
+            self.rootfs = str_at("/root/path")
+                .map(PathBuf::from)
+                .filter(|p| p.is_absolute());
+        }
+        if self.uid_map.is_empty() {
+            self.uid_map = oci_id_map(spec.pointer("/linux/uidMappings"));
+        }
+        if self.gid_map.is_empty() {
+            self.gid_map = oci_id_map(spec.pointer("/linux/gidMappings"));
+        }
+        if self.capabilities.is_empty() {
+            if let Some(caps) = spec.pointer("/process/capabilities/effective").and_then(|v| v.as_array()) {
+                self.capabilities = caps.iter().filter_map(|c| c.as_str().map(str::to_string)).collect();
+            }
+        }
+        if self.seccomp_profile.is_none() {
+            let filtered = spec.pointer("/linux/seccomp").is_some_and(|s| !s.is_null());
+            self.seccomp_profile = Some(if filtered { "filter" } else { "unconfined" }.to_string());
+        }
+        self.apparmor_profile = self.apparmor_profile.take().or_else(|| str_at("/process/apparmorProfile"));
+        self.selinux_label = self.selinux_label.take().or_else(|| str_at("/process/selinuxLabel"));
+        self.cgroup_path = self.cgroup_path.take().or_else(|| str_at("/linux/cgroupsPath"));
+    }
+
+    /// Fill fields still unset from what the kernel reports for `pid`.
+    pub fn complete_from_proc(&mut self, proc_root: &Path, pid: u32) {
+        let dir = proc_root.join(pid.to_string());
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+
+        self.pid.get_or_insert(pid);
+        if self.rootfs.is_none() {
+            self.rootfs = Some(dir.join("root"));
+        }
+        if self.uid_map.is_empty() {
+            self.uid_map = read("uid_map").map(|m| parse_id_map(&m)).unwrap_or_default();
+        }
+        if self.gid_map.is_empty() {
+            self.gid_map = read("gid_map").map(|m| parse_id_map(&m)).unwrap_or_default();
+        }
+
+        if self.started_at.is_none() {
+            self.started_at = read("stat").and_then(|stat| process_start_time(proc_root, &stat));
+        }
+
+        let status = read("status").unwrap_or_default();
+        let status_field = |key: &str| {
+            status
+                .lines()
+                .find_map(|l| l.strip_prefix(key))
+                .map(str::trim)
+        };
+        if self.capabilities.is_empty() {
+            if let Some(mask) = status_field("CapEff:").and_then(|m| u64::from_str_radix(m, 16).ok()) {
+                self.capabilities = capability_names(mask);
+            }
+        }
+        if self.seccomp_profile.is_none() {
+            self.seccomp_profile = match status_field("Seccomp:") {
+                Some("0") => Some("unconfined".to_string()),
+                Some("1") => Some("strict".to_string()),
+                Some("2") => Some("filter".to_string()),
+                _ => None,
+            };
+        }
+
+        if self.apparmor_profile.is_none() {
+            self.apparmor_profile = read("attr/apparmor/current")
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty() && p != "unconfined");
+        }
+        if self.cgroup_path.is_none() {
+            self.cgroup_path = read("cgroup").and_then(|c| cgroup_path(&c));
+        }
+    }
+}
+
+/// The process's cgroup: the unified (v2) entry, or the v1 systemd one.
+fn cgroup_path(contents: &str) -> Option<String> {
+    let entries: Vec<(&str, &str)> = contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let _id = fields.next()?;
+            Some((fields.next()?, fields.next()?))
+        })
+        .collect();
+
+    entries
+        .iter()
+        .find(|(controllers, _)| controllers.is_empty())
+        .or_else(|| entries.iter().find(|(controllers, _)| *controllers == "name=systemd"))
+        .map(|(_, path)| path.to_string())
+}
+
+/// Start time of a process in clock ticks since boot, from field 22 of its
+/// `stat` line. Together with the PID this identifies one process even
+/// after the PID is reused.
+pub(crate) fn start_ticks(stat: &str) -> Option<u64> {
+    // The command name may contain spaces, so count fields after its `)`
+    stat.rsplit_once(')')?.1.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// [`start_ticks`] of a running process.
+pub(crate) fn process_start_ticks(proc_root: &Path, pid: u32) -> Option<u64> {
+    start_ticks(&fs::read_to_string(proc_root.join(pid.to_string()).join("stat")).ok()?)
+}
+
+/// Wall-clock start time of a process from its `stat` line.
+fn process_start_time(proc_root: &Path, stat: &str) -> Option<DateTime<Utc>> {
+    let ticks = start_ticks(stat)? as i64;
+    let boot_time: i64 = fs::read_to_string(proc_root.join("stat"))
+        .ok()?
+        .lines()
+        .find_map(|l| l.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_second <= 0 {
+        return None;
+    }
+
+    let millis = boot_time * 1000 + ticks * 1000 / ticks_per_second as i64;
+    DateTime::from_timestamp_millis(millis)
+}
+
+fn oci_id_map(value: Option<&Value>) -> Vec<IdMap> {
+    value
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|m| {
+            Some(IdMap {
+                container_id: m.get("containerID")?.as_u64()? as u32,
+                host_id: m.get("hostID")?.as_u64()? as u32,
+                size: m.get("size")?.as_u64()? as u32,
+            })
+        })
+        .collect()
+}
+
+/// A JSON object of strings (labels, annotations) as a sorted map.
+pub(crate) fn string_map(value: Option<&Value>) -> BTreeMap<String, String> {
+    value
+        .and_then(|v| v.as_object())
+        .into_iter()
+        .flatten()
+        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+        .collect()
+}
+
+/// Parse an RFC 3339 timestamp; runtimes report never-started containers
+/// with the zero time, which maps to `None`.
+pub(crate) fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+        .filter(|t| t.timestamp() > 0)
+}
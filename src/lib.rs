@@ -1,9 +1,42 @@
+pub mod backend;
+pub mod cache;
+pub mod cgroup;
+pub mod config;
 pub mod container;
+pub mod containerd;
+pub mod credentials;
+pub mod cri;
+pub mod docker;
+pub mod exec;
+pub mod fleet;
+pub mod http;
 pub mod image;
+pub mod info;
+pub mod listing;
+pub mod lxc;
 pub mod mount;
 pub mod namespace;
+pub mod plugin;
+pub mod podman;
+pub mod privileges;
+pub mod pty;
+pub mod recording;
+pub mod security;
+pub mod selector;
+pub mod session;
+pub mod systemd;
+pub mod terminal;
 
+pub use backend::{BackendRegistry, ContainerSummary, RuntimeBackend};
+pub use cache::ResolutionCache;
+pub use cgroup::CgroupResolver;
+pub use config::Config;
 pub use container::ContainerRuntime;
+pub use cri::CriClient;
+pub use docker::DockerClient;
 pub use image::ImageManager;
+pub use info::ContainerInfo;
 pub use mount::MountManager;
-pub use namespace::NamespaceManager;
\ No newline at end of file
+pub use namespace::{Namespace, NamespaceManager};
+pub use selector::Selector;
+pub use session::SessionStore;
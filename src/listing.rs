@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use dialoguer::FuzzySelect;
+use serde::Serialize;
+
+use crate::backend::{BackendRegistry, ContainerSummary};
+use crate::mount::MountManager;
+
+/// One row of `crashcart list`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListEntry {
+    #[serde(flatten)]
+    pub container: ContainerSummary,
+    /// Whether the crashcart image is mounted in the container (unknown
+    /// without a PID).
+    pub mounted: Option<bool>,
+}
+
+impl ListEntry {
+    /// A target string that resolves back to exactly this container.
+    pub fn target(&self) -> String {
+        format!("{}://{}", self.container.runtime, self.container.id)
+    }
+}
+
+/// List containers across all available backends in `registry`.
+pub async fn list_entries(registry: &BackendRegistry) -> Vec<ListEntry> {
+    let mount_manager = MountManager::new();
+
+    registry
+        .list_all()
+        .await
+        .into_iter()
+        .map(|container| ListEntry {
+            mounted: container.pid.and_then(|pid| mount_manager.is_mounted_in(pid).ok()),
+            container,
+        })
+        .collect()
+}
+
+/// Format `entries` as aligned text rows, header first.
+pub fn table_rows(entries: &[ListEntry]) -> Vec<String> {
+    const HEADER: [&str; 6] = ["RUNTIME", "ID", "NAME", "IMAGE", "PID", "MOUNTED"];
+
+    let mut rows: Vec<[String; 6]> = vec![HEADER.map(str::to_string)];
+    for entry in entries {
+        let c = &entry.container;
+        rows.push([
+            c.runtime.clone(),
+            short_id(&c.id),
+            c.name.clone(),
+            c.image.clone(),
+            c.pid.map(|p| p.to_string()).unwrap_or_else(|| "-".to_string()),
+            match entry.mounted {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "-",
+            }
+            .to_string(),
+        ]);
+    }
+
+    format_table(&rows)
+}
+
+/// Align `rows` into columns two spaces apart, each as wide as its widest
+/// cell, with trailing blanks trimmed.
+pub fn format_table<const N: usize>(rows: &[[String; N]]) -> Vec<String> {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+/// Hex container IDs are shortened to 12 characters like `docker ps` does;
+/// other IDs (pod paths, unit names) are shown in full.
+fn short_id(id: &str) -> String {
+    if id.len() > 12 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+        id[..12].to_string()
+    } else {
+        id.to_string()
+    }
+}
+
+/// Let the user fuzzy-search `entries` on the terminal. Returns the target
+/// of the chosen container, or `None` if the picker was cancelled.
+pub fn pick(entries: &[ListEntry]) -> Result<Option<String>> {
+    let rows = table_rows(entries);
+    let choice = FuzzySelect::new()
+        .with_prompt(format!("Select a container\n  {}", rows[0]))
+        .items(&rows[1..])
+        .default(0)
+        .interact_opt()
+        .context("Container picker failed")?;
+
+    Ok(choice.map(|index| entries[index].target()))
+}
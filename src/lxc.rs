@@ -0,0 +1,399 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+use crate::cgroup::{CgroupTree, CGROUP_ROOT};
+use crate::http::{self, UnixHttpClient};
+use crate::info::{parse_timestamp, ContainerInfo};
+
+/// Runtime directory of classic LXC; its presence means LXC is in use.
+const LXC_RUN_DIR: &str = "/run/lxc";
+
+const LXD_SOCKETS: &[&str] = &[
+    "/var/snap/lxd/common/lxd/unix.socket",
+    "/var/lib/lxd/unix.socket",
+];
+
+const INCUS_SOCKETS: &[&str] = &["/var/lib/incus/unix.socket"];
+
+/// Classic LXC containers (`lxc://name`), resolved to their init process
+/// through the container's payload cgroup, or `lxc-info` when the cgroup
+/// cannot be found.
+#[derive(Clone)]
+pub struct LxcBackend {
+    tree: CgroupTree,
+    run_dir: PathBuf,
+}
+
+impl Default for LxcBackend {
+    fn default() -> Self {
+        Self::with_roots(CGROUP_ROOT, "/proc", LXC_RUN_DIR)
+    }
+}
+
+impl LxcBackend {
+    pub fn with_roots(
+        cgroup_root: impl Into<PathBuf>,
+        proc_root: impl Into<PathBuf>,
+        run_dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            tree: CgroupTree::new(cgroup_root.into(), proc_root.into()),
+            run_dir: run_dir.into(),
+        }
+    }
+
+    fn payload_dir(&self, name: &str) -> Option<PathBuf> {
+        // LXC 4+ uses `lxc.payload.<name>`, older releases nest the
+        // container under `lxc.payload/` or `lxc/`
+        let candidates = [
+            format!("lxc.payload.{}", name),
+            format!("lxc.payload/{}", name),
+            format!("lxc/{}", name),
+        ];
+        self.tree.find_relative(&candidates)
+    }
+
+    /// The payload cgroup and its init process, read off the async
+    /// runtime; `None` if there is no payload cgroup.
+    async fn payload_process(&self, name: &str) -> Result<Option<(PathBuf, u32)>> {
+        check_name("LXC container", name)?;
+        let (backend, name) = (self.clone(), name.to_string());
+        tokio::task::spawn_blocking(move || {
+            backend
+                .payload_dir(&name)
+                .map(|dir| backend.tree.init_process(&dir).map(|pid| (dir, pid)))
+                .transpose()
+        })
+        .await?
+    }
+
+    async fn lxc_info_pid(&self, name: &str) -> Result<u32> {
+        let output = Command::new("lxc-info")
+            .args(["-n", name, "-p", "-H"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run lxc-info")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("LXC container {} not found", name));
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("LXC container {} is not running", name))
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for LxcBackend {
+    fn name(&self) -> &str {
+        "lxc"
+    }
+
+    async fn is_available(&self) -> bool {
+        self.run_dir.is_dir() || which::which("lxc-info").is_ok()
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let pid = self.resolve_pid(target).await?;
+        info!("Detected LXC container {} with init PID {}", target, pid);
+        Ok(target.to_string())
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        let pid = match self.payload_process(id).await? {
+            Some((_, pid)) => pid,
+            None => self.lxc_info_pid(id).await?,
+        };
+        debug!("LXC container {} has init PID {}", id, pid);
+        Ok(pid)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        let (cgroup, pid) = match self.payload_process(id).await? {
+            Some((dir, pid)) => (Some(dir), pid),
+            None => (None, self.lxc_info_pid(id).await?),
+        };
+        Ok(json!({
+            "name": id,
+            "cgroup": cgroup,
+            "pid": pid,
+        }))
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let status = Command::new("lxc-attach")
+            .args(["-n", id, "--"])
+            .args(command)
+            .status()
+            .await
+            .context("Failed to execute lxc-attach")?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        let mut names = Vec::new();
+
+        for hierarchy in self.tree.hierarchies() {
+            let Ok(entries) = fs::read_dir(&hierarchy) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if let Some(name) = file_name.strip_prefix("lxc.payload.") {
+                    names.push(name.to_string());
+                }
+            }
+            for parent in ["lxc.payload", "lxc"] {
+                names.extend(subdirectories(&hierarchy.join(parent)));
+            }
+
+            if !names.is_empty() {
+                break;
+            }
+        }
+
+        let mut summaries = Vec::new();
+        for name in names {
+            summaries.push(ContainerSummary {
+                runtime: self.name().to_string(),
+                pid: self.resolve_pid(&name).await.ok(),
+                id: name.clone(),
+                name,
+                image: String::new(),
+                state: "running".to_string(),
+            });
+        }
+
+        Ok(summaries)
+    }
+}
+
+fn subdirectories(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect()
+}
+
+/// LXD and Incus instances (`lxd://name`, `incus://project/name`), resolved
+/// through the daemon's REST API on its unix socket.
+///
+/// Instance IDs are `[<project>/]<name>`; a bare name refers to the
+/// `default` project.
+pub struct LxdBackend {
+    name: String,
+    cli: String,
+    http: UnixHttpClient,
+}
+
+/// Split a `project/name` reference into an optional project and the name.
+fn split_instance(reference: &str) -> (Option<&str>, &str) {
+    match reference.split_once('/') {
+        Some((project, name)) => (Some(project), name),
+        None => (None, reference),
+    }
+}
+
+/// Refuse names that could reach outside the container's own cgroup
+/// directory or API path.
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(anyhow!("Invalid {} name {:?}", kind, name));
+    }
+    Ok(())
+}
+
+/// The socket named by `$<PREFIX>_SOCKET` or `$<PREFIX>_DIR`, else the first
+/// of `defaults` that exists.
+fn daemon_socket(env_prefix: &str, defaults: &[&str]) -> PathBuf {
+    if let Some(socket) = std::env::var_os(format!("{}_SOCKET", env_prefix)) {
+        return PathBuf::from(socket);
+    }
+    if let Some(dir) = std::env::var_os(format!("{}_DIR", env_prefix)) {
+        return PathBuf::from(dir).join("unix.socket");
+    }
+    defaults
+        .iter()
+        .map(PathBuf::from)
+        .find(|p| p.exists())
+        .unwrap_or_else(|| PathBuf::from(defaults[0]))
+}
+
+impl LxdBackend {
+    /// LXD, at `$LXD_SOCKET`, `$LXD_DIR/unix.socket` or the snap/deb socket.
+    pub fn lxd() -> Self {
+        Self::with_socket("lxd", daemon_socket("LXD", LXD_SOCKETS))
+    }
+
+    /// Incus, at `$INCUS_SOCKET`, `$INCUS_DIR/unix.socket` or the default socket.
+    pub fn incus() -> Self {
+        Self::with_socket("incus", daemon_socket("INCUS", INCUS_SOCKETS))
+    }
+
+    /// A backend called `name` talking to the daemon on `socket`. The
+    /// command line client is `lxc` for LXD and `name` otherwise.
+    pub fn with_socket(name: &str, socket: impl Into<PathBuf>) -> Self {
+        let cli = if name == "lxd" { "lxc" } else { name };
+        Self {
+            name: name.to_string(),
+            cli: cli.to_string(),
+            http: UnixHttpClient::new(socket),
+        }
+    }
+
+    /// GET `path` and return the `metadata` of the daemon's sync response.
+    async fn get(&self, path: &str) -> Result<Value> {
+        let response = self.http.get(path).await?;
+        if !response.is_success() {
+            return Err(anyhow!("{} API request {} failed: {}", self.name, path, response.error_message()));
+        }
+
+        let mut body: Value = response.json()?;
+        Ok(body["metadata"].take())
+    }
+
+    fn instance_path(id: &str, suffix: &str) -> Result<String> {
+        let (project, name) = split_instance(id);
+        check_name("instance", name)?;
+        let path = format!("/1.0/instances/{}{}", http::path_segment(name), suffix);
+        match project {
+            Some(project) => {
+                check_name("project", project)?;
+                Ok(format!("{}?project={}", path, http::path_segment(project)))
+            }
+            None => Ok(path),
+        }
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for LxdBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn is_available(&self) -> bool {
+        self.http.socket().exists() && self.get("/1.0").await.is_ok()
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let pid = self.resolve_pid(target).await?;
+        info!("Detected {} instance {} with init PID {}", self.name, target, pid);
+        Ok(target.to_string())
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        let state = self.get(&Self::instance_path(id, "/state")?).await?;
+        let pid = state.get("pid").and_then(|v| v.as_u64()).unwrap_or(0);
+
+        if pid == 0 {
+            return Err(anyhow!("{} instance {} is not running", self.name, id));
+        }
+
+        debug!("{} instance {} has PID {}", self.name, id, pid);
+        Ok(pid as u32)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        self.get(&Self::instance_path(id, "")?).await
+    }
+
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        let instance = self.inspect(id).await?;
+        let config = |key: &str| {
+            instance
+                .pointer("/expanded_config")
+                .and_then(|c| c.get(key))
+                .and_then(|v| v.as_str())
+        };
+
+        let mut info = ContainerInfo {
+            runtime: self.name.clone(),
+            id: id.to_string(),
+            name: instance["name"].as_str().unwrap_or_default().to_string(),
+            image: config("image.description").unwrap_or_default().to_string(),
+            // User metadata lives in `user.*` config keys
+            labels: instance["expanded_config"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(k, v)| Some((k.strip_prefix("user.")?.to_string(), v.as_str()?.to_string())))
+                .collect(),
+            state: instance["status"].as_str().unwrap_or_default().to_lowercase(),
+            started_at: instance["last_used_at"].as_str().and_then(parse_timestamp),
+            privileged: config("security.privileged") == Some("true"),
+            ..Default::default()
+        };
+        if let Ok(pid) = self.resolve_pid(id).await {
+            info.complete_from_proc(Path::new("/proc"), pid);
+        }
+        Ok(info)
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let (project, name) = split_instance(id);
+        let mut cmd = Command::new(&self.cli);
+        cmd.arg("exec");
+        if let Some(project) = project {
+            cmd.args(["--project", project]);
+        }
+
+        let status = cmd
+            .arg(name)
+            .arg("--")
+            .args(command)
+            .status()
+            .await
+            .with_context(|| format!("Failed to execute {} exec", self.cli))?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        let instances = self.get("/1.0/instances?recursion=2&all-projects=true").await?;
+
+        Ok(instances
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|instance| {
+                let name = instance["name"].as_str().unwrap_or_default().to_string();
+                let id = match instance["project"].as_str() {
+                    Some(project) if project != "default" => format!("{}/{}", project, name),
+                    _ => name.clone(),
+                };
+
+                ContainerSummary {
+                    runtime: self.name.clone(),
+                    id,
+                    name,
+                    image: instance
+                        .pointer("/config/image.description")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                    state: instance["status"].as_str().unwrap_or_default().to_lowercase(),
+                    pid: instance
+                        .pointer("/state/pid")
+                        .and_then(|v| v.as_u64())
+                        .filter(|pid| *pid > 0)
+                        .map(|pid| pid as u32),
+                }
+            })
+            .collect())
+    }
+}
@@ -1,24 +1,48 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use regex::Regex;
 use std::path::PathBuf;
 use tracing::info;
 
-mod container;
-mod image;
-mod mount;
-mod namespace;
-
-use container::ContainerRuntime;
-use image::ImageManager;
-use mount::MountManager;
+use crashcart::credentials::{Credentials, UserSpec};
+use crashcart::exec::ExecSpec;
+use crashcart::privileges::PrivilegeProfile;
+use crashcart::recording::{self, Cast, Recording, SessionInfo};
+use crashcart::security::SecurityContext;
+use crashcart::session::{ImageHold, Role, SessionRecord};
+use crashcart::{exec, fleet, listing, namespace, session, terminal};
+use crashcart::{
+    BackendRegistry, Config, ContainerRuntime, ImageManager, MountManager, Namespace, ResolutionCache, Selector,
+    SessionStore,
+};
 
 #[derive(Parser)]
 #[command(name = "crashcart")]
 #[command(about = "A modern container debugging tool")]
 #[command(version)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Container ID or process PID to attach to
-    target: String,
+    #[command(subcommand)]
+    subcommand: Option<Commands>,
+
+    /// Verbose logging
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    #[command(flatten)]
+    attach: AttachArgs,
+}
+
+/// What to attach to and how.
+#[derive(Args)]
+struct AttachArgs {
+    /// Container ID or process PID to attach to (picked interactively when
+    /// omitted on a terminal)
+    target: Option<String>,
+
+    /// Pick the target by labels instead (`app=api,env=staging`)
+    #[arg(short = 'l', long, value_name = "SELECTOR")]
+    selector: Option<Selector>,
 
     /// Path to crashcart image file
     #[arg(short, long, default_value = "crashcart.img")]
@@ -36,14 +60,149 @@ struct Cli {
     #[arg(short, long)]
     exec: bool,
 
-    /// Verbose logging
-    #[arg(short, long)]
-    verbose: bool,
+    /// Namespaces of the target to join (default: all of them)
+    #[arg(long = "ns", value_name = "NS,...", value_delimiter = ',', conflicts_with = "exec")]
+    namespaces: Vec<Namespace>,
+
+    /// Namespaces of the target not to join
+    #[arg(long = "no-ns", value_name = "NS,...", value_delimiter = ',', conflicts_with = "exec")]
+    skip_namespaces: Vec<Namespace>,
+
+    /// Join only the target's network namespace, keeping host tools and
+    /// mounts (same as `--ns net`)
+    #[arg(long, conflicts_with_all = ["namespaces", "skip_namespaces", "host_net", "exec"])]
+    net_only: bool,
+
+    /// Join everything but the target's network namespace (same as
+    /// `--no-ns net`)
+    #[arg(long, conflicts_with_all = ["namespaces", "exec"])]
+    host_net: bool,
+
+    /// Run as this user of the target: `uid[:gid]` or `name[:group]`,
+    /// looked up in the target's /etc/passwd and /etc/group
+    #[arg(long, value_name = "USER", conflicts_with = "exec")]
+    user: Option<UserSpec>,
+
+    /// Run with the target process's own user, groups and capabilities
+    #[arg(long, conflicts_with_all = ["user", "exec"])]
+    as_target: bool,
+
+    /// Capabilities the command keeps: observe (ptrace and raw sockets),
+    /// network (observe plus network administration) or full. Defaults to
+    /// observe, or to the target's own capabilities with --as-target
+    #[arg(long, value_name = "PROFILE", conflicts_with = "exec")]
+    privileges: Option<PrivilegeProfile>,
+
+    /// Also adopt the target's seccomp filters, AppArmor profile or
+    /// SELinux label, and its no_new_privs state
+    #[arg(long, conflicts_with = "exec")]
+    match_security: bool,
+
+    /// Record the session as an asciicast v2 file
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["exec", "mount_only", "unmount"])]
+    record: Option<PathBuf>,
+
+    /// Record keystrokes too, including any password typed
+    #[arg(long, requires = "record")]
+    record_input: bool,
+
+    /// Keep the session running under a supervisor after this terminal
+    /// detaches (^P ^Q) or goes away, and let others watch it
+    #[arg(short, long, visible_alias = "share", conflicts_with_all = ["exec", "mount_only", "unmount"])]
+    detach: bool,
+
+    /// Resolve the target from scratch instead of using the cache
+    #[arg(long)]
+    no_cache: bool,
 
     /// Command to run (defaults to interactive bash)
     command: Vec<String>,
 }
 
+#[derive(Subcommand)]
+enum Commands {
+    /// Attach to a container (the same as giving the target directly)
+    Attach(AttachArgs),
+    /// List sessions started with --detach
+    Sessions,
+    /// Attach to a session started with --detach, driving it unless
+    /// someone else does
+    Reattach {
+        /// Session ID, or a unique prefix of it
+        id: String,
+    },
+    /// Watch a session without typing into it
+    Watch {
+        /// Session ID, or a unique prefix of it
+        id: String,
+    },
+    /// Pass control of a session you drive to someone watching it
+    Handoff {
+        /// Session ID, or a unique prefix of it
+        id: String,
+
+        /// Who gets control: `#number` or name, as `crashcart sessions` shows
+        to: String,
+    },
+    /// Run a detached session (started by crashcart itself)
+    #[command(hide = true)]
+    Supervise {
+        #[arg(long, default_value = session::DEFAULT_SESSIONS_DIR)]
+        sessions_dir: PathBuf,
+
+        id: String,
+    },
+    /// List containers across all available runtimes
+    List {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run a command in every container matching a selector
+    Run {
+        /// Label selector picking the containers (`app=api,env=staging`)
+        #[arg(short = 'l', long, value_name = "SELECTOR")]
+        selector: Selector,
+
+        /// Path to crashcart image file
+        #[arg(short, long, default_value = "crashcart.img")]
+        image: PathBuf,
+
+        /// Capabilities the command keeps (observe, network or full)
+        #[arg(long, value_name = "PROFILE", default_value_t)]
+        privileges: PrivilegeProfile,
+
+        /// How many containers to work on at once
+        #[arg(short, long, default_value_t = fleet::DEFAULT_CONCURRENCY)]
+        concurrency: usize,
+
+        /// Print one JSON object per container instead of a table
+        #[arg(long)]
+        json: bool,
+
+        /// Command to run in each container
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Play back a session recorded with --record
+    Replay {
+        /// Recording to play
+        file: PathBuf,
+
+        /// Mask text matching this regex; may be repeated
+        #[arg(long, value_name = "REGEX")]
+        redact: Vec<Regex>,
+
+        /// Playback speed factor
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Longest pause between outputs, in seconds
+        #[arg(long, default_value_t = 2.0)]
+        max_idle: f64,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -52,51 +211,404 @@ async fn main() -> Result<()> {
     let log_level = if cli.verbose { "debug" } else { "info" };
     tracing_subscriber::fmt()
         .with_env_filter(format!("crashcart={}", log_level))
+        .with_writer(std::io::stderr)
         .init();
 
+    let config = Config::load()?;
+    let registry = BackendRegistry::from_config(&config);
+
+    match cli.subcommand {
+        Some(Commands::List { json }) => return list(&registry, json).await,
+        Some(Commands::Run {
+            selector,
+            image,
+            privileges,
+            concurrency,
+            json,
+            command,
+        }) => {
+            let mut spec = namespace::tool_spec(&Namespace::ALL, &command);
+            spec.privileges = privileges;
+            return run(&registry, &config, &selector, &image, concurrency, json, &spec).await;
+        }
+        Some(Commands::Replay {
+            file,
+            redact,
+            speed,
+            max_idle,
+        }) => return replay(&file, &redact, speed, max_idle).await,
+        Some(Commands::Sessions) => return sessions(&config),
+        Some(Commands::Reattach { id }) => return reattach(&config, &id, Role::Drive).await,
+        Some(Commands::Watch { id }) => return reattach(&config, &id, Role::Watch).await,
+        Some(Commands::Handoff { id, to }) => return handoff(&config, &id, &to).await,
+        Some(Commands::Supervise { sessions_dir, id }) => {
+            std::process::exit(SessionStore::new(sessions_dir).supervise(&id).await?)
+        }
+        Some(Commands::Attach(args)) => attach(args, &config, &registry).await,
+        None => attach(cli.attach, &config, &registry).await,
+    }
+}
+
+/// Attach to the target: the default command.
+async fn attach(args: AttachArgs, config: &Config, registry: &BackendRegistry) -> Result<()> {
     info!("Starting crashcart v{}", env!("CARGO_PKG_VERSION"));
 
+    let namespaces = if args.net_only {
+        vec![Namespace::Net]
+    } else {
+        let mut skip = args.skip_namespaces.clone();
+        if args.host_net {
+            skip.push(Namespace::Net);
+        }
+        Namespace::selection(&args.namespaces, &skip)
+    };
+    // The image lives in the target's mount namespace; without it the
+    // host's own tools are used and nothing is mounted
+    let use_image = args.exec || namespaces.contains(&Namespace::Mnt);
+    if !use_image && (args.mount_only || args.unmount) {
+        return Err(anyhow!("Mounting needs the target's mnt namespace"));
+    }
+    if args.exec && config.recording.always {
+        return Err(anyhow!("Sessions must be recorded (see the crashcart config), which --exec can't do"));
+    }
+    let record = args.record.is_some() || (config.recording.always && !args.mount_only && !args.unmount);
+
     // Detect container runtime and get PID
     info!("Detecting container runtime...");
-    let runtime = ContainerRuntime::detect(&cli.target).await?;
+    let mut command = args.command;
+    let runtime = match (&args.selector, args.target) {
+        (Some(selector), target) => {
+            // With a selector there is no target, so the first positional
+            // argument is really the start of the command
+            if let Some(word) = target {
+                command.insert(0, word);
+            }
+            ContainerRuntime::select_with(registry, selector).await?
+        }
+        (None, target) => {
+            let target = match target {
+                Some(target) => target,
+                None => pick_target(registry).await?,
+            };
+            if config.cache.enabled && !args.no_cache {
+                let cache = ResolutionCache::new(&config.cache.dir);
+                ContainerRuntime::detect_cached(registry, &cache, &target).await?
+            } else {
+                ContainerRuntime::detect_with(registry, &target).await?
+            }
+        }
+    };
     let pid = runtime.get_pid().await?;
     
     info!("Target PID: {}", pid);
 
+    let mut spec = namespace::tool_spec(&namespaces, &command).env("CRASHCART_TARGET_PID", &pid.to_string());
+    spec.cwd = Some(PathBuf::from(format!("/proc/{}/cwd", pid)));
+    spec.credentials = if args.as_target {
+        Some(Credentials::of_process(pid)?)
+    } else if let Some(user) = &args.user {
+        Some(Credentials::for_user(&PathBuf::from(format!("/proc/{}/root", pid)), user)?)
+    } else {
+        None
+    };
+    if let Some(credentials) = &spec.credentials {
+        info!("Running as {}:{} (groups {:?})", credentials.uid, credentials.gid, credentials.groups);
+    }
+    spec.privileges = match args.privileges {
+        Some(profile) => profile,
+        // The target's own capability sets are the limit
+        None if args.as_target => PrivilegeProfile::Full,
+        None => PrivilegeProfile::Observe,
+    };
+    info!("Privilege profile: {}", spec.privileges);
+    if args.match_security {
+        spec.security = Some(SecurityContext::of_process(pid).await?);
+    }
+
+    let mut session = SessionInfo {
+        target: runtime.to_string(),
+        runtime: runtime.runtime_name().to_string(),
+        user: recording::operator(),
+        image_digest: None,
+        command: spec.argv.clone(),
+    };
+
+    if !use_image {
+        info!("Joining {:?} of PID {} with host tools", namespaces, pid);
+        spec = spec.env("CONTAINER_ROOT", &format!("/proc/{}/root", pid));
+        if record {
+            spec.recording = Some(recording_for(args.record, args.record_input, config, session, pid)?);
+        }
+        if args.detach {
+            let sessions = SessionStore::new(&config.sessions.dir);
+            let record = start_detached(&sessions, &runtime.to_string(), pid, &spec, None).await?;
+            std::process::exit(follow_detached(&sessions, &record).await?);
+        }
+        std::process::exit(exec::run(pid, &spec).await?);
+    }
+
     info!("Creating image manager...");
-    let image_manager = ImageManager::new(&cli.image)?;
+    let mut image_manager = ImageManager::new(&args.image)?;
     info!("Creating mount manager...");
     let mount_manager = MountManager::new();
+    let sessions = SessionStore::new(&config.sessions.dir);
 
     // Handle unmount-only case
-    if cli.unmount {
+    if args.unmount {
         info!("Unmount-only mode");
-        mount_manager.unmount_with_nsenter(pid, &image_manager).await?;
+        let _lock = sessions.lock_images().await?;
+        let live: Vec<String> = sessions.on_target(pid).into_iter().map(|s| s.id).collect();
+        if !live.is_empty() {
+            return Err(anyhow!("Detached sessions {} still use the image in PID {}", live.join(", "), pid));
+        }
+        if sessions.image_in_use(pid) {
+            return Err(anyhow!("Another crashcart still uses the image in PID {}", pid));
+        }
+        mount_manager.unmount(pid, &mut image_manager).await?;
         info!("Successfully unmounted crashcart from PID {}", pid);
         return Ok(());
     }
 
-    // Mount the image using nsenter approach
+    // A recording that can't be written fails here, before anything is
+    // mounted
+    if record {
+        session.image_digest = Some(image_manager.digest().await?);
+        spec.recording = Some(recording_for(args.record, args.record_input, config, session, pid)?);
+    }
+
+    // Mount the image inside the target's mount namespace, holding it so
+    // sessions ending meanwhile leave it mounted
     info!("Starting mount operation...");
-    mount_manager.mount_with_nsenter(pid, &image_manager).await?;
+    let lock = sessions.lock_images().await?;
+    let hold = sessions.hold(pid)?;
+    mount_manager.mount(pid, &mut image_manager).await?;
+    drop(lock);
     info!("Successfully mounted crashcart image");
 
-    if cli.mount_only {
+    if args.mount_only {
         info!("Mount-only mode: crashcart image is now available at /dev/crashcart");
         return Ok(());
     }
+    spec = spec.env("CONTAINER_ROOT", mount_manager.container_root(pid));
+
+    if args.detach {
+        let image = image_manager.get_loop_device().map(|device| (image_manager.image_path(), device));
+        let started = start_detached(&sessions, &runtime.to_string(), pid, &spec, image).await;
+        let record = match started {
+            Ok(record) => record,
+            Err(e) => {
+                release_image(&sessions, &mount_manager, pid, &mut image_manager, hold).await?;
+                return Err(e);
+            }
+        };
+        // The session keeps the image mounted from here on
+        image_manager.take_loop_device();
+        drop(hold);
+        std::process::exit(follow_detached(&sessions, &record).await?);
+    }
 
     // Execute command
-    let exit_code = if cli.exec {
-        runtime.exec_command(&cli.command).await?
+    let result = if args.exec {
+        runtime.exec_command(&command).await
     } else {
-        namespace::exec_in_namespace(pid, &cli.command, Some(("CRASHCART_TARGET_PID", &pid.to_string()))).await?
+        exec::run(pid, &spec).await
     };
 
-    // Cleanup
-    if !cli.mount_only {
-        mount_manager.unmount_with_nsenter(pid, &image_manager).await?;
-    }
+    // Cleanup, whether or not the command ran
+    let released = release_image(&sessions, &mount_manager, pid, &mut image_manager, hold).await;
+    let exit_code = result?;
+    released?;
 
     std::process::exit(exit_code);
-}
\ No newline at end of file
+}
+
+/// Start the session under a supervisor, on a pty sized like this
+/// terminal. With `image`, the supervisor takes over its loop device.
+async fn start_detached(
+    sessions: &SessionStore,
+    target: &str,
+    pid: u32,
+    spec: &ExecSpec,
+    image: Option<(&std::path::Path, &str)>,
+) -> Result<SessionRecord> {
+    let size = terminal::window_size().unwrap_or((24, 80));
+    let record = sessions.start(target, pid, spec, image, size).await?;
+    info!("Started session {}", record.id);
+    Ok(record)
+}
+
+/// Attach to a just started session when on a terminal, or print its ID.
+async fn follow_detached(sessions: &SessionStore, record: &SessionRecord) -> Result<i32> {
+    if terminal::stdin_is_tty() && terminal::stdout_is_tty() {
+        attach_session(sessions, record, Role::Drive).await
+    } else {
+        println!("{}", record.id);
+        Ok(0)
+    }
+}
+
+/// Let go of `hold` and unmount the image unless detached sessions or
+/// other crashcarts on the target still use it, and detach this process's
+/// loop device either way.
+async fn release_image(
+    sessions: &SessionStore,
+    mount_manager: &MountManager,
+    pid: u32,
+    image_manager: &mut ImageManager,
+    hold: ImageHold,
+) -> Result<()> {
+    let _lock = sessions.lock_images().await?;
+    drop(hold);
+    if !sessions.image_in_use(pid) {
+        mount_manager.unmount(pid, image_manager).await
+    } else {
+        info!("Leaving crashcart mounted for the other sessions in PID {}", pid);
+        image_manager.cleanup_loop_device().await
+    }
+}
+
+/// Attach to a detached session until its command exits, returning the
+/// exit code, or until the user detaches again.
+async fn attach_session(sessions: &SessionStore, record: &SessionRecord, role: Role) -> Result<i32> {
+    match sessions.attach(record, role).await? {
+        Some(code) => Ok(code),
+        None => {
+            eprintln!("Detached from session {} (`crashcart reattach {}` to return)", record.id, record.id);
+            Ok(0)
+        }
+    }
+}
+
+fn sessions(config: &Config) -> Result<()> {
+    let sessions = SessionStore::new(&config.sessions.dir).list();
+    for row in session::table_rows(&sessions, chrono::Utc::now()) {
+        println!("{}", row);
+    }
+    Ok(())
+}
+
+async fn reattach(config: &Config, id: &str, role: Role) -> Result<()> {
+    let sessions = SessionStore::new(&config.sessions.dir);
+    let record = sessions.find(id)?;
+    std::process::exit(attach_session(&sessions, &record, role).await?);
+}
+
+async fn handoff(config: &Config, id: &str, to: &str) -> Result<()> {
+    let sessions = SessionStore::new(&config.sessions.dir);
+    let record = sessions.find(id)?;
+    println!("{}", sessions.handoff(&record, to).await?);
+    Ok(())
+}
+
+async fn list(registry: &BackendRegistry, json: bool) -> Result<()> {
+    let entries = listing::list_entries(registry).await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        for row in listing::table_rows(&entries) {
+            println!("{}", row);
+        }
+    }
+    Ok(())
+}
+
+async fn run(
+    registry: &BackendRegistry,
+    config: &Config,
+    selector: &Selector,
+    image: &std::path::Path,
+    concurrency: usize,
+    json: bool,
+    spec: &ExecSpec,
+) -> Result<()> {
+    let mut image_manager = ImageManager::new(image)?;
+    let targets = ContainerRuntime::select_all_with(registry, selector).await;
+    if targets.is_empty() {
+        return Err(anyhow!("No running container matches selector {}", selector));
+    }
+
+    let sessions = SessionStore::new(&config.sessions.dir);
+    let record_to = config.recording.always.then_some(config.recording.dir.as_path());
+    let results = fleet::run(targets, spec, &mut image_manager, &sessions, concurrency, record_to).await?;
+    if json {
+        for result in &results {
+            println!("{}", serde_json::to_string(result)?);
+        }
+    } else {
+        for row in fleet::table_rows(&results) {
+            println!("{}", row);
+        }
+    }
+
+    if !results.iter().all(|r| r.succeeded()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Where this session is recorded: the `--record` file, or a new file in
+/// the configured directory when recording is enforced. Keystrokes are
+/// recorded with `input` or when the config asks for them. Fails if the
+/// recording could not be created.
+fn recording_for(
+    path: Option<PathBuf>,
+    input: bool,
+    config: &Config,
+    session: SessionInfo,
+    pid: u32,
+) -> Result<Recording> {
+    let mut recording = match path {
+        Some(path) => Recording {
+            path,
+            info: session,
+            input: false,
+        },
+        None => Recording::in_dir(&config.recording.dir, session, pid),
+    };
+    recording.input = input || config.recording.input;
+    recording.check()?;
+    info!("Recording session to {}", recording.path.display());
+    Ok(recording)
+}
+
+async fn replay(file: &std::path::Path, redact: &[Regex], speed: f64, max_idle: f64) -> Result<()> {
+    if speed <= 0.0 || max_idle < 0.0 {
+        return Err(anyhow!("--speed must be positive and --max-idle not negative"));
+    }
+    let mut cast = Cast::load(file)?;
+    cast.redact(redact);
+
+    if let Some(session) = &cast.header.crashcart {
+        let started = cast
+            .header
+            .timestamp
+            .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_else(|| "unknown".to_string());
+        eprintln!(
+            "Session on {} ({}) by {} at {}, command {:?}, image {}",
+            session.target,
+            session.runtime,
+            session.user,
+            started,
+            session.command,
+            session.image_digest.as_deref().unwrap_or("none")
+        );
+    }
+    cast.play(&mut std::io::stdout(), speed, std::time::Duration::from_secs_f64(max_idle)).await
+}
+
+/// Ask for a target with the fuzzy picker when no target was given.
+async fn pick_target(registry: &BackendRegistry) -> Result<String> {
+    if !terminal::stdin_is_tty() {
+        return Err(anyhow!("No target given (run `crashcart list` to see containers)"));
+    }
+
+    let entries = listing::list_entries(registry).await;
+    if entries.is_empty() {
+        return Err(anyhow!("No running containers found"));
+    }
+
+    listing::pick(&entries)?.ok_or_else(|| anyhow!("No container selected"))
+}
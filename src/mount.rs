@@ -1,16 +1,21 @@
 use anyhow::{anyhow, Context, Result};
-use nix::mount::{mount, umount, MsFlags};
+use nix::mount::{mount, umount, umount2, MntFlags, MsFlags};
 use nix::sys::stat::{mknod, Mode, SFlag};
 use std::fs::{create_dir_all, remove_dir_all};
+use std::os::unix::fs::chown;
 use std::path::Path;
 use tracing::{debug, info, warn};
 
 use crate::image::ImageManager;
-use crate::namespace::NamespaceManager;
+use crate::namespace::{MountTable, NamespaceManager, UserNamespace};
 
 const CRASHCART_MOUNT_PATH: &str = "/dev/crashcart";
 const CRASHCART_LOOP_DIR: &str = "/dev/cc-loop";
+/// Where the target's root shows up inside the image, for tools chrooted
+/// into it.
+const TARGET_ROOT_PATH: &str = "/dev/crashcart/target";
 
+#[derive(Default, Clone)]
 pub struct MountManager {
     namespace_manager: NamespaceManager,
 }
@@ -22,173 +27,118 @@ impl MountManager {
         }
     }
 
-    pub async fn mount_with_nsenter(&self, pid: u32, image_manager: &ImageManager) -> Result<()> {
-        info!("Starting mount_with_nsenter for PID {}", pid);
-        
+    /// Mount the crashcart image at `/dev/crashcart` inside the target's
+    /// mount namespace.
+    ///
+    /// The mount sequence runs natively on a thread that has joined
+    /// the target's mount namespace, so the target needs no shell or
+    /// userland binaries of its own.
+    pub async fn mount(&self, pid: u32, image_manager: &mut ImageManager) -> Result<()> {
         // Verify image before mounting
-        info!("Verifying image...");
         image_manager.verify_image()
             .context("Image verification failed")?;
-        info!("Image verification successful");
 
         // Setup loop device
-        info!("Setting up loop device...");
-        let mut image_manager = image_manager.clone();
         let loop_device = image_manager.setup_loop_device().await?;
         info!("Loop device setup successful: {}", loop_device);
 
-        // Use nsenter to execute mount commands in the target namespace
-        info!("Preparing mount script...");
-        let mount_script = format!(
-            r#"#!/bin/bash
-set -euo pipefail
-
-# Check if already mounted
-if mountpoint -q /dev/crashcart 2>/dev/null; then
-    echo "Crashcart already mounted"
-    exit 0
-fi
-
-# Create mount directories
-mkdir -p /dev/cc-loop
-mkdir -p /dev/crashcart
-
-# Mount tmpfs for loop devices if not already mounted
-if ! mountpoint -q /dev/cc-loop 2>/dev/null; then
-    mount -t tmpfs tmpfs /dev/cc-loop
-fi
-
-# Create device node
-mknod /dev/cc-loop/crashcart b 7 {} 2>/dev/null || true
-
-# Mount the filesystem
-mount -t ext4 -o ro /dev/cc-loop/crashcart /dev/crashcart
-
-echo "Successfully mounted crashcart image"
-"#,
-            loop_device.strip_prefix("/dev/loop").unwrap_or("0")
-        );
-
-        // Execute the mount script using nsenter
-        let mut cmd = tokio::process::Command::new("nsenter");
-        cmd.args(["-t", &pid.to_string(), "-m", "--"])
-            .args(["bash", "-c", &mount_script]);
-
-        let output = cmd.output().await
-            .context("Failed to execute mount script with nsenter")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Mount script failed: {}", stderr));
-        }
-
-        info!("Successfully mounted crashcart image using nsenter");
-        Ok(())
+        self.mount_loop_device(pid, &loop_device).await
     }
 
-    pub async fn unmount_with_nsenter(&self, pid: u32, image_manager: &ImageManager) -> Result<()> {
-        // Use nsenter to execute unmount commands in the target namespace
-        let unmount_script = r#"#!/bin/bash
-set -euo pipefail
-
-# Unmount the filesystem
-if mountpoint -q /dev/crashcart 2>/dev/null; then
-    umount /dev/crashcart
-    echo "Unmounted crashcart filesystem"
-fi
-
-# Clean up directories
-rm -rf /dev/crashcart
-
-# Unmount tmpfs if mounted
-if mountpoint -q /dev/cc-loop 2>/dev/null; then
-    umount /dev/cc-loop
-fi
+    /// Mount an already attached loop device at `/dev/crashcart` inside the
+    /// target's mount namespace. Lets several targets share one device.
+    ///
+    /// The mount is made from crashcart's own user namespace, which owns
+    /// (or is an ancestor of) the one of any user-namespaced container, so
+    /// rootless and userns-remapped targets need nothing special. The
+    /// directories created for the mount are handed to the container's
+    /// root so they don't show up as owned by `nobody`.
+    pub async fn mount_loop_device(&self, pid: u32, loop_device: &str) -> Result<()> {
+        let owner = match UserNamespace::read(pid) {
+            Ok(user) if !user.is_initial() => user.host_root(),
+            Ok(_) => None,
+            Err(e) => {
+                debug!("Not checking the user namespace of PID {}: {:#}", pid, e);
+                None
+            }
+        };
+
+        let manager = self.clone();
+        let loop_device = loop_device.to_string();
+        self.namespace_manager
+            .run_in_mount_namespace(pid, move |mounts| {
+                // Check if already mounted
+                if mounts.contains(CRASHCART_MOUNT_PATH)? {
+                    return Ok(());
+                }
 
-rm -rf /dev/cc-loop
+                // Create mount directories
+                manager.setup_mount_directories(mounts)?;
+                if let Some((uid, gid)) = owner {
+                    for dir in [CRASHCART_LOOP_DIR, CRASHCART_MOUNT_PATH] {
+                        chown(dir, Some(uid), Some(gid))
+                            .with_context(|| format!("Failed to hand {} to the container's root", dir))?;
+                    }
+                }
 
-echo "Successfully cleaned up crashcart mount"
-"#;
+                // Create device node in the container's namespace
+                let device_path = format!("{}/crashcart", CRASHCART_LOOP_DIR);
+                manager.create_device_node(&device_path, &loop_device)?;
 
-        // Execute the unmount script using nsenter
-        let mut cmd = tokio::process::Command::new("nsenter");
-        cmd.args(["-t", &pid.to_string(), "-m", "--"])
-            .args(["bash", "-c", unmount_script]);
+                // Mount the filesystem
+                manager.mount_filesystem(&device_path)?;
+                manager.expose_target_root();
+                Ok(())
+            })
+            .await
+            .context("Failed to mount crashcart image in target mount namespace")?;
 
-        let output = cmd.output().await
-            .context("Failed to execute unmount script with nsenter")?;
+        info!("Successfully mounted crashcart image at {}", CRASHCART_MOUNT_PATH);
+        Ok(())
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            warn!("Unmount script failed: {}", stderr);
-        }
+    /// Unmount the crashcart image from the target's mount namespace and
+    /// remove the directories created by [`MountManager::mount`], then
+    /// detach the loop device if `image_manager` attached one.
+    pub async fn unmount(&self, pid: u32, image_manager: &mut ImageManager) -> Result<()> {
+        self.unmount_from(pid).await;
 
         // Clean up loop device
-        let mut image_manager = image_manager.clone();
         image_manager.cleanup_loop_device().await?;
 
-        info!("Successfully unmounted crashcart using nsenter");
+        info!("Successfully unmounted crashcart");
         Ok(())
     }
 
-    pub async fn mount(&self, pid: u32, image_manager: &ImageManager) -> Result<()> {
-        // Verify image before mounting
-        image_manager.verify_image()
-            .context("Image verification failed")?;
-
-        // Setup loop device
-        let mut image_manager = image_manager.clone();
-        let loop_device = image_manager.setup_loop_device().await?;
-
-        // Enter the target container's mount namespace
-        let _guard = self.namespace_manager.enter_mount_namespace(pid)?;
-
-        // Check if already mounted
-        if self.is_mounted(CRASHCART_MOUNT_PATH)? {
-            info!("Crashcart already mounted at {}", CRASHCART_MOUNT_PATH);
-            return Ok(());
-        }
-
-        // Create mount directories
-        self.setup_mount_directories()?;
-
-        // Create device node in the container's namespace
-        let device_path = format!("{}/crashcart", CRASHCART_LOOP_DIR);
-        self.create_device_node(&device_path, &loop_device)?;
-
-        // Mount the filesystem
-        self.mount_filesystem(&device_path)?;
-
-        // Setup the crashcart environment
-        self.setup_crashcart_environment()?;
-
-        info!("Successfully mounted crashcart image at {}", CRASHCART_MOUNT_PATH);
-        Ok(())
-    }
+    /// Remove the crashcart mount and directories from the target's mount
+    /// namespace, leaving the loop device attached. Failures are logged,
+    /// not returned, so cleanup carries on.
+    pub async fn unmount_from(&self, pid: u32) {
+        let manager = self.clone();
+        let result = self
+            .namespace_manager
+            .run_in_mount_namespace(pid, move |mounts| {
+                // Unmount the filesystem, and the target's root bound in it
+                if mounts.contains(TARGET_ROOT_PATH)? {
+                    umount2(TARGET_ROOT_PATH, MntFlags::MNT_DETACH)
+                        .context("Failed to unmount the target's root from the image")?;
+                }
+                if mounts.contains(CRASHCART_MOUNT_PATH)? {
+                    umount(CRASHCART_MOUNT_PATH)
+                        .context("Failed to unmount crashcart filesystem")?;
+                }
 
-    pub async fn unmount(&self, pid: u32, image_manager: &ImageManager) -> Result<()> {
-        // Enter the target container's mount namespace
-        let _guard = self.namespace_manager.enter_mount_namespace(pid)?;
+                // Clean up directories
+                manager.cleanup_mount_directories(mounts)
+            })
+            .await;
 
-        // Unmount the filesystem
-        if self.is_mounted(CRASHCART_MOUNT_PATH)? {
-            umount(CRASHCART_MOUNT_PATH)
-                .context("Failed to unmount crashcart filesystem")?;
-            info!("Unmounted crashcart filesystem");
+        if let Err(e) = result {
+            warn!("Failed to clean up crashcart mount in PID {}: {:#}", pid, e);
         }
-
-        // Clean up directories
-        self.cleanup_mount_directories()?;
-
-        // Clean up loop device
-        let mut image_manager = image_manager.clone();
-        image_manager.cleanup_loop_device().await?;
-
-        info!("Successfully unmounted crashcart");
-        Ok(())
     }
 
-    fn setup_mount_directories(&self) -> Result<()> {
+    fn setup_mount_directories(&self, mounts: &MountTable) -> Result<()> {
         // Create the loop device directory
         create_dir_all(CRASHCART_LOOP_DIR)
             .context("Failed to create loop device directory")?;
@@ -198,7 +148,7 @@ echo "Successfully cleaned up crashcart mount"
             .context("Failed to create mount point")?;
 
         // Mount tmpfs for loop devices (needed for user namespaces)
-        if !self.is_mounted(CRASHCART_LOOP_DIR)? {
+        if !mounts.contains(CRASHCART_LOOP_DIR)? {
             mount(
                 Some("tmpfs"),
                 CRASHCART_LOOP_DIR,
@@ -211,7 +161,7 @@ echo "Successfully cleaned up crashcart mount"
         Ok(())
     }
 
-    fn cleanup_mount_directories(&self) -> Result<()> {
+    fn cleanup_mount_directories(&self, mounts: &MountTable) -> Result<()> {
         // Remove mount point
         if Path::new(CRASHCART_MOUNT_PATH).exists() {
             if let Err(e) = remove_dir_all(CRASHCART_MOUNT_PATH) {
@@ -220,7 +170,7 @@ echo "Successfully cleaned up crashcart mount"
         }
 
         // Unmount and remove loop device directory
-        if self.is_mounted(CRASHCART_LOOP_DIR).unwrap_or(false) {
+        if mounts.contains(CRASHCART_LOOP_DIR).unwrap_or(false) {
             if let Err(e) = umount(CRASHCART_LOOP_DIR) {
                 warn!("Failed to unmount loop device tmpfs: {}", e);
             }
@@ -286,46 +236,46 @@ echo "Successfully cleaned up crashcart mount"
         Err(anyhow!("Failed to mount filesystem with any supported type"))
     }
 
-    fn setup_crashcart_environment(&self) -> Result<()> {
-        use std::fs::write;
-
-        // Create .crashcartrc file
-        let rcfile_path = format!("{}/.crashcartrc", CRASHCART_MOUNT_PATH);
-        let rcfile_content = format!(
-            r#"# Crashcart environment setup
-export PATH="{}:$PATH"
-export PS1="[crashcart] \u@\h:\w\$ "
-echo "Crashcart debugging environment loaded"
-echo "Available tools in {}/bin and {}/sbin"
-"#,
-            format!("{}/bin", CRASHCART_MOUNT_PATH),
-            CRASHCART_MOUNT_PATH,
-            CRASHCART_MOUNT_PATH
-        );
-
-        write(&rcfile_path, rcfile_content)
-            .context("Failed to create .crashcartrc")?;
-
-        debug!("Created crashcart environment file");
-        Ok(())
+    /// Bind the target's root, with everything mounted below it, onto the
+    /// image's `/target` directory. Images built without one are left as
+    /// they are. The bind follows mounts the target makes later but
+    /// doesn't pass ours back. The image mount is made private first, or
+    /// with a shared `/dev` the bind would show up in peer namespaces too.
+    fn expose_target_root(&self) {
+        if !Path::new(TARGET_ROOT_PATH).is_dir() {
+            warn!("The image has no {}, not exposing the target's root", TARGET_ROOT_PATH);
+            return;
+        }
+        let result = mount(None::<&str>, CRASHCART_MOUNT_PATH, None::<&str>, MsFlags::MS_PRIVATE, None::<&str>)
+            .and_then(|_| mount(Some("/"), TARGET_ROOT_PATH, None::<&str>, MsFlags::MS_BIND | MsFlags::MS_REC, None::<&str>))
+            .and_then(|_| mount(None::<&str>, TARGET_ROOT_PATH, None::<&str>, MsFlags::MS_REC | MsFlags::MS_SLAVE, None::<&str>));
+        if let Err(e) = result {
+            warn!("Failed to expose the target's root at {}: {}", TARGET_ROOT_PATH, e);
+        }
     }
 
-    fn is_mounted(&self, path: &str) -> Result<bool> {
-        use std::fs::File;
-        use std::io::{BufRead, BufReader};
-
-        let mounts_file = File::open("/proc/mounts")
-            .context("Failed to open /proc/mounts")?;
+    /// Whether the crashcart image is mounted in `pid`'s mount namespace,
+    /// checked from the host through `/proc/<pid>/mounts`.
+    pub fn is_mounted_in(&self, pid: u32) -> Result<bool> {
+        self.has_mount_in(pid, CRASHCART_MOUNT_PATH)
+    }
 
-        for line in BufReader::new(mounts_file).lines() {
-            let line = line.context("Failed to read /proc/mounts line")?;
-            let fields: Vec<&str> = line.split_whitespace().collect();
-            
-            if fields.len() >= 2 && fields[1] == path {
-                return Ok(true);
-            }
+    /// Where tools in `pid`'s mount namespace find its root: the bind in
+    /// the image when there is one, so the same files are there for tools
+    /// chrooted into it, or else `/`.
+    pub fn container_root(&self, pid: u32) -> &'static str {
+        match self.has_mount_in(pid, TARGET_ROOT_PATH) {
+            Ok(true) => TARGET_ROOT_PATH,
+            _ => "/",
         }
+    }
+
+    fn has_mount_in(&self, pid: u32, path: &str) -> Result<bool> {
+        let mounts = std::fs::read_to_string(format!("/proc/{}/mounts", pid))
+            .with_context(|| format!("Failed to read mounts of PID {}", pid))?;
 
-        Ok(false)
+        Ok(mounts
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some(path)))
     }
-}
\ No newline at end of file
+}
@@ -1,11 +1,21 @@
 use anyhow::{anyhow, Context, Result};
-use nix::fcntl::{open, OFlag};
-use nix::sched::{setns, CloneFlags};
+use nix::fcntl::{open, openat, OFlag};
+use nix::sched::{setns, unshare, CloneFlags};
 use nix::sys::stat::Mode;
-use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
-use tokio::process::Command;
-use tracing::{debug, info};
-
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::process::Output;
+use std::str::FromStr;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use crate::exec::{self, ExecSpec};
+use crate::info::{parse_id_map, IdMap};
+
+#[derive(Default, Clone)]
 pub struct NamespaceManager;
 
 impl NamespaceManager {
@@ -13,6 +23,36 @@ impl NamespaceManager {
         Self
     }
 
+    /// Run `f` on a new thread that has joined the mount namespace of the
+    /// target process.
+    ///
+    /// `setns(CLONE_NEWNS)` is refused while the filesystem attributes are
+    /// shared with other threads, so the thread unshares them first and the
+    /// rest of crashcart keeps its own root and working directory. The
+    /// thread exits once `f` returns, taking the namespace with it.
+    pub async fn run_in_mount_namespace<F>(&self, pid: u32, f: F) -> Result<()>
+    where
+        F: FnOnce(&MountTable) -> Result<()> + Send + 'static,
+    {
+        let target_ns = self.open_target_namespace(pid, "mnt")?;
+        let (tx, rx) = oneshot::channel();
+        std::thread::Builder::new()
+            .name(format!("mntns-{}", pid))
+            .spawn(move || {
+                let result = MountTable::open().and_then(|mounts| {
+                    unshare(CloneFlags::CLONE_FS).context("Failed to unshare filesystem attributes")?;
+                    setns(&target_ns, CloneFlags::CLONE_NEWNS).context("Failed to enter mount namespace")?;
+                    f(&mounts)
+                });
+                let _ = tx.send(result);
+            })
+            .context("Failed to start mount namespace thread")?;
+
+        rx.await.map_err(|_| anyhow!("Mount namespace thread for PID {} panicked", pid))??;
+        debug!("Mount namespace thread for PID {} succeeded", pid);
+        Ok(())
+    }
+
     /// Enter the mount namespace of the target process
     pub fn enter_mount_namespace(&self, pid: u32) -> Result<NamespaceGuard> {
         let current_ns = self.open_current_namespace("mnt")?;
@@ -56,6 +96,34 @@ impl NamespaceManager {
     }
 }
 
+/// The mount table of the calling thread, read through crashcart's own
+/// `/proc`: the target's may belong to a PID namespace the thread is not
+/// in, and `/proc/self` follows the main thread rather than the caller.
+pub struct MountTable {
+    proc: OwnedFd,
+}
+
+impl MountTable {
+    fn open() -> Result<Self> {
+        let proc = open("/proc", OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC, Mode::empty())
+            .context("Failed to open /proc")?;
+        Ok(Self {
+            proc: unsafe { OwnedFd::from_raw_fd(proc) },
+        })
+    }
+
+    /// Whether something is mounted at `path`.
+    pub fn contains(&self, path: &str) -> Result<bool> {
+        let fd = openat(self.proc.as_raw_fd(), "thread-self/mounts", OFlag::O_RDONLY | OFlag::O_CLOEXEC, Mode::empty())
+            .context("Failed to open the mount table")?;
+        let mut mounts = String::new();
+        File::from(unsafe { OwnedFd::from_raw_fd(fd) })
+            .read_to_string(&mut mounts)
+            .context("Failed to read the mount table")?;
+        Ok(mounts.lines().any(|line| line.split_whitespace().nth(1) == Some(path)))
+    }
+}
+
 pub struct NamespaceGuard {
     original_ns: Option<OwnedFd>,
 }
@@ -76,116 +144,380 @@ impl Drop for NamespaceGuard {
     }
 }
 
-/// Execute a command in the target process's namespaces
-pub async fn exec_in_namespace(pid: u32, command: &[String], env_var: Option<(&str, &str)>) -> Result<i32> {
-    let cmd = if command.is_empty() {
-        vec![
-            "/dev/crashcart/lib64/ld-linux-x86-64.so.2".to_string(),
-            "--library-path".to_string(),
-            "/dev/crashcart/lib:/dev/crashcart/lib64:/dev/crashcart/usr/lib:/dev/crashcart/usr/lib64".to_string(),
-            "/dev/crashcart/usr/bin/bash".to_string(),
-            "--rcfile".to_string(),
-            "/dev/crashcart/.crashcartrc".to_string(),
-            "-i".to_string(),
-        ]
-    } else {
-        // For custom commands, also use the dynamic linker
-        let mut cmd_vec = vec![
-            "/dev/crashcart/lib64/ld-linux-x86-64.so.2".to_string(),
-            "--library-path".to_string(),
-            "/dev/crashcart/lib:/dev/crashcart/lib64:/dev/crashcart/usr/lib:/dev/crashcart/usr/lib64".to_string(),
-        ];
-        cmd_vec.extend(command.iter().cloned());
-        cmd_vec
-    };
+/// A kind of Linux namespace, named as under `/proc/<pid>/ns`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Namespace {
+    User,
+    Cgroup,
+    Ipc,
+    Uts,
+    Net,
+    Pid,
+    Mnt,
+    Time,
+}
+
+impl Namespace {
+    /// Every namespace, in the order they are joined. The user namespace
+    /// comes first so the capabilities it grants cover the others; the
+    /// mount namespace comes late because joining it changes the root
+    /// directory.
+    pub const ALL: [Namespace; 8] = [
+        Namespace::User,
+        Namespace::Cgroup,
+        Namespace::Ipc,
+        Namespace::Uts,
+        Namespace::Net,
+        Namespace::Pid,
+        Namespace::Mnt,
+        Namespace::Time,
+    ];
+
+    /// The namespaces in `only` (every namespace when empty) that are not
+    /// in `without`, in join order.
+    pub fn selection(only: &[Namespace], without: &[Namespace]) -> Vec<Namespace> {
+        Self::ALL
+            .into_iter()
+            .filter(|ns| (only.is_empty() || only.contains(ns)) && !without.contains(ns))
+            .collect()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Namespace::User => "user",
+            Namespace::Cgroup => "cgroup",
+            Namespace::Ipc => "ipc",
+            Namespace::Uts => "uts",
+            Namespace::Net => "net",
+            Namespace::Pid => "pid",
+            Namespace::Mnt => "mnt",
+            Namespace::Time => "time",
+        }
+    }
+
+    fn clone_flag(self) -> libc::c_int {
+        match self {
+            Namespace::User => libc::CLONE_NEWUSER,
+            Namespace::Cgroup => libc::CLONE_NEWCGROUP,
+            Namespace::Ipc => libc::CLONE_NEWIPC,
+            Namespace::Uts => libc::CLONE_NEWUTS,
+            Namespace::Net => libc::CLONE_NEWNET,
+            Namespace::Pid => libc::CLONE_NEWPID,
+            Namespace::Mnt => libc::CLONE_NEWNS,
+            Namespace::Time => libc::CLONE_NEWTIME,
+        }
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Namespace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "user" => Ok(Namespace::User),
+            "cgroup" => Ok(Namespace::Cgroup),
+            "ipc" => Ok(Namespace::Ipc),
+            "uts" => Ok(Namespace::Uts),
+            "net" | "network" => Ok(Namespace::Net),
+            "pid" => Ok(Namespace::Pid),
+            "mnt" | "mount" => Ok(Namespace::Mnt),
+            "time" => Ok(Namespace::Time),
+            other => Err(anyhow!(
+                "Unknown namespace {:?} (expected user, cgroup, ipc, uts, net, pid, mnt or time)",
+                other
+            )),
+        }
+    }
+}
+
+/// The ID maps of a process's user namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserNamespace {
+    pub uid_map: Vec<IdMap>,
+    pub gid_map: Vec<IdMap>,
+}
+
+impl UserNamespace {
+    /// Read the maps from `/proc/<pid>/uid_map` and `gid_map`.
+    pub fn read(pid: u32) -> Result<Self> {
+        let read = |name: &str| {
+            std::fs::read_to_string(format!("/proc/{}/{}", pid, name))
+                .with_context(|| format!("Failed to read {} of PID {}", name, pid))
+        };
+        Ok(Self::parse(&read("uid_map")?, &read("gid_map")?))
+    }
+
+    pub fn parse(uid_map: &str, gid_map: &str) -> Self {
+        Self {
+            uid_map: parse_id_map(uid_map),
+            gid_map: parse_id_map(gid_map),
+        }
+    }
+
+    /// Whether this is the host's user namespace.
+    pub fn is_initial(&self) -> bool {
+        IdMap::is_identity(&self.uid_map) && IdMap::is_identity(&self.gid_map)
+    }
+
+    /// The IDs inside the namespace that tools run as: root when the
+    /// namespace maps it (it nearly always does), otherwise the lowest
+    /// mapped IDs.
+    pub fn credentials(&self) -> Option<(u32, u32)> {
+        let pick = |maps: &[IdMap]| {
+            IdMap::to_host(maps, 0)
+                .map(|_| 0)
+                .or_else(|| maps.iter().map(|m| m.container_id).min())
+        };
+        Some((pick(&self.uid_map)?, pick(&self.gid_map)?))
+    }
+
+    /// Host IDs of the namespace's root, for files crashcart creates on
+    /// its behalf.
+    pub fn host_root(&self) -> Option<(u32, u32)> {
+        Some((IdMap::to_host(&self.uid_map, 0)?, IdMap::to_host(&self.gid_map, 0)?))
+    }
+}
+
+/// Open handles on a target process's namespaces, ready to be joined.
+///
+/// The process is pinned with a pidfd while the namespace files are
+/// opened, so a PID reused in the meantime is detected instead of
+/// silently joining some other process's namespaces.
+pub struct TargetNamespaces {
+    pid: u32,
+    /// In join order, see [`TargetNamespaces::join`].
+    fds: Vec<(Namespace, OwnedFd)>,
+    /// Set when the target's user namespace is joined.
+    user: Option<UserNamespace>,
+}
+
+impl TargetNamespaces {
+    /// Open `namespaces` of `pid`. Namespaces crashcart already shares with
+    /// the target, and ones the kernel does not support, are left out.
+    pub fn open(pid: u32, namespaces: &[Namespace]) -> Result<Self> {
+        let pidfd = pidfd_open(pid)?;
+
+        let mut wanted = namespaces.to_vec();
+        wanted.sort();
+        wanted.dedup();
+
+        let mut fds = Vec::new();
+        for ns in wanted {
+            let target = match File::open(format!("/proc/{}/ns/{}", pid, ns)) {
+                Ok(file) => OwnedFd::from(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && ns == Namespace::Time => {
+                    debug!("Kernel has no time namespaces, not joining one");
+                    continue;
+                }
+                Err(e) => return Err(e).with_context(|| format!("Failed to open {} namespace of PID {}", ns, pid)),
+            };
+            let current = OwnedFd::from(
+                File::open(format!("/proc/self/ns/{}", ns))
+                    .with_context(|| format!("Failed to open own {} namespace", ns))?,
+            );
+
+            if same_inode(&current, &target)? {
+                debug!("Already in the {} namespace of PID {}", ns, pid);
+            } else {
+                fds.push((ns, target));
+            }
+        }
+
+        let user = match fds.iter().position(|(ns, _)| *ns == Namespace::User) {
+            Some(index) => {
+                let user = UserNamespace::read(pid)?;
+                debug!("PID {} is in a user namespace (uid map {:?})", pid, user.uid_map);
+                fds = user_namespace_order(fds, index);
+                Some(user)
+            }
+            None => None,
+        };
+
+        // A signal 0 through the pidfd fails once the process has exited,
+        // in which case the files above may belong to a new owner of the PID
+        let alive = unsafe {
+            libc::syscall(libc::SYS_pidfd_send_signal, pidfd.as_raw_fd(), 0, std::ptr::null::<libc::siginfo_t>(), 0)
+        };
+        if alive != 0 {
+            return Err(anyhow!("PID {} exited while its namespaces were being opened", pid));
+        }
+
+        Ok(Self { pid, fds, user })
+    }
 
-    // Use nsenter to execute the command in all namespaces
-    let mut nsenter_cmd = Command::new("nsenter");
-    nsenter_cmd
-        .args([
-            "-t", &pid.to_string(),
-            "-m", "-u", "-i", "-n", "-p",
-            "--"
-        ])
-        .args(&cmd);
-
-    // Add environment variable if provided
-    if let Some((key, value)) = env_var {
-        nsenter_cmd.env(key, value);
-    }
-
-    let status = nsenter_cmd
-        .status()
-        .await
-        .context("Failed to execute nsenter")?;
-
-    Ok(status.code().unwrap_or(-1))
-}
-
-/// Enter all namespaces of the target process (for more complex operations)
-pub fn enter_all_namespaces(pid: u32) -> Result<Vec<NamespaceGuard>> {
-    let namespaces = ["mnt", "uts", "ipc", "net", "pid", "cgroup"];
-    let mut guards = Vec::new();
-
-    for ns_type in &namespaces {
-        match enter_single_namespace(pid, ns_type) {
-            Ok(guard) => guards.push(guard),
-            Err(e) => {
-                debug!("Failed to enter {} namespace: {}", ns_type, e);
-                // Some namespaces might not exist or be accessible
-                guards.push(NamespaceGuard::new(None));
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// The namespaces [`TargetNamespaces::join`] will enter, in order.
+    pub fn namespaces(&self) -> impl Iterator<Item = Namespace> + '_ {
+        self.fds.iter().map(|(ns, _)| *ns)
+    }
+
+    /// The target's user namespace, if it differs from crashcart's.
+    pub fn user_namespace(&self) -> Option<&UserNamespace> {
+        self.user.as_ref()
+    }
+
+    pub(crate) fn raw_fds(&self) -> impl Iterator<Item = RawFd> + '_ {
+        self.fds.iter().map(|(_, fd)| fd.as_raw_fd())
+    }
+
+    /// Join every opened namespace, broadly in [`Namespace::ALL`] order.
+    ///
+    /// When the target has its own user namespace, namespaces owned by
+    /// another one (a container sharing the host network, say) are joined
+    /// before it, while crashcart still has the capabilities to. Afterwards
+    /// the process switches to the credentials from
+    /// [`UserNamespace::credentials`], since its host IDs usually mean
+    /// nothing there.
+    ///
+    /// Only call this in a single-threaded process (a forked child): the
+    /// kernel refuses user and mount namespace changes otherwise. The PID
+    /// namespace only applies to children forked afterwards.
+    pub fn join(&self) -> Result<()> {
+        for (ns, fd) in &self.fds {
+            if unsafe { libc::setns(fd.as_raw_fd(), ns.clone_flag()) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Failed to enter {} namespace of PID {}", ns, self.pid));
             }
         }
+
+        if let Some(user) = &self.user {
+            let (uid, gid) = user
+                .credentials()
+                .ok_or_else(|| anyhow!("The user namespace of PID {} maps no IDs", self.pid))?;
+            switch_credentials(uid, gid)
+                .with_context(|| format!("Failed to become {}:{} in the user namespace of PID {}", uid, gid, self.pid))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reorder `fds` so namespaces not owned by the user namespace at
+/// `user_index` come before it and the rest after.
+fn user_namespace_order(fds: Vec<(Namespace, OwnedFd)>, user_index: usize) -> Vec<(Namespace, OwnedFd)> {
+    // NS_GET_USERNS: a descriptor for the user namespace owning a namespace
+    const NS_GET_USERNS: libc::c_ulong = 0xb701;
+
+    let owned_by_user = |fd: &OwnedFd, user: &OwnedFd| {
+        let owner = unsafe { libc::ioctl(fd.as_raw_fd(), NS_GET_USERNS as _) };
+        if owner < 0 {
+            // Kernels before 4.9 can't tell; assume the usual layout
+            return true;
+        }
+        let owner = unsafe { OwnedFd::from_raw_fd(owner) };
+        same_inode(&owner, user).unwrap_or(true)
+    };
+
+    let mut fds = fds;
+    let user = fds.remove(user_index);
+    let (after, before): (Vec<_>, Vec<_>) = fds.into_iter().partition(|(_, fd)| owned_by_user(fd, &user.1));
+    for (ns, _) in &before {
+        debug!("The {} namespace is owned by another user namespace, joining it first", ns);
     }
 
-    info!("Entered namespaces for PID {}", pid);
-    Ok(guards)
+    before.into_iter().chain(std::iter::once(user)).chain(after).collect()
 }
 
-fn enter_single_namespace(pid: u32, ns_type: &str) -> Result<NamespaceGuard> {
-    let current_path = format!("/proc/self/ns/{}", ns_type);
-    let target_path = format!("/proc/{}/ns/{}", pid, ns_type);
+/// Drop supplementary groups and switch to `uid` and `gid`.
+fn switch_credentials(uid: u32, gid: u32) -> Result<()> {
+    // Refused when the namespace has setgroups disabled; then there are
+    // no host groups to drop anyway
+    unsafe { libc::setgroups(0, std::ptr::null()) };
 
-    let current_fd_raw = open(&*current_path, OFlag::O_RDONLY, Mode::empty())
-        .context("Failed to open current namespace")?;
-    let current_fd = unsafe { OwnedFd::from_raw_fd(current_fd_raw) };
+    if unsafe { libc::setresgid(gid, gid, gid) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("setresgid failed");
+    }
+    if unsafe { libc::setresuid(uid, uid, uid) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("setresuid failed");
+    }
+    Ok(())
+}
 
-    let target_fd_raw = open(&*target_path, OFlag::O_RDONLY, Mode::empty())
-        .context("Failed to open target namespace")?;
-    let target_fd = unsafe { OwnedFd::from_raw_fd(target_fd_raw) };
+fn pidfd_open(pid: u32) -> Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("Failed to open PID {}", pid));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
 
-    // Check if already in the same namespace
+fn same_inode(fd1: &OwnedFd, fd2: &OwnedFd) -> Result<bool> {
     use nix::sys::stat::fstat;
-    let current_stat = fstat(current_fd.as_raw_fd())?;
-    let target_stat = fstat(target_fd.as_raw_fd())?;
-
-    if current_stat.st_dev == target_stat.st_dev && current_stat.st_ino == target_stat.st_ino {
-        return Ok(NamespaceGuard::new(None));
-    }
-
-    // Enter the namespace
-    let clone_flag = match ns_type {
-        "mnt" => CloneFlags::CLONE_NEWNS,
-        "uts" => CloneFlags::CLONE_NEWUTS,
-        "ipc" => CloneFlags::CLONE_NEWIPC,
-        "net" => CloneFlags::CLONE_NEWNET,
-        "pid" => CloneFlags::CLONE_NEWPID,
-        "cgroup" => CloneFlags::CLONE_NEWCGROUP,
-        "user" => CloneFlags::CLONE_NEWUSER,
-        _ => return Err(anyhow!("Unknown namespace type: {}", ns_type)),
-    };
 
-    setns(&target_fd, clone_flag)
-        .context(format!("Failed to enter {} namespace", ns_type))?;
+    let stat1 = fstat(fd1.as_raw_fd()).context("Failed to stat namespace")?;
+    let stat2 = fstat(fd2.as_raw_fd()).context("Failed to stat namespace")?;
+    Ok(stat1.st_dev == stat2.st_dev && stat1.st_ino == stat2.st_ino)
+}
+
+/// Execute a command in `namespaces` of the target process, with
+/// crashcart's stdio. Returns the exit code, or 128 + the signal number if
+/// the command was killed.
+pub async fn exec_in_namespace(
+    pid: u32,
+    namespaces: &[Namespace],
+    command: &[String],
+    env_var: Option<(&str, &str)>,
+) -> Result<i32> {
+    exec::run(pid, &with_env(tool_spec(namespaces, command), env_var)).await
+}
+
+/// Like [`exec_in_namespace`], but with stdin closed and stdout and stderr
+/// captured instead of inherited.
+pub async fn exec_in_namespace_output(
+    pid: u32,
+    namespaces: &[Namespace],
+    command: &[String],
+    env_var: Option<(&str, &str)>,
+) -> Result<Output> {
+    exec::output(pid, &with_env(tool_spec(namespaces, command), env_var)).await
+}
 
-    // Handle user namespace special case
-    if ns_type == "user" {
-        // Set uid/gid to root in the new namespace
-        unsafe {
-            libc::setresgid(0, 0, 0);
-            libc::setresuid(0, 0, 0);
+/// What to run for `command` (an interactive shell when empty).
+///
+/// In the target's mount namespace that is a tool from the crashcart image,
+/// started through the image's own dynamic linker so it works whatever
+/// libc the target has. Without it, the host's own tools are used.
+pub fn tool_spec(namespaces: &[Namespace], command: &[String]) -> ExecSpec {
+    let mut argv = Vec::new();
+    if !namespaces.contains(&Namespace::Mnt) {
+        if command.is_empty() {
+            argv.push(std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string()));
+            argv.push("-i".to_string());
+        } else {
+            argv.extend(command.iter().cloned());
+        }
+    } else {
+        argv.extend([
+            "/dev/crashcart/lib64/ld-linux-x86-64.so.2".to_string(),
+            "--library-path".to_string(),
+            "/dev/crashcart/lib:/dev/crashcart/lib64:/dev/crashcart/usr/lib:/dev/crashcart/usr/lib64".to_string(),
+        ]);
+        if command.is_empty() {
+            argv.extend(
+                ["/dev/crashcart/usr/bin/bash", "--rcfile", "/dev/crashcart/.crashcartrc", "-i"].map(str::to_string),
+            );
+        } else {
+            argv.extend(command.iter().cloned());
         }
     }
 
-    Ok(NamespaceGuard::new(Some(current_fd)))
-}
\ No newline at end of file
+    let mut spec = ExecSpec::new(argv);
+    spec.namespaces = namespaces.to_vec();
+    spec
+}
+
+fn with_env(spec: ExecSpec, env_var: Option<(&str, &str)>) -> ExecSpec {
+    match env_var {
+        Some((key, value)) => spec.env(key, value),
+        None => spec,
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::backend::RuntimeBackend;
+
+/// Executable name prefix of resolver plugins, e.g. `crashcart-resolver-svc`.
+pub const RESOLVER_PREFIX: &str = "crashcart-resolver-";
+
+/// Directory searched for resolver plugins before `PATH`. Overridden by
+/// `CRASHCART_RESOLVER_DIR`.
+pub const DEFAULT_RESOLVER_DIR: &str = "/etc/crashcart/resolvers";
+
+/// An external executable that maps targets of one scheme (`svc://...`)
+/// to a PID, in the style of docker credential helpers.
+///
+/// The plugin is run as `crashcart-resolver-<scheme> resolve` with the full
+/// target string on stdin, and must print a [`ResolverResponse`] as JSON.
+#[derive(Debug, Clone)]
+pub struct ResolverPlugin {
+    scheme: String,
+    path: PathBuf,
+}
+
+/// JSON document printed by a resolver plugin.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResolverResponse {
+    pub pid: u32,
+    /// Runtime kind reported for the target (defaults to the scheme).
+    #[serde(default)]
+    pub runtime: Option<String>,
+    #[serde(default)]
+    pub metadata: Value,
+    /// Command prefix for exec mode; crashcart appends the command to run.
+    #[serde(default)]
+    pub exec: Option<Vec<String>>,
+}
+
+impl ResolverPlugin {
+    /// Find the plugin for `scheme` in the resolver directory or on `PATH`.
+    pub fn find(scheme: &str) -> Option<Self> {
+        if scheme.is_empty()
+            || !scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return None;
+        }
+
+        let name = format!("{}{}", RESOLVER_PREFIX, scheme);
+        let in_dir = resolver_dir().join(&name);
+        let path = if is_executable(&in_dir) {
+            in_dir
+        } else {
+            which::which(&name).ok()?
+        };
+
+        debug!("Found resolver plugin for {}:// at {}", scheme, path.display());
+        Some(Self {
+            scheme: scheme.to_string(),
+            path,
+        })
+    }
+
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Run the plugin for `target`, killing it if it takes longer than
+    /// `timeout`.
+    pub async fn resolve(&self, target: &str, timeout: Duration) -> Result<ResolverResponse> {
+        let mut child = Command::new(&self.path)
+            .arg("resolve")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run resolver plugin {}", self.path.display()))?;
+
+        let stdin = child.stdin.take();
+        let work = async move {
+            if let Some(mut stdin) = stdin {
+                stdin.write_all(format!("{}\n", target).as_bytes()).await?;
+            }
+            Ok::<_, anyhow::Error>(child.wait_with_output().await?)
+        };
+        let output = tokio::time::timeout(timeout, work).await.map_err(|_| {
+            anyhow!("Resolver plugin {} did not answer within {:?}", self.path.display(), timeout)
+        })??;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Resolver plugin {} failed for {}: {}",
+                self.path.display(),
+                target,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let response: ResolverResponse = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("Invalid JSON from resolver plugin {}", self.path.display()))?;
+        if response.pid == 0 {
+            return Err(anyhow!("Resolver plugin {} returned PID 0 for {}", self.path.display(), target));
+        }
+        Ok(response)
+    }
+}
+
+/// A target resolved by a plugin. Each resolution gets its own backend so
+/// the plugin's answer (PID, metadata, exec command) travels with it.
+pub struct PluginTarget {
+    runtime: String,
+    response: ResolverResponse,
+}
+
+impl PluginTarget {
+    pub async fn resolve(plugin: &ResolverPlugin, target: &str, timeout: Duration) -> Result<Self> {
+        let response = plugin.resolve(target, timeout).await?;
+        info!("Resolved {} to PID {} via {}", target, response.pid, plugin.path().display());
+
+        Ok(Self {
+            runtime: response
+                .runtime
+                .clone()
+                .unwrap_or_else(|| plugin.scheme().to_string()),
+            response,
+        })
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for PluginTarget {
+    fn name(&self) -> &str {
+        &self.runtime
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        Ok(target.to_string())
+    }
+
+    async fn resolve_pid(&self, _id: &str) -> Result<u32> {
+        Ok(self.response.pid)
+    }
+
+    async fn inspect(&self, _id: &str) -> Result<Value> {
+        Ok(self.response.metadata.clone())
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let prefix = self
+            .response
+            .exec
+            .as_ref()
+            .filter(|prefix| !prefix.is_empty())
+            .ok_or_else(|| anyhow!("Resolver for {} did not provide an exec command", id))?;
+
+        let status = Command::new(&prefix[0])
+            .args(&prefix[1..])
+            .args(command)
+            .status()
+            .await
+            .with_context(|| format!("Failed to execute {}", prefix[0]))?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+}
+
+fn resolver_dir() -> PathBuf {
+    std::env::var_os("CRASHCART_RESOLVER_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_RESOLVER_DIR))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::path::Path;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+use crate::info::ContainerInfo;
+use crate::terminal;
+
+/// Podman backend, driven through the `podman` CLI.
+pub struct PodmanBackend;
+
+#[async_trait]
+impl RuntimeBackend for PodmanBackend {
+    fn name(&self) -> &str {
+        "podman"
+    }
+
+    async fn is_available(&self) -> bool {
+        which::which("podman").is_ok()
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let info = self.inspect(target).await?;
+        info!("Detected Podman container: {}", target);
+
+        Ok(info
+            .get("Id")
+            .and_then(|v| v.as_str())
+            .unwrap_or(target)
+            .to_string())
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        let output = Command::new("podman")
+            .args(["inspect", "--format", "{{.State.Pid}}", id])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to get Podman container PID")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Podman container {} not found: {}",
+                id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let pid_str = String::from_utf8(output.stdout)?;
+        let pid = pid_str.trim().parse::<u32>()
+            .context("Failed to parse Podman PID")?;
+        if pid == 0 {
+            return Err(anyhow!("Podman container {} is not running", id));
+        }
+
+        debug!("Podman container {} has PID {}", id, pid);
+        Ok(pid)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        let output = Command::new("podman")
+            .args(["inspect", id])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run podman inspect")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Podman container not found"));
+        }
+
+        let mut entries: Vec<Value> = serde_json::from_slice(&output.stdout)?;
+        entries
+            .pop()
+            .ok_or_else(|| anyhow!("podman inspect returned no entries for {}", id))
+    }
+
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        let inspect = self.inspect(id).await?;
+        let mut info = ContainerInfo::from_docker_inspect(self.name(), &inspect);
+        if let Some(pid) = info.pid {
+            info.complete_from_proc(Path::new("/proc"), pid);
+        }
+        Ok(info)
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let status = Command::new("podman")
+            .args(["exec", terminal::exec_flags(), id])
+            .args(command)
+            .status()
+            .await
+            .context("Failed to execute podman exec")?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        let output = Command::new("podman")
+            .args(["ps", "--format", "json"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run podman ps")?;
+
+        let containers: Vec<Value> = serde_json::from_slice(&output.stdout)?;
+        Ok(containers
+            .iter()
+            .map(|c| {
+                let field = |key: &str| c.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+                ContainerSummary {
+                    runtime: self.name().to_string(),
+                    id: field("Id"),
+                    name: c
+                        .pointer("/Names/0")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                    image: field("Image"),
+                    state: field("State"),
+                    pid: c.get("Pid").and_then(|v| v.as_u64()).map(|pid| pid as u32),
+                }
+            })
+            .collect())
+    }
+}
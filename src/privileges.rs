@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::credentials::{self, Credentials};
+use crate::info::CAPABILITY_NAMES;
+
+/// How much of root's power a debug command keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivilegeProfile {
+    /// Look, don't touch: trace processes and capture packets.
+    #[default]
+    Observe,
+    /// Observe, plus reconfigure the network (routes, firewall, links).
+    Network,
+    /// Every capability.
+    Full,
+}
+
+impl PrivilegeProfile {
+    pub fn name(self) -> &'static str {
+        match self {
+            PrivilegeProfile::Observe => "observe",
+            PrivilegeProfile::Network => "network",
+            PrivilegeProfile::Full => "full",
+        }
+    }
+
+    /// Names of the capabilities the profile keeps. For `Full` that is
+    /// every capability crashcart has a name for; the mask has them all.
+    pub fn capability_names(self) -> &'static [&'static str] {
+        match self {
+            PrivilegeProfile::Observe => &["CAP_SYS_PTRACE", "CAP_NET_RAW"],
+            PrivilegeProfile::Network => &[
+                "CAP_SYS_PTRACE",
+                "CAP_NET_RAW",
+                "CAP_NET_ADMIN",
+                "CAP_NET_BIND_SERVICE",
+                "CAP_NET_BROADCAST",
+            ],
+            PrivilegeProfile::Full => CAPABILITY_NAMES,
+        }
+    }
+
+    /// The kept capabilities as a bitmask, limited to those the running
+    /// kernel knows.
+    pub fn capabilities(self) -> u64 {
+        let known = u64::MAX >> (63 - credentials::last_capability().min(63));
+        if self == PrivilegeProfile::Full {
+            return known;
+        }
+        self.capability_names()
+            .iter()
+            .filter_map(|name| CAPABILITY_NAMES.iter().position(|c| c == name))
+            .fold(0, |mask, bit| mask | (1 << bit))
+            & known
+    }
+}
+
+impl fmt::Display for PrivilegeProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for PrivilegeProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "observe" => Ok(PrivilegeProfile::Observe),
+            "network" => Ok(PrivilegeProfile::Network),
+            "full" => Ok(PrivilegeProfile::Full),
+            other => Err(anyhow!("Unknown privilege profile {:?} (expected observe, network or full)", other)),
+        }
+    }
+}
+
+/// Drop to `credentials` (if any) with at most the capabilities in `mask`,
+/// and set no_new_privs (unless told not to, to match a target without it)
+/// so nothing the command runs can regain more.
+///
+/// The bounding set is shrunk first, while the process still holds
+/// CAP_SETPCAP; a mask that leaves it as it is needs no CAP_SETPCAP. A
+/// process that stays root keeps `mask` as its permitted, effective,
+/// inheritable and ambient sets; one switching credentials keeps the
+/// intersection of its own sets with `mask`.
+pub(crate) fn restrict(mask: u64, credentials: Option<&Credentials>, no_new_privs: bool) -> Result<()> {
+    credentials::drop_bounding_set(!mask)?;
+
+    match credentials {
+        Some(credentials) => {
+            credentials.apply()?;
+            let (effective, permitted, inheritable) = credentials::get_capabilities()?;
+            credentials::set_capabilities(effective & mask, permitted & mask, inheritable & mask)?;
+        }
+        None => {
+            let (effective, permitted, _) = credentials::get_capabilities()?;
+            credentials::set_capabilities(effective & mask, permitted & mask, permitted & mask)?;
+            credentials::raise_ambient(permitted & mask)?;
+        }
+    }
+
+    if no_new_privs && unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to set no_new_privs");
+    }
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use crate::recording::Recorder;
+
+/// A pseudo-terminal pair. The functions used in the exec helper only make
+/// system calls, since they run in a child forked from a threaded process.
+pub(crate) struct Pty {
+    pub master: OwnedFd,
+    pub slave: OwnedFd,
+}
+
+impl Pty {
+    /// Allocate a pty from the `/dev/ptmx` of the current mount namespace,
+    /// sized `(rows, cols)` if given.
+    pub fn open(size: Option<(u16, u16)>) -> std::io::Result<Self> {
+        let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+        if master < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let master = unsafe { OwnedFd::from_raw_fd(master) };
+        if unsafe { libc::grantpt(master.as_raw_fd()) } != 0 || unsafe { libc::unlockpt(master.as_raw_fd()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // Opening the peer through the master avoids looking up a
+        // /dev/pts path that may belong to another devpts instance
+        let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+        let slave = unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGPTPEER, flags) };
+        if slave < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let pty = Self {
+            master,
+            slave: unsafe { OwnedFd::from_raw_fd(slave) },
+        };
+        if let Some((rows, cols)) = size {
+            set_window_size(&pty.master, rows, cols);
+        }
+        Ok(pty)
+    }
+}
+
+/// Make `slave` the controlling terminal of a new session and the stdio
+/// of the calling process.
+pub(crate) fn make_controlling(slave: &OwnedFd) -> std::io::Result<()> {
+    unsafe {
+        if libc::setsid() < 0 || libc::ioctl(slave.as_raw_fd(), libc::TIOCSCTTY, 0) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        for fd in 0..3 {
+            if libc::dup2(slave.as_raw_fd(), fd) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn set_window_size(master: &OwnedFd, rows: u16, cols: u16) {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+}
+
+/// Process group in the foreground of the terminal, as crashcart sees it.
+pub(crate) fn foreground_group(master: &OwnedFd) -> Option<libc::pid_t> {
+    let mut group: libc::pid_t = 0;
+    let ret = unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGPGRP, &mut group) };
+    (ret == 0 && group > 0).then_some(group)
+}
+
+// Room for one SCM_RIGHTS descriptor, aligned for cmsghdr
+type ControlBuffer = [u64; 4];
+
+/// Pass `fd` over a unix socket.
+pub(crate) fn send_fd(socket: &OwnedFd, fd: RawFd) -> std::io::Result<()> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: 1,
+    };
+    let mut control: ControlBuffer = [0; 4];
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) } as usize;
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as usize;
+        std::ptr::write_unaligned(libc::CMSG_DATA(header).cast::<RawFd>(), fd);
+    }
+
+    if unsafe { libc::sendmsg(socket.as_raw_fd(), &message, 0) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive a descriptor sent with [`send_fd`]; `None` if the peer closed
+/// the socket without sending one.
+pub(crate) fn recv_fd(socket: &OwnedFd) -> std::io::Result<Option<OwnedFd>> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: 1,
+    };
+    let mut control: ControlBuffer = [0; 4];
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = std::mem::size_of::<ControlBuffer>();
+
+    if unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let header = unsafe { libc::CMSG_FIRSTHDR(&message) };
+    if header.is_null() || unsafe { (*header).cmsg_type } != libc::SCM_RIGHTS {
+        return Ok(None);
+    }
+    let fd = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(header).cast::<RawFd>()) };
+    Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+/// Copies crashcart's stdin to the pty and the pty to crashcart's stdout,
+/// recording both directions if asked to.
+pub(crate) struct Proxy {
+    output_done: oneshot::Receiver<()>,
+    // Closing it tells the input thread to stop
+    stop: OwnedFd,
+}
+
+impl Proxy {
+    /// Start copying on two threads: reads from a terminal can't be
+    /// cancelled, so they stay out of the async runtime.
+    pub fn start(master: Arc<OwnedFd>, recorder: Option<Arc<Mutex<Recorder>>>) -> Result<Self> {
+        let mut fds = [0 as RawFd; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to create pipe");
+        }
+        let (stop_read, stop) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+        let input_master = master.clone();
+        let input_recorder = recorder.clone();
+        std::thread::spawn(move || copy_input(&input_master, &stop_read, input_recorder.as_deref()));
+
+        let (done, output_done) = oneshot::channel();
+        std::thread::spawn(move || {
+            copy_output(&master, recorder.as_deref());
+            let _ = done.send(());
+        });
+
+        Ok(Self { output_done, stop })
+    }
+
+    /// Wait for the last output once the command has exited. Background
+    /// processes holding the terminal open don't keep crashcart waiting.
+    pub async fn finish(self) {
+        if tokio::time::timeout(Duration::from_millis(500), self.output_done).await.is_err() {
+            debug!("Terminal still open after the command exited");
+        }
+        drop(self.stop);
+    }
+}
+
+fn copy_input(master: &OwnedFd, stop: &OwnedFd, recorder: Option<&Mutex<Recorder>>) {
+    let mut buf = [0u8; 4096];
+    loop {
+        let mut fds = [
+            libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: stop.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+                continue;
+            }
+            return;
+        }
+        if fds[1].revents != 0 {
+            return;
+        }
+
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if n <= 0 {
+            return;
+        }
+        if let Some(recorder) = recorder {
+            recorder.lock().unwrap().input(&buf[..n as usize]);
+        }
+        if write_all(master.as_raw_fd(), &buf[..n as usize]).is_err() {
+            return;
+        }
+    }
+}
+
+fn copy_output(master: &OwnedFd, recorder: Option<&Mutex<Recorder>>) {
+    let mut buf = [0u8; 4096];
+    let mut stdout = std::io::stdout();
+    loop {
+        // EIO once the last slave descriptor is closed
+        let n = unsafe { libc::read(master.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+            continue;
+        }
+        if n <= 0 {
+            return;
+        }
+        if let Some(recorder) = recorder {
+            recorder.lock().unwrap().output(&buf[..n as usize]);
+        }
+        if stdout.write_all(&buf[..n as usize]).and_then(|_| stdout.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+pub(crate) fn write_all(fd: RawFd, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        let n = unsafe { libc::write(fd, data.as_ptr().cast(), data.len()) };
+        if n < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::EINTR) {
+                continue;
+            }
+            return Err(error);
+        }
+        data = &data[n as usize..];
+    }
+    Ok(())
+}
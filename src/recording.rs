@@ -0,0 +1,301 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Where sessions go when the config enforces recording.
+pub const DEFAULT_RECORDING_DIR: &str = "/var/log/crashcart";
+
+/// Who debugged what, kept in the recording's header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub target: String,
+    pub runtime: String,
+    /// The person running crashcart (`$SUDO_USER` when run through sudo).
+    pub user: String,
+    /// Digest of the tools image, when one is mounted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_digest: Option<String>,
+    pub command: Vec<String>,
+}
+
+/// A session to record, and where.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub path: PathBuf,
+    pub info: SessionInfo,
+    /// Also record what is typed, passwords included.
+    #[serde(default)]
+    pub input: bool,
+}
+
+impl Recording {
+    /// A file for this session under `dir`, named after the time (to the
+    /// nanosecond), runtime and PID.
+    pub fn in_dir(dir: &Path, info: SessionInfo, pid: u32) -> Self {
+        let name = format!("{}-{}-{}.cast", chrono::Utc::now().format("%Y%m%dT%H%M%S%.9fZ"), info.runtime, pid);
+        Self {
+            path: dir.join(name),
+            info,
+            input: false,
+        }
+    }
+
+    /// Fail early if [`Recorder::create`] would: the directory can't be
+    /// created or written, or the file already exists.
+    pub fn check(&self) -> Result<()> {
+        let dir = match self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => {
+                create_dir(dir)?;
+                dir
+            }
+            None => Path::new("."),
+        };
+        if self.path.symlink_metadata().is_ok() {
+            return Err(anyhow!("Recording {} already exists", self.path.display()));
+        }
+        nix::unistd::access(dir, nix::unistd::AccessFlags::W_OK | nix::unistd::AccessFlags::X_OK)
+            .with_context(|| format!("Cannot write recordings to {}", dir.display()))
+    }
+}
+
+/// The login name of whoever runs crashcart.
+pub fn operator() -> String {
+    std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| format!("uid {}", unsafe { libc::getuid() }))
+}
+
+/// Header line of an asciicast v2 file. Session details are kept under an
+/// extra `crashcart` key, which players ignore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crashcart: Option<SessionInfo>,
+}
+
+/// One `[time, kind, data]` line: `o` for output, `i` for input, `r` for
+/// a resize to `COLSxROWS` and `m` for a marker, such as someone joining.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(f64, String, String)", into = "(f64, String, String)")]
+pub struct Event {
+    pub time: f64,
+    pub kind: String,
+    pub data: String,
+}
+
+impl From<(f64, String, String)> for Event {
+    fn from((time, kind, data): (f64, String, String)) -> Self {
+        Self { time, kind, data }
+    }
+}
+
+impl From<Event> for (f64, String, String) {
+    fn from(event: Event) -> Self {
+        (event.time, event.kind, event.data)
+    }
+}
+
+/// Writes a session as asciicast v2, one line per event so an interrupted
+/// session still leaves a readable file.
+pub struct Recorder {
+    file: LineWriter<File>,
+    start: Instant,
+    output: Utf8Stream,
+    /// `None` when input is left out.
+    input: Option<Utf8Stream>,
+}
+
+impl Recorder {
+    /// Create the file (readable by its owner only, as it holds what the
+    /// session showed) and write the header for a `(rows, cols)` terminal.
+    /// An existing file is never overwritten.
+    pub fn create(recording: &Recording, size: (u16, u16)) -> Result<Self> {
+        if let Some(dir) = recording.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            create_dir(dir)?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&recording.path)
+            .with_context(|| format!("Failed to create recording {}", recording.path.display()))?;
+
+        let header = Header {
+            version: 2,
+            width: size.1,
+            height: size.0,
+            timestamp: Some(chrono::Utc::now().timestamp()),
+            title: Some(format!("crashcart {}", recording.info.target)),
+            env: ["SHELL", "TERM"]
+                .into_iter()
+                .filter_map(|key| Some((key.to_string(), std::env::var(key).ok()?)))
+                .collect(),
+            crashcart: Some(recording.info.clone()),
+        };
+        let mut file = LineWriter::new(file);
+        writeln!(file, "{}", serde_json::to_string(&header)?).context("Failed to write recording header")?;
+
+        Ok(Self {
+            file,
+            start: Instant::now(),
+            output: Utf8Stream::default(),
+            input: recording.input.then(Utf8Stream::default),
+        })
+    }
+
+    pub fn output(&mut self, data: &[u8]) {
+        let data = self.output.decode(data);
+        self.event("o", data);
+    }
+
+    /// Record typed input, unless the recording leaves it out.
+    pub fn input(&mut self, data: &[u8]) {
+        if let Some(input) = &mut self.input {
+            let data = input.decode(data);
+            self.event("i", data);
+        }
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.event("r", format!("{}x{}", cols, rows));
+    }
+
+    pub fn marker(&mut self, label: &str) {
+        self.event("m", label.to_string());
+    }
+
+    fn event(&mut self, kind: &str, data: String) {
+        if data.is_empty() {
+            return;
+        }
+        let event = Event {
+            time: self.start.elapsed().as_secs_f64(),
+            kind: kind.to_string(),
+            data,
+        };
+        // A full disk shouldn't end the debug session
+        if let Err(e) = serde_json::to_string(&event).map_err(Into::into).and_then(|line| writeln!(self.file, "{}", line)) {
+            warn!("Failed to write to the session recording: {}", e);
+        }
+    }
+}
+
+/// Decodes a byte stream as UTF-8 across reads that split characters.
+#[derive(Default)]
+struct Utf8Stream {
+    pending: Vec<u8>,
+}
+
+impl Utf8Stream {
+    fn decode(&mut self, data: &[u8]) -> String {
+        self.pending.extend_from_slice(data);
+        let complete = self.pending.len() - incomplete_tail(&self.pending);
+        let text = String::from_utf8_lossy(&self.pending[..complete]).into_owned();
+        self.pending.drain(..complete);
+        text
+    }
+}
+
+/// Create a recordings directory readable by its owner only.
+fn create_dir(dir: &Path) -> Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))
+}
+
+/// Length of a multi-byte character cut off at the end of `bytes`.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xc0 == 0x80 {
+            continue;
+        }
+        let needed = match byte {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        return if needed > back { back } else { 0 };
+    }
+    0
+}
+
+/// A recording read back for replay.
+#[derive(Debug, Clone)]
+pub struct Cast {
+    pub header: Header,
+    pub events: Vec<Event>,
+}
+
+impl Cast {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Invalid recording {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let header: Header = serde_json::from_str(lines.next().ok_or_else(|| anyhow!("Empty file"))?)?;
+        if header.version != 2 {
+            return Err(anyhow!("Unsupported asciicast version {}", header.version));
+        }
+        let events = lines.map(serde_json::from_str).collect::<Result<_, _>>()?;
+        Ok(Self { header, events })
+    }
+
+    /// Mask everything matching one of `patterns` with `*`. Output and
+    /// input are each matched as one continuous stream, so a secret split
+    /// across reads is still caught.
+    pub fn redact(&mut self, patterns: &[Regex]) {
+        for kind in ["o", "i"] {
+            let indices: Vec<usize> = (0..self.events.len()).filter(|&i| self.events[i].kind == kind).collect();
+            let mut stream: String = indices.iter().map(|&i| self.events[i].data.as_str()).collect();
+            for pattern in patterns {
+                stream = pattern
+                    .replace_all(&stream, |m: &regex::Captures| "*".repeat(m[0].chars().count()))
+                    .into_owned();
+            }
+
+            // Masking keeps the character count, so the events split the
+            // stream where they did before
+            let mut chars = stream.chars();
+            for i in indices {
+                let len = self.events[i].data.chars().count();
+                self.events[i].data = chars.by_ref().take(len).collect();
+            }
+        }
+    }
+
+    /// Print the output to `out` with the original timing, `speed` times
+    /// faster and with pauses capped at `max_idle`.
+    pub async fn play(&self, out: &mut impl Write, speed: f64, max_idle: Duration) -> Result<()> {
+        let mut previous = 0.0;
+        for event in self.events.iter().filter(|event| event.kind == "o") {
+            let pause = Duration::from_secs_f64(((event.time - previous) / speed).max(0.0)).min(max_idle);
+            previous = event.time;
+            tokio::time::sleep(pause).await;
+            out.write_all(event.data.as_bytes())?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tracing::debug;
+
+/// One classic BPF instruction of a seccomp filter (`struct sock_filter`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BpfInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// The confinement of a target process, for `--match-security` to give
+/// the debug command the same.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecurityContext {
+    /// Seccomp filters in the order they were installed, oldest first.
+    pub seccomp_filters: Vec<Vec<BpfInstruction>>,
+    /// AppArmor profile name, unless unconfined.
+    pub apparmor_profile: Option<String>,
+    /// SELinux process label.
+    pub selinux_label: Option<String>,
+    pub no_new_privs: bool,
+}
+
+// Not in the libc crate yet
+const PTRACE_SECCOMP_GET_FILTER: libc::c_uint = 0x420c;
+
+impl SecurityContext {
+    /// Recover the confinement of `pid`. The seccomp filters are read
+    /// through `PTRACE_SECCOMP_GET_FILTER`, which briefly stops the target
+    /// and needs a kernel with `CONFIG_CHECKPOINT_RESTORE`; the LSM
+    /// contexts come from `/proc/<pid>/attr`.
+    pub async fn of_process(pid: u32) -> Result<Self> {
+        tokio::task::spawn_blocking(move || Self::of_process_blocking(pid)).await?
+    }
+
+    fn of_process_blocking(pid: u32) -> Result<Self> {
+        let proc_dir = Path::new("/proc").join(pid.to_string());
+        let status = fs::read_to_string(proc_dir.join("status"))
+            .with_context(|| format!("Failed to read status of PID {}", pid))?;
+        let status_field = |key: &str| status.lines().find_map(|l| l.strip_prefix(key)).map(str::trim);
+
+        let seccomp_filters = match status_field("Seccomp:") {
+            Some("0") | None => Vec::new(),
+            Some("2") => read_seccomp_filters(pid)?,
+            Some(mode) => return Err(anyhow!("PID {} uses seccomp mode {}, which can't be copied", pid, mode)),
+        };
+
+        let apparmor_profile = fs::read_to_string(proc_dir.join("attr/apparmor/current"))
+            .ok()
+            .and_then(|current| parse_apparmor_profile(&current));
+        let selinux_label = if Path::new("/sys/fs/selinux/enforce").exists() {
+            fs::read_to_string(proc_dir.join("attr/current"))
+                .ok()
+                .map(|label| label.trim_end_matches(['\0', '\n']).to_string())
+                .filter(|label| !label.is_empty())
+        } else {
+            None
+        };
+
+        let context = Self {
+            seccomp_filters,
+            apparmor_profile,
+            selinux_label,
+            no_new_privs: status_field("NoNewPrivs:") == Some("1"),
+        };
+        debug!(
+            "PID {} has {} seccomp filters, AppArmor {:?}, SELinux {:?}, no_new_privs {}",
+            pid,
+            context.seccomp_filters.len(),
+            context.apparmor_profile,
+            context.selinux_label,
+            context.no_new_privs
+        );
+        Ok(context)
+    }
+
+    /// Ask for the LSM context to change on the next exec. Call it in the
+    /// forked command: a process can only set its own attributes.
+    pub(crate) fn apply_lsm(&self) -> Result<()> {
+        if let Some(profile) = &self.apparmor_profile {
+            write_attr(&["/proc/self/attr/apparmor/exec", "/proc/self/attr/exec"], &format!("exec {}", profile))
+                .with_context(|| format!("Failed to switch to AppArmor profile {}", profile))?;
+        }
+        if let Some(label) = &self.selinux_label {
+            write_attr(&["/proc/self/attr/exec"], label)
+                .with_context(|| format!("Failed to switch to SELinux label {}", label))?;
+        }
+        Ok(())
+    }
+
+    /// Install the target's seccomp filters on the calling thread, oldest
+    /// first so they stack as they do in the target. Needs no_new_privs or
+    /// CAP_SYS_ADMIN.
+    pub(crate) fn install_seccomp(&self) -> Result<()> {
+        for filter in &self.seccomp_filters {
+            let mut instructions: Vec<libc::sock_filter> = filter
+                .iter()
+                .map(|i| libc::sock_filter {
+                    code: i.code,
+                    jt: i.jt,
+                    jf: i.jf,
+                    k: i.k,
+                })
+                .collect();
+            let program = libc::sock_fprog {
+                len: instructions.len() as libc::c_ushort,
+                filter: instructions.as_mut_ptr(),
+            };
+            let ret = unsafe {
+                libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &program as *const libc::sock_fprog)
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to install seccomp filter");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The profile name from an AppArmor `current` attribute such as
+/// `docker-default (enforce)`; `None` when unconfined.
+pub fn parse_apparmor_profile(current: &str) -> Option<String> {
+    let current = current.trim_end_matches(['\0', '\n']).trim();
+    let profile = match current.rsplit_once(" (") {
+        Some((name, mode)) if mode.ends_with(')') => name,
+        _ => current,
+    };
+    (!profile.is_empty() && profile != "unconfined").then(|| profile.to_string())
+}
+
+fn write_attr(candidates: &[&str], value: &str) -> Result<()> {
+    let path = candidates
+        .iter()
+        .find(|path| Path::new(path).exists())
+        .ok_or_else(|| anyhow!("No LSM attribute file in /proc/self/attr"))?;
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(value.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path))
+}
+
+/// Read every seccomp filter of `pid`, stopping it for the duration.
+fn read_seccomp_filters(pid: u32) -> Result<Vec<Vec<BpfInstruction>>> {
+    let pid = pid as libc::pid_t;
+    let null = std::ptr::null_mut::<libc::c_void>();
+
+    // ptrace requests must all come from the attaching thread, which
+    // holds for this blocking closure
+    if unsafe { libc::ptrace(libc::PTRACE_SEIZE, pid, null, null) } != 0 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("Failed to attach to PID {}", pid));
+    }
+    let result = (|| {
+        if unsafe { libc::ptrace(libc::PTRACE_INTERRUPT, pid, null, null) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to stop target");
+        }
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, libc::__WALL) } < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to wait for target to stop");
+        }
+
+        // Index 0 is the most recently installed filter
+        let mut filters = Vec::new();
+        for index in 0.. {
+            let len = unsafe { libc::ptrace(PTRACE_SECCOMP_GET_FILTER, pid, index as *mut libc::c_void, null) };
+            if len < 0 {
+                let error = std::io::Error::last_os_error();
+                if error.raw_os_error() == Some(libc::ENOENT) {
+                    break;
+                }
+                return Err(error).context("PTRACE_SECCOMP_GET_FILTER failed (kernel without CONFIG_CHECKPOINT_RESTORE?)");
+            }
+
+            let mut instructions = vec![libc::sock_filter { code: 0, jt: 0, jf: 0, k: 0 }; len as usize];
+            let ret = unsafe {
+                libc::ptrace(
+                    PTRACE_SECCOMP_GET_FILTER,
+                    pid,
+                    index as *mut libc::c_void,
+                    instructions.as_mut_ptr() as *mut libc::c_void,
+                )
+            };
+            if ret < 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to read seccomp filter");
+            }
+            filters.push(
+                instructions
+                    .into_iter()
+                    .map(|i| BpfInstruction {
+                        code: i.code,
+                        jt: i.jt,
+                        jf: i.jf,
+                        k: i.k,
+                    })
+                    .collect(),
+            );
+        }
+        filters.reverse();
+        Ok(filters)
+    })();
+
+    unsafe { libc::ptrace(libc::PTRACE_DETACH, pid, null, null) };
+    result.with_context(|| format!("Failed to read the seccomp filters of PID {}", pid))
+}
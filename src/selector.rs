@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+/// A label selector in the Kubernetes style: comma-separated `key=value`,
+/// `key!=value` and bare `key` (label present) requirements, all of which
+/// must hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+}
+
+impl Selector {
+    /// The service `service` of compose project `project`.
+    pub fn compose(project: &str, service: &str) -> Self {
+        Self {
+            requirements: vec![
+                Requirement::Equals(COMPOSE_PROJECT_LABEL.to_string(), project.to_string()),
+                Requirement::Equals(COMPOSE_SERVICE_LABEL.to_string(), service.to_string()),
+            ],
+        }
+    }
+
+    /// Parse a compose `project/service` reference.
+    pub fn parse_compose(reference: &str) -> Result<Self> {
+        match reference.split_once('/') {
+            Some((project, service)) if !project.is_empty() && !service.is_empty() => {
+                Ok(Self::compose(project, service))
+            }
+            _ => Err(anyhow!("Invalid compose target {} (expected project/service)", reference)),
+        }
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements.iter().all(|requirement| match requirement {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::Exists(key) => labels.contains_key(key),
+        })
+    }
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let requirements = s
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let requirement = if let Some((key, value)) = part.split_once("!=") {
+                    Requirement::NotEquals(key.trim().to_string(), value.trim().to_string())
+                } else if let Some((key, value)) = part.split_once('=') {
+                    Requirement::Equals(key.trim().to_string(), value.trim().to_string())
+                } else {
+                    Requirement::Exists(part.to_string())
+                };
+
+                match &requirement {
+                    Requirement::Equals(key, _)
+                    | Requirement::NotEquals(key, _)
+                    | Requirement::Exists(key)
+                        if key.is_empty() =>
+                    {
+                        Err(anyhow!("Invalid selector requirement '{}'", part))
+                    }
+                    _ => Ok(requirement),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if requirements.is_empty() {
+            return Err(anyhow!("Empty label selector"));
+        }
+        Ok(Self { requirements })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self
+            .requirements
+            .iter()
+            .map(|requirement| match requirement {
+                Requirement::Equals(key, value) => format!("{}={}", key, value),
+                Requirement::NotEquals(key, value) => format!("{}!={}", key, value),
+                Requirement::Exists(key) => key.clone(),
+            })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
@@ -0,0 +1,1174 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::exec::{self, ExecSpec};
+use crate::image::ImageManager;
+use crate::info;
+use crate::listing;
+use crate::mount::MountManager;
+use crate::pty;
+use crate::recording::{self, Recorder};
+use crate::terminal::{self, RawModeGuard};
+
+/// Where detached sessions keep their state. Like the resolution cache it
+/// lives on a tmpfs, as no session survives a reboot.
+pub const DEFAULT_SESSIONS_DIR: &str = "/run/crashcart/sessions";
+
+/// Typed on the terminal to detach from a session: ^P ^Q, as with
+/// `docker attach`.
+pub const DETACH_KEYS: [u8; 2] = [0x10, 0x11];
+
+const RECORD_FILE: &str = "session.json";
+const SOCKET_FILE: &str = "socket";
+const LOG_FILE: &str = "supervisor.log";
+const HOLDS_DIR: &str = "holds";
+const LOCK_FILE: &str = "images.lock";
+
+/// Output replayed to a client attaching to a running session.
+const SCROLLBACK: usize = 64 * 1024;
+
+/// Largest frame accepted from the session socket.
+const MAX_FRAME: usize = 1 << 20;
+
+/// How many chunks of the driver's input wait for the command to read them
+/// before more is dropped.
+const INPUT_QUEUE: usize = 256;
+
+/// A detached session, as its supervisor describes it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub id: String,
+    /// The target as resolved, e.g. `docker://4f1b0c5e9a7d`.
+    pub target: String,
+    pub pid: u32,
+    pub command: Vec<String>,
+    pub started: DateTime<Utc>,
+    /// The supervisor and its start time in clock ticks, telling whether
+    /// the session is still alive.
+    pub supervisor: u32,
+    pub supervisor_start_ticks: u64,
+    /// The tools image and the loop device it was mounted from, released
+    /// by the supervisor when the session ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_device: Option<String>,
+    /// Who is attached, as of the last join or leave.
+    #[serde(default)]
+    pub clients: Vec<Participant>,
+}
+
+/// Someone attached to a session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Participant {
+    /// Number of the client within its session, for handoffs.
+    pub number: u32,
+    pub name: String,
+    pub driving: bool,
+}
+
+impl std::fmt::Display for Participant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (#{})", self.name, self.number)
+    }
+}
+
+/// How a client takes part in a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Types into the session. At most one client drives at a time; one
+    /// asking to drive while another does watches until handed control.
+    Drive,
+    /// Only sees the output.
+    Watch,
+    /// Makes one request, such as a handoff, without taking part. A
+    /// handoff is only accepted from the driver's own user.
+    Control,
+}
+
+impl Role {
+    fn tag(self) -> u8 {
+        match self {
+            Role::Drive => b'd',
+            Role::Watch => b'w',
+            Role::Control => b'c',
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            b'd' => Some(Role::Drive),
+            b'w' => Some(Role::Watch),
+            b'c' => Some(Role::Control),
+            _ => None,
+        }
+    }
+}
+
+/// What the supervisor is told to run, read from its stdin.
+#[derive(Serialize, Deserialize)]
+struct Launch {
+    record: SessionRecord,
+    spec: ExecSpec,
+    size: (u16, u16),
+}
+
+/// One message on a session socket: a tag byte, a big-endian `u32` payload
+/// length and the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// First frame from a client: how it takes part and who it is.
+    Hello(Role, String),
+    /// Terminal input or output.
+    Data(Vec<u8>),
+    /// The client's terminal size as `(rows, cols)`.
+    Resize(u16, u16),
+    /// Pass control to a participant, given by `#number` or name.
+    Handoff(String),
+    /// The command exited with this code.
+    Exit(i32),
+    /// A message for whoever is attached.
+    Notice(String),
+    /// A request was refused.
+    Error(String),
+}
+
+impl Frame {
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, payload) = match self {
+            Frame::Hello(role, name) => (b'h', [&[role.tag()], name.as_bytes()].concat()),
+            Frame::Data(data) => (b'd', data.clone()),
+            Frame::Resize(rows, cols) => (b'r', [rows.to_be_bytes(), cols.to_be_bytes()].concat()),
+            Frame::Handoff(to) => (b't', to.as_bytes().to_vec()),
+            Frame::Exit(code) => (b'x', code.to_be_bytes().to_vec()),
+            Frame::Notice(text) => (b'n', text.as_bytes().to_vec()),
+            Frame::Error(text) => (b'e', text.as_bytes().to_vec()),
+        };
+        let mut frame = Vec::with_capacity(5 + payload.len());
+        frame.push(tag);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    /// The next frame, or `None` at the end of the stream.
+    pub async fn read(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Option<Self>> {
+        let mut header = [0u8; 5];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let malformed = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Malformed session frame {:?}", header[0] as char),
+            )
+        };
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if len > MAX_FRAME {
+            return Err(malformed());
+        }
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).await?;
+
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        Ok(Some(match (header[0], payload.as_slice()) {
+            (b'h', [role, name @ ..]) => Frame::Hello(Role::from_tag(*role).ok_or_else(malformed)?, text(name)),
+            (b'd', _) => Frame::Data(payload),
+            (b'r', &[r0, r1, c0, c1]) => Frame::Resize(u16::from_be_bytes([r0, r1]), u16::from_be_bytes([c0, c1])),
+            (b't', to) => Frame::Handoff(text(to)),
+            (b'x', &[a, b, c, d]) => Frame::Exit(i32::from_be_bytes([a, b, c, d])),
+            (b'n', notice) => Frame::Notice(text(notice)),
+            (b'e', error) => Frame::Error(text(error)),
+            _ => return Err(malformed()),
+        }))
+    }
+
+    pub async fn write(&self, writer: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<()> {
+        writer.write_all(&self.encode()).await
+    }
+}
+
+/// Spots [`DETACH_KEYS`] in terminal input, even when split across reads.
+/// A lone ^P is held back until the next byte shows what it starts.
+#[derive(Debug, Default)]
+pub struct DetachKeys {
+    pending: bool,
+}
+
+impl DetachKeys {
+    /// The input to pass on, and whether the detach sequence was typed.
+    /// Anything typed after the sequence is dropped.
+    pub fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut forward = Vec::with_capacity(input.len() + 1);
+        for &byte in input {
+            if std::mem::take(&mut self.pending) {
+                if byte == DETACH_KEYS[1] {
+                    return (forward, true);
+                }
+                forward.push(DETACH_KEYS[0]);
+            }
+            if byte == DETACH_KEYS[0] {
+                self.pending = true;
+            } else {
+                forward.push(byte);
+            }
+        }
+        (forward, false)
+    }
+}
+
+/// A foreground crashcart using the image mounted in a target, so that
+/// sessions ending meanwhile leave it mounted. Dropping it lets go.
+pub struct ImageHold {
+    path: PathBuf,
+}
+
+impl Drop for ImageHold {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// On disk as `holds/<pid>.<holder>`; a holder that has gone is ignored.
+#[derive(Serialize, Deserialize)]
+struct HoldRecord {
+    pid: u32,
+    holder: u32,
+    holder_start_ticks: u64,
+}
+
+/// Exclusive right to mount and unmount images, held until dropped.
+pub struct ImageLock {
+    _file: File,
+}
+
+/// Detached sessions, one directory each holding the session record, the
+/// socket clients attach through and the supervisor's log. Foreground
+/// crashcarts using a target's image leave a hold next to them.
+#[derive(Clone)]
+pub struct SessionStore {
+    dir: PathBuf,
+    proc_root: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self::with_proc_root(dir, "/proc")
+    }
+
+    /// A store checking supervisors against an alternative `/proc`.
+    pub fn with_proc_root(dir: impl Into<PathBuf>, proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            proc_root: proc_root.into(),
+        }
+    }
+
+    /// Live sessions, oldest first. Sessions whose supervisor has gone are
+    /// removed.
+    pub fn list(&self) -> Vec<SessionRecord> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut sessions = Vec::new();
+        for entry in entries.flatten() {
+            // Sessions still starting have no record yet
+            let Some(record) = fs::read_to_string(entry.path().join(RECORD_FILE))
+                .ok()
+                .and_then(|contents| serde_json::from_str::<SessionRecord>(&contents).ok())
+            else {
+                continue;
+            };
+            if self.is_alive(&record) {
+                sessions.push(record);
+            } else {
+                debug!("Removing session {}, its supervisor is gone", record.id);
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+        sessions.sort_by_key(|session| session.started);
+        sessions
+    }
+
+    /// The live session whose ID starts with `id`.
+    pub fn find(&self, id: &str) -> Result<SessionRecord> {
+        let mut matches: Vec<SessionRecord> = self.list().into_iter().filter(|s| s.id.starts_with(id)).collect();
+        match matches.len() {
+            0 => Err(anyhow!("No session {} (run `crashcart sessions` to see them)", id)),
+            1 => Ok(matches.remove(0)),
+            _ => Err(anyhow!("Session ID {} is ambiguous", id)),
+        }
+    }
+
+    /// Live sessions on the target process `pid`.
+    pub fn on_target(&self, pid: u32) -> Vec<SessionRecord> {
+        self.list().into_iter().filter(|session| session.pid == pid).collect()
+    }
+
+    /// Write `record` for clients to find, replacing any earlier one.
+    pub fn save(&self, record: &SessionRecord) -> Result<()> {
+        let dir = self.dir.join(&record.id);
+        let temp = dir.join(format!("{}.tmp", RECORD_FILE));
+        fs::write(&temp, serde_json::to_vec(record)?).with_context(|| format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, dir.join(RECORD_FILE)).with_context(|| format!("Failed to save session {}", record.id))
+    }
+
+    /// Take the lock every crashcart holds while mounting, or while
+    /// deciding whether to unmount, so the last user of a target's image
+    /// can't unmount it under one just arriving.
+    pub async fn lock_images(&self) -> Result<ImageLock> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        tokio::task::spawn_blocking(move || {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to lock the sessions directory");
+            }
+            Ok(ImageLock { _file: file })
+        })
+        .await?
+    }
+
+    /// Mark the image in `pid` as used by this crashcart until the hold is
+    /// dropped.
+    pub fn hold(&self, pid: u32) -> Result<ImageHold> {
+        let dir = self.dir.join(HOLDS_DIR);
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let holder = std::process::id();
+        let record = HoldRecord {
+            pid,
+            holder,
+            holder_start_ticks: info::process_start_ticks(&self.proc_root, holder)
+                .context("Cannot read crashcart's start time")?,
+        };
+        let path = dir.join(format!("{}.{}", pid, holder));
+        fs::write(&path, serde_json::to_vec(&record)?).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(ImageHold { path })
+    }
+
+    /// Whether a session or a foreground crashcart still uses the image in
+    /// `pid`. Holds whose crashcart has gone are removed.
+    pub fn image_in_use(&self, pid: u32) -> bool {
+        if !self.on_target(pid).is_empty() {
+            return true;
+        }
+        let Ok(entries) = fs::read_dir(self.dir.join(HOLDS_DIR)) else {
+            return false;
+        };
+        let mut in_use = false;
+        for entry in entries.flatten() {
+            let hold = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|contents| serde_json::from_str::<HoldRecord>(&contents).ok());
+            match hold {
+                Some(hold) if info::process_start_ticks(&self.proc_root, hold.holder) == Some(hold.holder_start_ticks) => {
+                    in_use |= hold.pid == pid;
+                }
+                _ => {
+                    debug!("Removing hold {}, its crashcart is gone", entry.path().display());
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+        in_use
+    }
+
+    fn is_alive(&self, record: &SessionRecord) -> bool {
+        info::process_start_ticks(&self.proc_root, record.supervisor) == Some(record.supervisor_start_ticks)
+    }
+
+    fn socket_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id).join(SOCKET_FILE)
+    }
+
+    /// Start `spec` in the namespaces of `pid` under a supervisor of its
+    /// own, on a `(rows, cols)` pty. The supervisor runs `crashcart
+    /// supervise` in a new session, so it outlives this process and its
+    /// terminal. With `image`, the supervisor takes over the image's loop
+    /// device once the session is running.
+    pub async fn start(
+        &self,
+        target: &str,
+        pid: u32,
+        spec: &ExecSpec,
+        image: Option<(&Path, &str)>,
+        size: (u16, u16),
+    ) -> Result<SessionRecord> {
+        let id = new_id()?;
+        let dir = self.dir.join(&id);
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let launch = Launch {
+            record: SessionRecord {
+                id: id.clone(),
+                target: target.to_string(),
+                pid,
+                command: spec.argv.clone(),
+                started: Utc::now(),
+                supervisor: 0,
+                supervisor_start_ticks: 0,
+                image: image.map(|(path, _)| path.to_path_buf()),
+                loop_device: image.map(|(_, device)| device.to_string()),
+                clients: Vec::new(),
+            },
+            spec: spec.clone(),
+            size,
+        };
+
+        let log = File::create(dir.join(LOG_FILE)).context("Failed to create the supervisor log")?;
+        let mut command = tokio::process::Command::new(std::env::current_exe()?);
+        command
+            .arg("supervise")
+            .arg("--sessions-dir")
+            .arg(&self.dir)
+            .arg(&id)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(log);
+        // SAFETY: setsid is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().context("Failed to start the session supervisor")?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(&serde_json::to_vec(&launch)?).await?;
+        drop(stdin);
+
+        // The supervisor prints the session record once the command runs
+        // and clients can attach
+        let mut ready = String::new();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let error = match tokio::time::timeout(Duration::from_secs(10), stdout.read_line(&mut ready)).await {
+            Ok(Ok(1..)) => return Ok(serde_json::from_str(&ready)?),
+            Ok(_) => {
+                let status = child.wait().await?;
+                let log = fs::read_to_string(dir.join(LOG_FILE)).unwrap_or_default();
+                let reason = log
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Error: "))
+                    .or_else(|| log.lines().last())
+                    .unwrap_or("no output")
+                    .to_string();
+                anyhow!("Session supervisor failed ({}): {}", status, reason)
+            }
+            Err(_) => {
+                let _ = child.kill().await;
+                anyhow!("Session supervisor did not start in time")
+            }
+        };
+        let _ = fs::remove_dir_all(&dir);
+        Err(error)
+    }
+
+    /// Body of `crashcart supervise`: run the session read from stdin,
+    /// serve its terminal to the clients attached and clean up after the
+    /// command exits. Returns the command's exit code.
+    pub async fn supervise(&self, id: &str) -> Result<i32> {
+        let launch: Launch = serde_json::from_reader(std::io::stdin()).context("Failed to read the session to run")?;
+        let mut record = launch.record;
+
+        let listener = UnixListener::bind(self.socket_path(id)).context("Failed to create the session socket")?;
+        // A session that should be recorded doesn't run without it
+        let recorder = match &launch.spec.recording {
+            Some(recording) => Some(Recorder::create(recording, launch.size)?),
+            None => None,
+        };
+        let command = exec::spawn_on_pty(record.pid, &launch.spec, launch.size).await?;
+        record.supervisor = std::process::id();
+        record.supervisor_start_ticks = info::process_start_ticks(&self.proc_root, record.supervisor)
+            .context("Cannot read the supervisor's start time")?;
+        self.save(&record)?;
+        info!("Session {} running {:?} in PID {}", id, record.command, record.pid);
+
+        // Tell `start` the session is up; anything printed later goes to
+        // the log
+        println!("{}", serde_json::to_string(&record)?);
+        unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) };
+
+        let master = command.master();
+        let code = match Host::new(self, record.clone(), master, recorder) {
+            Ok((host, events)) => host.run(events, listener, command.wait()).await,
+            Err(e) => Err(e),
+        };
+
+        // The log, with who joined and left, stays behind as `<id>.log`
+        let dir = self.dir.join(id);
+        if let Err(e) = fs::rename(dir.join(LOG_FILE), self.dir.join(format!("{}.log", id))) {
+            warn!("Failed to keep the session log: {}", e);
+        }
+        let _ = fs::remove_dir_all(&dir);
+        self.release(&record).await;
+        info!("Session {} ended", id);
+        code
+    }
+
+    /// Unmount the image once no other session or foreground crashcart on
+    /// the target uses it, and detach this session's loop device either way.
+    async fn release(&self, record: &SessionRecord) {
+        let (Some(image), Some(device)) = (&record.image, &record.loop_device) else {
+            return;
+        };
+        match self.lock_images().await {
+            Ok(_lock) if !self.image_in_use(record.pid) => MountManager::new().unmount_from(record.pid).await,
+            Ok(_) => {}
+            Err(e) => warn!("Leaving crashcart mounted in PID {}: {:#}", record.pid, e),
+        }
+        let result = match ImageManager::new(image) {
+            Ok(mut image_manager) => {
+                image_manager.adopt_loop_device(device.clone());
+                image_manager.cleanup_loop_device().await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Failed to release loop device {}: {:#}", device, e);
+        }
+    }
+
+    async fn connect(&self, record: &SessionRecord) -> Result<UnixStream> {
+        match UnixStream::connect(self.socket_path(&record.id)).await {
+            Ok(stream) => Ok(stream),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(anyhow!("Session {} has already ended", record.id)),
+            Err(e) => Err(e).with_context(|| format!("Failed to connect to session {}", record.id)),
+        }
+    }
+
+    /// Attach this terminal to `record`, driving or watching it, until its
+    /// command exits, returning the exit code, or until the user detaches
+    /// with [`DETACH_KEYS`], returning `None`. Input is always sent, since
+    /// a watcher may be handed control at any time.
+    pub async fn attach(&self, record: &SessionRecord, role: Role) -> Result<Option<i32>> {
+        if !(terminal::stdin_is_tty() && terminal::stdout_is_tty()) {
+            return Err(anyhow!("Attaching to a session needs a terminal"));
+        }
+        let (mut reader, mut writer) = self.connect(record).await?.into_split();
+        Frame::Hello(role, recording::operator()).write(&mut writer).await?;
+        let (rows, cols) = terminal::window_size().unwrap_or((24, 80));
+        Frame::Resize(rows, cols).write(&mut writer).await?;
+
+        // Frame reads aren't cancel safe, so they get a task of their own
+        let (frames_tx, mut frames) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(frame) = Frame::read(&mut reader).await.transpose() {
+                if frames_tx.send(frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Reads from a terminal can't be cancelled either; this thread is
+        // left blocked when the user detaches and crashcart exits
+        let (input_tx, mut input) = mpsc::channel(16);
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            let mut stdin = std::io::stdin();
+            while let Ok(n @ 1..) = stdin.read(&mut buf) {
+                if input_tx.blocking_send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut window_change = signal(SignalKind::window_change())?;
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let _raw_mode = RawModeGuard::enable()?;
+        let mut stdout = std::io::stdout();
+        let mut keys = DetachKeys::default();
+        loop {
+            tokio::select! {
+                frame = frames.recv() => match frame.transpose()? {
+                    Some(Frame::Data(data)) => {
+                        stdout.write_all(&data)?;
+                        stdout.flush()?;
+                    }
+                    Some(Frame::Exit(code)) => return Ok(Some(code)),
+                    Some(Frame::Notice(text)) => {
+                        write!(stdout, "\r\n[{}]\r\n", text)?;
+                        stdout.flush()?;
+                    }
+                    Some(Frame::Error(text)) => return Err(anyhow!(text)),
+                    Some(_) => {}
+                    None => return Err(anyhow!("Lost the connection to session {}", record.id)),
+                },
+                data = input.recv() => {
+                    let Some(data) = data else { return Ok(None) };
+                    let (data, detach) = keys.feed(&data);
+                    if !data.is_empty() {
+                        Frame::Data(data).write(&mut writer).await?;
+                    }
+                    if detach {
+                        return Ok(None);
+                    }
+                }
+                _ = window_change.recv() => {
+                    if let Some((rows, cols)) = terminal::window_size() {
+                        Frame::Resize(rows, cols).write(&mut writer).await?;
+                    }
+                }
+                _ = hangup.recv() => return Ok(None),
+                _ = terminate.recv() => return Ok(None),
+            }
+        }
+    }
+
+    /// Pass control of `record` to the participant `to` (`#number` or
+    /// name), returning the session's confirmation.
+    pub async fn handoff(&self, record: &SessionRecord, to: &str) -> Result<String> {
+        let (mut reader, mut writer) = self.connect(record).await?.into_split();
+        Frame::Hello(Role::Control, recording::operator()).write(&mut writer).await?;
+        Frame::Handoff(to.to_string()).write(&mut writer).await?;
+        loop {
+            match Frame::read(&mut reader).await? {
+                Some(Frame::Notice(text)) => return Ok(text),
+                Some(Frame::Error(text)) => return Err(anyhow!(text)),
+                Some(_) => {}
+                None => return Err(anyhow!("Session {} closed the connection", record.id)),
+            }
+        }
+    }
+}
+
+/// A client connected to a session.
+struct Client {
+    /// Frames for the client's writer task.
+    frames: mpsc::Sender<Frame>,
+    writer: JoinHandle<()>,
+    /// Unknown until the client's hello.
+    role: Option<Role>,
+    name: String,
+    size: Option<(u16, u16)>,
+    /// Who connected, from the socket's peer credentials.
+    user: Option<u32>,
+}
+
+impl Client {
+    fn participant(&self, number: u32) -> Option<Participant> {
+        match self.role? {
+            Role::Control => None,
+            role => Some(Participant {
+                number,
+                name: self.name.clone(),
+                driving: role == Role::Drive,
+            }),
+        }
+    }
+}
+
+/// What a client's reader task passes on: a frame, or `None` once the
+/// client has gone.
+type ClientEvent = (u32, Option<Frame>);
+
+/// The supervisor's side of a session: the pty master, its recent output
+/// and the clients connected, numbered in the order they came.
+struct Host<'a> {
+    store: &'a SessionStore,
+    record: SessionRecord,
+    master: AsyncFd<Arc<OwnedFd>>,
+    scrollback: VecDeque<u8>,
+    recorder: Option<Recorder>,
+    clients: BTreeMap<u32, Client>,
+    last_number: u32,
+    events: mpsc::UnboundedSender<ClientEvent>,
+    input: mpsc::Sender<Vec<u8>>,
+    input_writer: JoinHandle<()>,
+}
+
+impl<'a> Host<'a> {
+    fn new(
+        store: &'a SessionStore,
+        record: SessionRecord,
+        master: Arc<OwnedFd>,
+        recorder: Option<Recorder>,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<ClientEvent>)> {
+        let flags = unsafe { libc::fcntl(master.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to configure the session terminal");
+        }
+        // Input is written from its own task, so a command that doesn't read
+        // it can't stall the output. The task needs its own descriptor, as
+        // the reactor takes each one only once
+        let duplicate = unsafe { libc::fcntl(master.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if duplicate < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to configure the session terminal");
+        }
+        let writer = AsyncFd::new(unsafe { OwnedFd::from_raw_fd(duplicate) })?;
+        let (input, mut queued) = mpsc::channel::<Vec<u8>>(INPUT_QUEUE);
+        let input_writer = tokio::spawn(async move {
+            while let Some(data) = queued.recv().await {
+                if let Err(e) = write_master(&writer, &data).await {
+                    debug!("Failed to write to the session terminal: {}", e);
+                }
+            }
+        });
+
+        let (events, received) = mpsc::unbounded_channel();
+        let host = Self {
+            store,
+            record,
+            master: AsyncFd::new(master)?,
+            scrollback: VecDeque::new(),
+            recorder,
+            clients: BTreeMap::new(),
+            last_number: 0,
+            events,
+            input,
+            input_writer,
+        };
+        Ok((host, received))
+    }
+
+    /// Serve the session until `exit` yields the command's exit code.
+    async fn run(
+        mut self,
+        mut events: mpsc::UnboundedReceiver<ClientEvent>,
+        listener: UnixListener,
+        exit: impl Future<Output = Result<i32>>,
+    ) -> Result<i32> {
+        tokio::pin!(exit);
+        let mut buf = [0u8; 4096];
+        let mut output_open = true;
+        loop {
+            tokio::select! {
+                code = &mut exit => {
+                    let code = code?;
+                    self.drain(&mut buf).await;
+                    self.broadcast(Frame::Exit(code));
+                    self.disconnect_all().await;
+                    return Ok(code);
+                }
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => self.accept(stream),
+                    Err(e) => warn!("Failed to accept a client: {}", e),
+                },
+                read = read_master(&self.master, &mut buf), if output_open => match read {
+                    Ok(n @ 1..) => self.output(&buf[..n]),
+                    // EIO once the last slave descriptor is closed
+                    _ => output_open = false,
+                },
+                Some((number, frame)) = events.recv() => match frame {
+                    Some(frame) => self.handle(number, frame),
+                    None => self.leave(number),
+                },
+            }
+        }
+    }
+
+    /// Start reading from and writing to a new client, which takes part
+    /// once it has said hello.
+    fn accept(&mut self, stream: UnixStream) {
+        self.last_number += 1;
+        let number = self.last_number;
+        let user = peer_user(&stream);
+        let (mut reader, mut writer) = stream.into_split();
+
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(frame)) = Frame::read(&mut reader).await {
+                if events.send((number, Some(frame))).is_err() {
+                    return;
+                }
+            }
+            let _ = events.send((number, None));
+        });
+
+        // A client too slow for the output is dropped rather than holding
+        // up the session
+        let (frames, mut outgoing) = mpsc::channel::<Frame>(256);
+        let writer = tokio::spawn(async move {
+            while let Some(frame) = outgoing.recv().await {
+                if frame.write(&mut writer).await.is_err() {
+                    break;
+                }
+            }
+        });
+        self.clients.insert(
+            number,
+            Client {
+                frames,
+                writer,
+                role: None,
+                name: String::new(),
+                size: None,
+                user,
+            },
+        );
+    }
+
+    fn handle(&mut self, number: u32, frame: Frame) {
+        let Some(client) = self.clients.get_mut(&number) else {
+            return;
+        };
+        match (client.role, frame) {
+            (None, Frame::Hello(role, name)) => self.join(number, role, name),
+            (None, frame) => {
+                debug!("Client #{} sent {:?} before saying hello", number, frame);
+                self.clients.remove(&number);
+            }
+            (Some(Role::Drive), Frame::Data(data)) => match self.input.try_reserve() {
+                Ok(permit) => {
+                    if let Some(recorder) = &mut self.recorder {
+                        recorder.input(&data);
+                    }
+                    permit.send(data);
+                }
+                Err(_) => warn!("Dropping input from client #{}, the command isn't reading it", number),
+            },
+            // Watchers can't type
+            (Some(_), Frame::Data(_)) => {}
+            (Some(role), Frame::Resize(rows, cols)) => {
+                client.size = Some((rows, cols));
+                if role == Role::Drive {
+                    self.resize(rows, cols);
+                }
+            }
+            (Some(Role::Drive), Frame::Handoff(to)) => self.handoff(number, &to),
+            (Some(Role::Control), Frame::Handoff(to)) => {
+                if self.speaks_for_driver(number) {
+                    self.handoff(number, &to);
+                } else {
+                    self.send(number, Frame::Error("Only the driver can hand over control".to_string()));
+                    self.clients.remove(&number);
+                }
+            }
+            (Some(Role::Watch), Frame::Handoff(_)) => {
+                self.send(number, Frame::Error("Only the driver can hand over control".to_string()));
+            }
+            (Some(_), frame) => debug!("Ignoring {:?} from client #{}", frame, number),
+        }
+    }
+
+    /// Let a client that said hello take part. One asking to drive while
+    /// another client drives watches instead, until it is handed control.
+    fn join(&mut self, number: u32, role: Role, name: String) {
+        let role = match (role, self.driver()) {
+            (Role::Drive, Some(_)) => Role::Watch,
+            (role, _) => role,
+        };
+        let Some(client) = self.clients.get_mut(&number) else {
+            return;
+        };
+        client.role = Some(role);
+        client.name = name;
+        let Some(participant) = client.participant(number) else {
+            return;
+        };
+
+        let doing = if participant.driving { "driving" } else { "watching" };
+        self.log(format!("{} joined, {}", participant, doing), number);
+
+        if !self.scrollback.is_empty() {
+            let scrollback = self.scrollback.iter().copied().collect();
+            self.send(number, Frame::Data(scrollback));
+        }
+        if !participant.driving {
+            let driver = self.driver().and_then(|driver| self.participant(driver));
+            let notice = match driver {
+                Some(driver) => format!("Watching session {}, {} drives", self.record.id, driver),
+                None => format!("Watching session {}, nobody drives", self.record.id),
+            };
+            self.send(number, Frame::Notice(notice));
+        }
+        self.publish();
+    }
+
+    fn leave(&mut self, number: u32) {
+        let Some(client) = self.clients.remove(&number) else {
+            return;
+        };
+        if let Some(participant) = client.participant(number) {
+            self.log(format!("{} left", participant), number);
+            self.publish();
+        }
+    }
+
+    /// Pass control to the participant `to` on behalf of client `from`.
+    fn handoff(&mut self, from: u32, to: &str) {
+        let number = match self.find(to) {
+            Ok(number) => number,
+            Err(e) => {
+                self.send(from, Frame::Error(e));
+                return;
+            }
+        };
+        if let Some(previous) = self.driver().filter(|&previous| previous != number) {
+            self.set_role(previous, Role::Watch);
+        }
+        self.set_role(number, Role::Drive);
+        if let Some((rows, cols)) = self.clients.get(&number).and_then(|client| client.size) {
+            self.resize(rows, cols);
+        }
+
+        let participant = self.participant(number).expect("a participant was found");
+        let by = self.clients.get(&from).map(|client| client.name.clone()).unwrap_or_default();
+        self.log(format!("{} has control, handed over by {}", participant, by), from);
+        self.send(number, Frame::Notice("You have control".to_string()));
+        if self.clients.get(&from).and_then(|client| client.role) == Some(Role::Control) {
+            self.send(from, Frame::Notice(format!("{} has control of session {}", participant, self.record.id)));
+            self.clients.remove(&from);
+        }
+        self.publish();
+    }
+
+    /// The participant `to` refers to: `#number`, the number alone, or a
+    /// name only one participant has.
+    fn find(&self, to: &str) -> std::result::Result<u32, String> {
+        let participants: Vec<Participant> =
+            self.clients.iter().filter_map(|(&number, client)| client.participant(number)).collect();
+        if let Ok(number) = to.trim_start_matches('#').parse::<u32>() {
+            return match participants.iter().find(|p| p.number == number) {
+                Some(p) => Ok(p.number),
+                None => Err(format!("Nobody is attached as #{}", number)),
+            };
+        }
+        match participants.iter().filter(|p| p.name == to).collect::<Vec<_>>()[..] {
+            [p] => Ok(p.number),
+            [] => Err(format!("Nobody called {} is attached", to)),
+            _ => Err(format!("Several participants are called {}, give their #number", to)),
+        }
+    }
+
+    /// Whether client `number` connected as the same user as the driver,
+    /// or nobody drives.
+    fn speaks_for_driver(&self, number: u32) -> bool {
+        let Some(driver) = self.driver() else {
+            return true;
+        };
+        let user = |number| self.clients.get(&number).and_then(|client| client.user);
+        user(number).is_some() && user(number) == user(driver)
+    }
+
+    fn driver(&self) -> Option<u32> {
+        self.clients.iter().find(|(_, client)| client.role == Some(Role::Drive)).map(|(&number, _)| number)
+    }
+
+    fn participant(&self, number: u32) -> Option<Participant> {
+        self.clients.get(&number)?.participant(number)
+    }
+
+    fn set_role(&mut self, number: u32, role: Role) {
+        if let Some(client) = self.clients.get_mut(&number) {
+            client.role = Some(role);
+        }
+    }
+
+    fn resize(&mut self, rows: u16, cols: u16) {
+        pty::set_window_size(self.master.get_ref(), rows, cols);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.resize(rows, cols);
+        }
+    }
+
+    /// Log a join, leave or handoff, mark it in the recording and tell the
+    /// other participants than `except`.
+    fn log(&mut self, message: String, except: u32) {
+        info!("Session {}: {}", self.record.id, message);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.marker(&message);
+        }
+        let others: Vec<u32> = self
+            .clients
+            .iter()
+            .filter(|(&number, client)| number != except && client.participant(number).is_some())
+            .map(|(&number, _)| number)
+            .collect();
+        for number in others {
+            self.send(number, Frame::Notice(message.clone()));
+        }
+    }
+
+    /// Update the session record with the participants, for `crashcart
+    /// sessions` and handoffs.
+    fn publish(&mut self) {
+        self.record.clients = self.clients.iter().filter_map(|(&number, client)| client.participant(number)).collect();
+        if let Err(e) = self.store.save(&self.record) {
+            warn!("Failed to update session {}: {:#}", self.record.id, e);
+        }
+    }
+
+    fn output(&mut self, data: &[u8]) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.output(data);
+        }
+        self.scrollback.extend(data);
+        let excess = self.scrollback.len().saturating_sub(SCROLLBACK);
+        self.scrollback.drain(..excess);
+        self.broadcast(Frame::Data(data.to_vec()));
+    }
+
+    /// Pass on output still in the pty after the command exited, until it
+    /// is closed or stays quiet for a moment.
+    async fn drain(&mut self, buf: &mut [u8]) {
+        while let Ok(Ok(n @ 1..)) = tokio::time::timeout(Duration::from_millis(100), read_master(&self.master, buf)).await {
+            self.output(&buf[..n]);
+        }
+    }
+
+    /// Send `frame` to every participant.
+    fn broadcast(&mut self, frame: Frame) {
+        let participants: Vec<u32> =
+            self.clients.iter().filter(|(&number, client)| client.participant(number).is_some()).map(|(&n, _)| n).collect();
+        for number in participants {
+            self.send(number, frame.clone());
+        }
+    }
+
+    fn send(&mut self, number: u32, frame: Frame) {
+        let Some(client) = self.clients.get(&number) else {
+            return;
+        };
+        if client.frames.try_send(frame).is_err() {
+            warn!("Dropping client #{}, it can't keep up", number);
+            self.leave(number);
+        }
+    }
+
+    /// Let the writers send what is queued, then close every connection.
+    async fn disconnect_all(&mut self) {
+        // Dropping the senders ends each writer once its queue is empty
+        let writers: Vec<JoinHandle<()>> = std::mem::take(&mut self.clients).into_values().map(|c| c.writer).collect();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(1);
+        for writer in writers {
+            let _ = tokio::time::timeout_at(deadline, writer).await;
+        }
+    }
+}
+
+impl Drop for Host<'_> {
+    fn drop(&mut self) {
+        // Input still queued has nowhere to go once the command has exited
+        self.input_writer.abort();
+    }
+}
+
+async fn read_master(master: &AsyncFd<Arc<OwnedFd>>, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        let mut guard = master.readable().await?;
+        let result = guard.try_io(|fd| {
+            let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        if let Ok(result) = result {
+            return result;
+        }
+    }
+}
+
+async fn write_master(master: &AsyncFd<OwnedFd>, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        let mut guard = master.writable().await?;
+        let result = guard.try_io(|fd| {
+            let n = unsafe { libc::write(fd.as_raw_fd(), data.as_ptr().cast(), data.len()) };
+            if n < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        match result {
+            Ok(result) => data = &data[result?..],
+            // The master keeps saying it is writable while the command
+            // leaves its input unread, so don't spin on it
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    Ok(())
+}
+
+/// The user on the other end of a session socket: its login UID, which
+/// survives sudo, or its UID where no login UID is set.
+fn peer_user(stream: &UnixStream) -> Option<u32> {
+    let peer = stream.peer_cred().ok()?;
+    let login = peer
+        .pid()
+        .and_then(|pid| fs::read_to_string(format!("/proc/{}/loginuid", pid)).ok())
+        .and_then(|uid| uid.trim().parse::<u32>().ok())
+        .filter(|&uid| uid != u32::MAX);
+    Some(login.unwrap_or(peer.uid()))
+}
+
+/// A short random session ID.
+fn new_id() -> Result<String> {
+    let mut bytes = [0u8; 4];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .context("Failed to read /dev/urandom")?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Format `sessions` as aligned text rows, header first, with ages
+/// relative to `now`. The client driving is marked with `*`.
+pub fn table_rows(sessions: &[SessionRecord], now: DateTime<Utc>) -> Vec<String> {
+    const HEADER: [&str; 5] = ["ID", "TARGET", "AGE", "CLIENTS", "COMMAND"];
+
+    let mut rows: Vec<[String; 5]> = vec![HEADER.map(str::to_string)];
+    for session in sessions {
+        let clients: Vec<String> = session
+            .clients
+            .iter()
+            .map(|c| format!("#{} {}{}", c.number, c.name, if c.driving { "*" } else { "" }))
+            .collect();
+        rows.push([
+            session.id.clone(),
+            session.target.clone(),
+            age(now - session.started),
+            if clients.is_empty() { "-".to_string() } else { clients.join(", ") },
+            session.command.join(" "),
+        ]);
+    }
+
+    listing::format_table(&rows)
+}
+
+/// `42s`, `5m`, `3h` or `2d`, like `docker ps`' short form.
+fn age(elapsed: chrono::Duration) -> String {
+    let seconds = elapsed.num_seconds().max(0);
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::backend::{ContainerSummary, RuntimeBackend};
+use crate::cgroup::{CgroupTree, CGROUP_ROOT};
+
+/// Escape a string the way systemd escapes unit instance names, byte by
+/// byte of its UTF-8 (`my-box` becomes `my\x2dbox`).
+fn escape_unit_name(name: &str) -> String {
+    name.bytes()
+        .enumerate()
+        .map(|(i, b)| {
+            if b.is_ascii_alphanumeric() || b == b':' || b == b'_' || (b == b'.' && i > 0) {
+                (b as char).to_string()
+            } else {
+                format!("\\x{:02x}", b)
+            }
+        })
+        .collect()
+}
+
+fn unescape_unit_name(name: &str) -> String {
+    let mut out = Vec::new();
+    let mut rest = name;
+    while let Some(pos) = rest.find("\\x") {
+        out.extend_from_slice(&rest.as_bytes()[..pos]);
+        match rest.get(pos + 2..pos + 4).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                out.push(byte);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.extend_from_slice(b"\\x");
+                rest = &rest[pos + 2..];
+            }
+        }
+    }
+    out.extend_from_slice(rest.as_bytes());
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Unit types a target may name; a bare name means a `.service`.
+const UNIT_TYPES: &[&str] = &[".service", ".scope"];
+
+/// Systemd services (`systemd://nginx` or `systemd://nginx.service`),
+/// resolved to the unit's `MainPID`, or through `cgroup.procs` for units
+/// without one.
+#[derive(Clone)]
+pub struct SystemdBackend {
+    tree: CgroupTree,
+    systemctl: PathBuf,
+}
+
+impl Default for SystemdBackend {
+    fn default() -> Self {
+        Self::with_roots(CGROUP_ROOT, "/proc")
+    }
+}
+
+impl SystemdBackend {
+    pub fn with_roots(cgroup_root: impl Into<PathBuf>, proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            tree: CgroupTree::new(cgroup_root.into(), proc_root.into()),
+            systemctl: PathBuf::from("systemctl"),
+        }
+    }
+
+    /// Ask `systemctl` at `path` for units' main PIDs.
+    pub fn with_systemctl(mut self, path: impl Into<PathBuf>) -> Self {
+        self.systemctl = path.into();
+        self
+    }
+
+    /// The unit's `MainPID`, or `None` if systemd has none for it or can't
+    /// be asked.
+    async fn main_pid(&self, unit: &str) -> Option<u32> {
+        let output = Command::new(&self.systemctl)
+            .args(["show", "--property=MainPID", "--value", "--", unit])
+            .kill_on_drop(true)
+            .output()
+            .await;
+        let output = match output {
+            Ok(output) if output.status.success() => output,
+            Ok(output) => {
+                debug!("systemctl show {} failed: {}", unit, String::from_utf8_lossy(&output.stderr).trim());
+                return None;
+            }
+            Err(e) => {
+                debug!("Failed to run {}: {}", self.systemctl.display(), e);
+                return None;
+            }
+        };
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|&pid| pid != 0)
+    }
+
+    fn unit_dir(&self, unit: &str) -> Result<PathBuf> {
+        self.tree
+            .find_relative(&[format!("system.slice/{}", unit)])
+            .or_else(|| self.tree.find_named(unit))
+            .ok_or_else(|| anyhow!("No cgroup found for systemd unit {}", unit))
+    }
+
+    /// The unit's cgroup and main PID, falling back to the cgroup's oldest
+    /// process when systemd has no `main_pid` for it. The cgroup is looked up off
+    /// the async runtime as the search may walk the whole cgroup tree.
+    async fn main_process(&self, unit: &str, main_pid: Option<u32>) -> Result<(PathBuf, u32)> {
+        let (backend, unit) = (self.clone(), unit.to_string());
+        tokio::task::spawn_blocking(move || {
+            let dir = backend.unit_dir(&unit)?;
+            let pid = match main_pid {
+                Some(pid) => pid,
+                None => backend.tree.init_process(&dir)?,
+            };
+            Ok((dir, pid))
+        })
+        .await?
+    }
+}
+
+fn has_unit_type(target: &str) -> bool {
+    UNIT_TYPES.iter().any(|t| target.ends_with(t))
+}
+
+/// Default to a `.service` unit when no unit type is given.
+fn normalize_unit(target: &str) -> String {
+    if has_unit_type(target) {
+        target.to_string()
+    } else {
+        format!("{}.service", target)
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for SystemdBackend {
+    fn name(&self) -> &str {
+        "systemd"
+    }
+
+    async fn is_available(&self) -> bool {
+        self.tree.root().is_dir()
+    }
+
+    fn claims(&self, target: &str) -> bool {
+        has_unit_type(target)
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let unit = normalize_unit(target);
+        let pid = self.resolve_pid(&unit).await?;
+        info!("Detected systemd unit {} with main PID {}", unit, pid);
+        Ok(unit)
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        let pid = match self.main_pid(id).await {
+            Some(pid) => pid,
+            None => self.main_process(id, None).await?.1,
+        };
+        debug!("Systemd unit {} has main PID {}", id, pid);
+        Ok(pid)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        let (dir, pid) = self.main_process(id, self.main_pid(id).await).await?;
+        Ok(json!({
+            "unit": id,
+            "cgroup": dir,
+            "pid": pid,
+        }))
+    }
+}
+
+/// `systemd-nspawn` and other registered machines (`machine://name`),
+/// resolved to the machine's leader (its init process).
+#[derive(Clone)]
+pub struct MachineBackend {
+    tree: CgroupTree,
+}
+
+impl Default for MachineBackend {
+    fn default() -> Self {
+        Self::with_roots(CGROUP_ROOT, "/proc")
+    }
+}
+
+impl MachineBackend {
+    pub fn with_roots(cgroup_root: impl Into<PathBuf>, proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            tree: CgroupTree::new(cgroup_root.into(), proc_root.into()),
+        }
+    }
+
+    fn machine_dir(&self, name: &str) -> Result<PathBuf> {
+        let escaped = escape_unit_name(name);
+        // nspawn puts the container's processes in a `payload` subgroup
+        let candidates = [
+            format!("machine.slice/systemd-nspawn@{}.service/payload", escaped),
+            format!("machine.slice/systemd-nspawn@{}.service", escaped),
+            format!("machine.slice/machine-{}.scope/payload", escaped),
+            format!("machine.slice/machine-{}.scope", escaped),
+        ];
+
+        self.tree
+            .find_relative(&candidates)
+            .ok_or_else(|| anyhow!("No machine named {} found under machine.slice", name))
+    }
+
+    /// The machine's cgroup and leader PID, read off the async runtime.
+    async fn leader(&self, name: &str) -> Result<(PathBuf, u32)> {
+        let (backend, name) = (self.clone(), name.to_string());
+        tokio::task::spawn_blocking(move || {
+            let dir = backend.machine_dir(&name)?;
+            let pid = backend.tree.init_process(&dir)?;
+            Ok((dir, pid))
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for MachineBackend {
+    fn name(&self) -> &str {
+        "machine"
+    }
+
+    async fn is_available(&self) -> bool {
+        self.tree
+            .hierarchies()
+            .iter()
+            .any(|h| h.join("machine.slice").is_dir())
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        let pid = self.resolve_pid(target).await?;
+        info!("Detected machine {} with leader PID {}", target, pid);
+        Ok(target.to_string())
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        let (_, pid) = self.leader(id).await?;
+        debug!("Machine {} has leader PID {}", id, pid);
+        Ok(pid)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        let (dir, pid) = self.leader(id).await?;
+        Ok(json!({
+            "machine": id,
+            "cgroup": dir,
+            "pid": pid,
+        }))
+    }
+
+    async fn exec(&self, id: &str, command: &[String]) -> Result<i32> {
+        let status = Command::new("machinectl")
+            .args(["shell", id])
+            .args(command)
+            .status()
+            .await
+            .context("Failed to execute machinectl shell")?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        let mut summaries = Vec::new();
+
+        for hierarchy in self.tree.hierarchies() {
+            let Ok(entries) = fs::read_dir(hierarchy.join("machine.slice")) else {
+                continue;
+            };
+
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                let Some(escaped) = file_name
+                    .strip_prefix("systemd-nspawn@")
+                    .and_then(|n| n.strip_suffix(".service"))
+                    .or_else(|| {
+                        file_name
+                            .strip_prefix("machine-")
+                            .and_then(|n| n.strip_suffix(".scope"))
+                    })
+                else {
+                    continue;
+                };
+
+                let name = unescape_unit_name(escaped);
+                summaries.push(ContainerSummary {
+                    runtime: self.name().to_string(),
+                    pid: self.resolve_pid(&name).await.ok(),
+                    id: name.clone(),
+                    name,
+                    image: String::new(),
+                    state: "running".to_string(),
+                });
+            }
+
+            if !summaries.is_empty() {
+                break;
+            }
+        }
+
+        Ok(summaries)
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::{Context, Result};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use std::io::{stdin, stdout};
+use std::os::fd::AsRawFd;
+
+/// Returns true when crashcart's stdin is attached to a terminal.
+pub fn stdin_is_tty() -> bool {
+    nix::unistd::isatty(stdin().as_raw_fd()).unwrap_or(false)
+}
+
+/// Returns true when crashcart's stdout is attached to a terminal.
+pub fn stdout_is_tty() -> bool {
+    nix::unistd::isatty(stdout().as_raw_fd()).unwrap_or(false)
+}
+
+/// Flags for a runtime CLI's `exec`: keep stdin open, and ask for a TTY
+/// only when crashcart has one, so piped output stays byte-exact.
+pub fn exec_flags() -> &'static str {
+    if stdin_is_tty() && stdout_is_tty() {
+        "-it"
+    } else {
+        "-i"
+    }
+}
+
+/// Current size of the terminal on stdin as `(rows, cols)`.
+pub fn window_size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(stdin().as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+    (ret == 0 && size.ws_row > 0).then_some((size.ws_row, size.ws_col))
+}
+
+/// Puts the terminal on stdin into raw mode and restores the original
+/// settings when dropped.
+pub struct RawModeGuard {
+    original: Termios,
+}
+
+impl RawModeGuard {
+    pub fn enable() -> Result<Self> {
+        let original = tcgetattr(stdin()).context("Failed to read terminal attributes")?;
+
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(stdin(), SetArg::TCSANOW, &raw).context("Failed to enable raw mode")?;
+
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        if let Err(e) = tcsetattr(stdin(), SetArg::TCSANOW, &self.original) {
+            tracing::warn!("Failed to restore terminal attributes: {}", e);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use crashcart::cgroup::container_id_from_cgroup_path;
+use crashcart::CgroupResolver;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const ID_A: &str = "4f1b0c5e9a7d3e2f8b6c1a0d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1e0f";
+const ID_B: &str = "4f1b0c5e00000000000000000000000000000000000000000000000000000000";
+
+fn fake_process(root: &Path, pid: u32, ppid: u32, start: u64, cgroup: &str, nspid: &str) {
+    let dir = root.join(pid.to_string());
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("cgroup"), cgroup).unwrap();
+    fs::write(
+        dir.join("stat"),
+        format!(
+            "{} (my (odd) proc) S {} 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 {} 0 0",
+            pid, ppid, start
+        ),
+    )
+    .unwrap();
+    fs::write(dir.join("status"), format!("Name:\tproc\nNSpid:\t{}\n", nspid)).unwrap();
+}
+
+fn fake_proc_root(name: &str) -> PathBuf {
+    let root = common::temp_path(name);
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    fs::create_dir_all(root.join("self")).unwrap();
+    root
+}
+
+#[test]
+fn test_container_id_from_cgroup_paths() {
+    let cases = [
+        format!("/system.slice/docker-{}.scope", ID_A),
+        format!("/docker/{}", ID_A),
+        format!("/machine.slice/libpod-{}.scope/container", ID_A),
+        format!(
+            "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234.slice/cri-containerd-{}.scope",
+            ID_A
+        ),
+        format!("/kubepods.slice/kubepods-pod1234.slice/crio-{}.scope", ID_A),
+        format!("/kubepods/besteffort/pod1234/{}", ID_A),
+    ];
+    for path in &cases {
+        assert_eq!(container_id_from_cgroup_path(path).as_deref(), Some(ID_A), "{}", path);
+    }
+
+    assert_eq!(container_id_from_cgroup_path(&format!("/machine.slice/libpod-conmon-{}.scope", ID_A)), None);
+    assert_eq!(container_id_from_cgroup_path("/user.slice/user-1000.slice/session-3.scope"), None);
+}
+
+#[test]
+fn test_cgroup_resolver_picks_namespace_init() {
+    let root = fake_proc_root("cgroup-init");
+    let v2 = format!("0::/system.slice/docker-{}.scope\n", ID_A);
+    // Runtime shim outside the container, the init and an exec'd shell
+    fake_process(&root, 100, 1, 10, "0::/system.slice/containerd.service\n", "100");
+    fake_process(&root, 200, 100, 20, &v2, "200 1");
+    fake_process(&root, 250, 200, 30, &v2, "250 7");
+    fake_process(&root, 300, 100, 40, &v2, "300 12");
+
+    let container = CgroupResolver::with_proc_root(&root).resolve(&ID_A[..12]).unwrap();
+    assert_eq!(container.id, ID_A);
+    assert_eq!(container.pid, 200);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_cgroup_resolver_without_pid_namespace() {
+    let root = fake_proc_root("cgroup-hostpid");
+    let v1 = format!("12:memory:/docker/{}\n1:name=systemd:/docker/{}\n", ID_B, ID_B);
+    fake_process(&root, 500, 1, 50, &v1, "500");
+    fake_process(&root, 510, 500, 60, &v1, "510");
+    fake_process(&root, 600, 1, 70, &v1, "600");
+    fake_process(&root, 700, 1, 5, &format!("0::/system.slice/docker-{}.scope\n", ID_A), "700");
+
+    let resolver = CgroupResolver::with_proc_root(&root);
+    assert_eq!(resolver.resolve(ID_B).unwrap().pid, 500);
+    assert_eq!(resolver.list().unwrap().len(), 2);
+
+    let err = resolver.resolve("4f1b0c5e").unwrap_err();
+    assert!(err.to_string().contains("ambiguous"));
+    assert!(resolver.resolve("not-an-id").is_err());
+
+    fs::remove_dir_all(&root).unwrap();
+}
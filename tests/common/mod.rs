@@ -0,0 +1,91 @@
+//! Fixtures shared by the integration tests. Each test crate uses only
+//! some of them.
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+use tokio::net::{UnixListener, UnixStream};
+
+/// A path in the temp directory named after `name` and this test process.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("crashcart-{}-{}", name, std::process::id()))
+}
+
+/// Listen on a fresh unix socket named after `name`, handling every
+/// connection with `handle` on a task of its own.
+pub fn serve<F, Fut>(name: &str, handle: F) -> PathBuf
+where
+    F: Fn(UnixStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let socket = temp_path(name).with_extension("sock");
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(handle(stream));
+        }
+    });
+
+    socket
+}
+
+/// Read an HTTP/1.1 request's head and body, or `None` if the client hangs
+/// up first.
+pub async fn read_request(stream: &mut UnixStream) -> Option<(String, Vec<u8>)> {
+    let mut request = Vec::new();
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await.unwrap() == 0 {
+            return None;
+        }
+        request.push(byte[0]);
+    }
+
+    let head = String::from_utf8(request).unwrap();
+    let content_length = head
+        .lines()
+        .find_map(|l| l.strip_prefix("Content-Length: "))
+        .map(|v| v.trim().parse::<usize>().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0u8; content_length];
+    stream.read_exact(&mut body).await.unwrap();
+    Some((head, body))
+}
+
+/// Add process `pid` with parent `ppid` and `NSpid:` line `nspid` to a
+/// fake `/proc`.
+pub fn fake_process(proc_root: &Path, pid: u32, ppid: u32, nspid: &str) {
+    let dir = proc_root.join(pid.to_string());
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("stat"),
+        format!("{} (proc) S {} 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 {} 0 0", pid, ppid, pid),
+    )
+    .unwrap();
+    fs::write(dir.join("status"), format!("NSpid:\t{}\n", nspid)).unwrap();
+}
+
+/// Add a cgroup at `relative` holding `pids` to a fake cgroup tree.
+pub fn fake_cgroup(cgroup_root: &Path, relative: &str, pids: &[u32]) {
+    let dir = cgroup_root.join(relative);
+    fs::create_dir_all(&dir).unwrap();
+    let procs: String = pids.iter().map(|p| format!("{}\n", p)).collect();
+    fs::write(dir.join("cgroup.procs"), procs).unwrap();
+}
+
+/// A fresh directory named after `name` with empty fake cgroup and `/proc`
+/// roots in it, returned as `(base, cgroup_root, proc_root)`.
+pub fn fake_host(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+    let base = temp_path(name);
+    let _ = fs::remove_dir_all(&base);
+    let cgroup_root = base.join("cgroup");
+    let proc_root = base.join("proc");
+    fs::create_dir_all(&cgroup_root).unwrap();
+    fs::create_dir_all(&proc_root).unwrap();
+    (base, cgroup_root, proc_root)
+}
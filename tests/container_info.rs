@@ -0,0 +1,81 @@
+use crashcart::info::{capability_names, parse_id_map, IdMap};
+use crashcart::ContainerInfo;
+use serde_json::json;
+use std::path::PathBuf;
+
+#[test]
+fn test_info_from_docker_inspect() {
+    let inspect = json!({
+        "Id": "4f1b0c5e9a7d",
+        "Name": "/web",
+        "Config": {
+            "Image": "nginx:1.25",
+            "Labels": {"app": "web", "com.docker.compose.service": "frontend"}
+        },
+        "State": {"Status": "running", "Pid": 4242, "StartedAt": "2024-05-01T12:00:00.123456789Z"},
+        "GraphDriver": {"Data": {"MergedDir": "/var/lib/docker/overlay2/abc/merged"}},
+        "HostConfig": {"Privileged": false, "SecurityOpt": ["seccomp=unconfined", "label=disable"]},
+        "AppArmorProfile": "docker-default",
+        "ProcessLabel": ""
+    });
+
+    let info = ContainerInfo::from_docker_inspect("docker", &inspect);
+    assert_eq!(info.name, "web");
+    assert_eq!(info.image, "nginx:1.25");
+    assert_eq!(info.labels["com.docker.compose.service"], "frontend");
+    assert_eq!(info.pid, Some(4242));
+    assert_eq!(info.started_at.unwrap().to_rfc3339(), "2024-05-01T12:00:00.123456789+00:00");
+    assert_eq!(info.rootfs, Some(PathBuf::from("/var/lib/docker/overlay2/abc/merged")));
+    assert_eq!(info.seccomp_profile.as_deref(), Some("unconfined"));
+    assert_eq!(info.apparmor_profile.as_deref(), Some("docker-default"));
+    assert_eq!(info.selinux_label, None);
+}
+
+#[test]
+fn test_info_from_oci_spec() {
+    let mut info = ContainerInfo {
+        runtime: "containerd".to_string(),
+        ..Default::default()
+    };
+    info.apply_oci_spec(&json!({
+        "root": {"path": "rootfs"},
+        "process": {
+            "capabilities": {"effective": ["CAP_CHOWN", "CAP_KILL"]},
+            "apparmorProfile": "cri-containerd.apparmor.d"
+        },
+        "linux": {
+            "uidMappings": [{"containerID": 0, "hostID": 100000, "size": 65536}],
+            "seccomp": {"defaultAction": "SCMP_ACT_ERRNO"},
+            "cgroupsPath": "kubepods-besteffort.slice:cri-containerd:4f1b"
+        }
+    }));
+
+    assert_eq!(info.rootfs, None);
+    assert_eq!(info.uid_map, vec![IdMap { container_id: 0, host_id: 100000, size: 65536 }]);
+    assert_eq!(info.capabilities, vec!["CAP_CHOWN", "CAP_KILL"]);
+    assert_eq!(info.seccomp_profile.as_deref(), Some("filter"));
+    assert_eq!(info.apparmor_profile.as_deref(), Some("cri-containerd.apparmor.d"));
+    assert_eq!(info.cgroup_path.as_deref(), Some("kubepods-besteffort.slice:cri-containerd:4f1b"));
+}
+
+#[test]
+fn test_info_for_own_process() {
+    let pid = std::process::id();
+    let info = ContainerInfo::for_pid(pid);
+
+    assert_eq!(info.runtime, "pid");
+    assert_eq!(info.pid, Some(pid));
+    assert_eq!(info.rootfs, Some(PathBuf::from(format!("/proc/{}/root", pid))));
+    assert!(!info.uid_map.is_empty());
+    assert!(info.started_at.is_some());
+    assert!(info.cgroup_path.is_some());
+}
+
+#[test]
+fn test_id_map_and_capability_parsing() {
+    let maps = parse_id_map("         0     100000      65536\n     65536          0          1\n");
+    assert_eq!(maps.len(), 2);
+    assert_eq!(maps[1], IdMap { container_id: 65536, host_id: 0, size: 1 });
+
+    assert_eq!(capability_names(0x1 | 1 << 21), vec!["CAP_CHOWN", "CAP_SYS_ADMIN"]);
+}
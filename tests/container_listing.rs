@@ -0,0 +1,102 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use crashcart::listing::{self, ListEntry};
+use crashcart::{BackendRegistry, ContainerSummary, RuntimeBackend};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// A backend that lists fixed containers and resolves PIDs from a table.
+struct FixedBackend {
+    name: &'static str,
+    containers: Vec<(&'static str, &'static str, Option<u32>)>,
+}
+
+#[async_trait]
+impl RuntimeBackend for FixedBackend {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        Ok(target.to_string())
+    }
+
+    async fn resolve_pid(&self, id: &str) -> Result<u32> {
+        match id {
+            "f00dfeedf00dfeedf00d" => Ok(std::process::id()),
+            _ => Err(anyhow!("no pid")),
+        }
+    }
+
+    async fn inspect(&self, _id: &str) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        Ok(self
+            .containers
+            .iter()
+            .map(|(id, name, pid)| ContainerSummary {
+                runtime: self.name.to_string(),
+                id: id.to_string(),
+                name: name.to_string(),
+                image: "alpine".to_string(),
+                state: "running".to_string(),
+                pid: *pid,
+            })
+            .collect())
+    }
+}
+
+#[tokio::test]
+async fn test_list_across_backends() {
+    let mut registry = BackendRegistry::new();
+    registry
+        .register(Arc::new(FixedBackend {
+            name: "first",
+            containers: vec![("f00dfeedf00dfeedf00d", "web", None), ("svc-b", "worker", Some(99999))],
+        }))
+        .register(Arc::new(FixedBackend {
+            name: "scan",
+            // The same containers again, by ID and by PID, plus a new one
+            containers: vec![("f00dfeedf00dfeedf00d", "", None), ("other", "", Some(99999)), ("db", "db", None)],
+        }));
+
+    let entries = listing::list_entries(&registry).await;
+    let ids: Vec<&str> = entries.iter().map(|e| e.container.id.as_str()).collect();
+    assert_eq!(ids, vec!["f00dfeedf00dfeedf00d", "svc-b", "db"]);
+
+    // The PID was filled in from resolve_pid, and our own process has no
+    // crashcart mount
+    assert_eq!(entries[0].container.pid, Some(std::process::id()));
+    assert_eq!(entries[0].mounted, Some(false));
+    assert_eq!(entries[2].mounted, None);
+    assert_eq!(entries[0].target(), "first://f00dfeedf00dfeedf00d");
+}
+
+#[test]
+fn test_table_rows() {
+    let entry = |id: &str, name: &str, pid: Option<u32>, mounted: Option<bool>| ListEntry {
+        container: ContainerSummary {
+            runtime: "docker".to_string(),
+            id: id.to_string(),
+            name: name.to_string(),
+            image: "nginx".to_string(),
+            state: "running".to_string(),
+            pid,
+        },
+        mounted,
+    };
+
+    let rows = listing::table_rows(&[
+        entry("4f1b0c5e9a7d2b3c4f1b0c5e9a7d2b3c", "web", Some(4242), Some(true)),
+        entry("prod/api/app", "api", None, None),
+    ]);
+    assert_eq!(rows[0], "RUNTIME  ID            NAME  IMAGE  PID   MOUNTED");
+    assert_eq!(rows[1], "docker   4f1b0c5e9a7d  web   nginx  4242  yes");
+    assert_eq!(rows[2], "docker   prod/api/app  api   nginx  -     -");
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use crashcart::credentials::{CapabilitySets, Credentials, Ids, UserSpec};
+use crashcart::exec::{self, ExecSpec};
+use std::path::PathBuf;
+
+const STATUS: &str = "\
+Name:\tnginx
+Umask:\t0022
+State:\tS (sleeping)
+Uid:\t0\t101\t101\t101
+Gid:\t0\t101\t101\t101
+Groups:\t101 4
+CapInh:\t0000000000000000
+CapPrm:\t0000000000000400
+CapEff:\t0000000000000400
+CapBnd:\t00000000a80425fb
+CapAmb:\t0000000000000400
+NoNewPrivs:\t1
+";
+
+fn target_root(name: &str) -> PathBuf {
+    let root = common::temp_path(name);
+    std::fs::create_dir_all(root.join("etc")).unwrap();
+    std::fs::write(
+        root.join("etc/passwd"),
+        "root:x:0:0:root:/root:/bin/sh\nnginx:x:101:101:nginx:/var/cache/nginx:/sbin/nologin\n",
+    )
+    .unwrap();
+    std::fs::write(root.join("etc/group"), "root:x:0:\nadm:x:4:nginx,syslog\nnginx:x:101:\nwww:x:33:\n").unwrap();
+    root
+}
+
+#[test]
+fn test_user_spec_parsing() {
+    let spec: UserSpec = "nginx:www".parse().unwrap();
+    assert_eq!(spec.user, "nginx");
+    assert_eq!(spec.group.as_deref(), Some("www"));
+    assert_eq!(spec.to_string(), "nginx:www");
+    assert_eq!("1000".parse::<UserSpec>().unwrap().group, None);
+
+    assert!("".parse::<UserSpec>().is_err());
+    assert!("nginx:".parse::<UserSpec>().is_err());
+}
+
+#[test]
+fn test_credentials_from_status() {
+    let credentials = Credentials::from_status(STATUS).unwrap();
+    // Real IDs of a setuid program stay as they are
+    let setuid = Ids {
+        real: 0,
+        effective: 101,
+        saved: 101,
+    };
+    assert_eq!((credentials.uid, credentials.gid), (setuid, setuid));
+    assert_eq!(credentials.uid.to_string(), "101 (real 0, saved 101)");
+    assert_eq!(credentials.groups, [101, 4]);
+    assert_eq!(
+        credentials.capabilities,
+        Some(CapabilitySets {
+            inheritable: 0,
+            permitted: 0x400,
+            effective: 0x400,
+            bounding: 0xa80425fb,
+            ambient: 0x400,
+        })
+    );
+
+    assert!(Credentials::from_status("Name:\tx\n").is_err());
+}
+
+#[test]
+fn test_credentials_for_user() {
+    let root = target_root("passwd");
+
+    let nginx = Credentials::for_user(&root, &"nginx".parse().unwrap()).unwrap();
+    assert_eq!((nginx.uid, nginx.gid, nginx.groups.as_slice()), (Ids::all(101), Ids::all(101), &[4][..]));
+    assert_eq!(nginx.uid.to_string(), "101");
+    assert_eq!(nginx.capabilities, None);
+
+    let explicit = Credentials::for_user(&root, &"nginx:www".parse().unwrap()).unwrap();
+    assert_eq!((explicit.uid, explicit.gid), (Ids::all(101), Ids::all(33)));
+    assert!(explicit.groups.is_empty());
+
+    // Numeric IDs work without a passwd entry
+    let numeric = Credentials::for_user(&root, &"4242".parse().unwrap()).unwrap();
+    assert_eq!((numeric.uid, numeric.gid), (Ids::all(4242), Ids::all(0)));
+    let numeric = Credentials::for_user(&root, &"101:7".parse().unwrap()).unwrap();
+    assert_eq!((numeric.uid, numeric.gid), (Ids::all(101), Ids::all(7)));
+
+    assert!(Credentials::for_user(&root, &"postgres".parse().unwrap()).is_err());
+    assert!(Credentials::for_user(&root, &"nginx:staff".parse().unwrap()).is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_command_runs_with_credentials() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Switching credentials needs root, skipping");
+        return;
+    }
+
+    let mut spec = ExecSpec::new(vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        "id -u; id -g; id -G; grep -E '^Cap(Eff|Amb)' /proc/self/status".to_string(),
+    ]);
+    spec.credentials = Some(Credentials {
+        uid: Ids::all(65534),
+        gid: Ids::all(65534),
+        groups: vec![4],
+        capabilities: Some(CapabilitySets {
+            inheritable: 1 << 13,
+            permitted: 1 << 13,
+            effective: 1 << 13,
+            bounding: (1 << 13) | (1 << 19),
+            ambient: 1 << 13,
+        }),
+    });
+
+    let output = exec::output(std::process::id(), &spec).await.unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "65534\n65534\n65534 4\nCapEff:\t0000000000002000\nCapAmb:\t0000000000002000\n"
+    );
+}
+
+#[tokio::test]
+async fn test_command_keeps_real_and_saved_ids() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Switching credentials needs root, skipping");
+        return;
+    }
+
+    // Not through a shell: sh resets the effective ID when it differs from the real one.
+    // The kernel sets the saved ID to the effective one on exec, so only the real ID shows.
+    let spec_for = |ids: Ids| {
+        let mut spec = ExecSpec::new(
+            ["grep", "-E", "^[UG]id:", "/proc/self/status"].iter().map(|arg| arg.to_string()).collect(),
+        );
+        spec.credentials = Some(Credentials {
+            uid: ids,
+            gid: ids,
+            groups: vec![],
+            capabilities: None,
+        });
+        spec
+    };
+
+    let ids = Ids {
+        real: 0,
+        effective: 101,
+        saved: 101,
+    };
+    let output = exec::output(std::process::id(), &spec_for(ids)).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Uid:\t0\t101\t101\t101\nGid:\t0\t101\t101\t101\n");
+}
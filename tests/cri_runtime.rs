@@ -0,0 +1,150 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use crashcart::cri::api::*;
+use crashcart::cri::{decode_grpc_frame, encode_grpc_frame};
+use crashcart::CriClient;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Start a stand-in CRI runtime with one ready pod `prod/api-7f9c` running
+/// an `app` container, plus a stopped container in an old sandbox.
+fn start_fake_cri(name: &str) -> PathBuf {
+    common::serve(name, |stream| async move {
+        let mut connection = h2::server::handshake(stream).await.unwrap();
+        while let Some(request) = connection.accept().await {
+            let (request, respond) = request.unwrap();
+            tokio::spawn(handle_call(request, respond));
+        }
+    })
+}
+
+async fn handle_call(
+    request: http::Request<h2::RecvStream>,
+    mut respond: h2::server::SendResponse<Bytes>,
+) {
+    let method = request.uri().path().rsplit('/').next().unwrap().to_string();
+    let mut body = request.into_body();
+    let mut data = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    let data = data.freeze();
+
+    let reply = match method.as_str() {
+        "ListPodSandbox" => encode_grpc_frame(&ListPodSandboxResponse {
+            items: vec![sandbox("sb-old", 1, 100), sandbox("sb-new", SANDBOX_READY, 200)],
+        }),
+        "PodSandboxStatus" => {
+            let req: PodSandboxStatusRequest = decode_grpc_frame(data).unwrap();
+            let state = if req.pod_sandbox_id == "sb-new" { SANDBOX_READY } else { 1 };
+            encode_grpc_frame(&PodSandboxStatusResponse {
+                status: Some(PodSandboxStatus {
+                    id: req.pod_sandbox_id,
+                    state,
+                    ..Default::default()
+                }),
+                info: HashMap::new(),
+            })
+        }
+        "ListContainers" => {
+            let req: ListContainersRequest = decode_grpc_frame(data).unwrap();
+            let all = vec![
+                container("0ld0ld", "sb-old", "app", 2),
+                container("c0ffee1234", "sb-new", "app", CONTAINER_RUNNING),
+                container("c0ffee5678", "sb-new", "sidecar", CONTAINER_RUNNING),
+            ];
+            let pod = req.filter.map(|f| f.pod_sandbox_id).unwrap_or_default();
+            encode_grpc_frame(&ListContainersResponse {
+                containers: all
+                    .into_iter()
+                    .filter(|c| pod.is_empty() || c.pod_sandbox_id == pod)
+                    .collect(),
+            })
+        }
+        "ContainerStatus" => {
+            let req: ContainerStatusRequest = decode_grpc_frame(data).unwrap();
+            assert!(req.verbose);
+            let mut info = HashMap::new();
+            info.insert("info".to_string(), r#"{"pid":31337,"sandboxID":"sb-new"}"#.to_string());
+            encode_grpc_frame(&ContainerStatusResponse {
+                status: Some(ContainerStatus {
+                    id: req.container_id,
+                    state: CONTAINER_RUNNING,
+                    ..Default::default()
+                }),
+                info,
+            })
+        }
+        _ => {
+            let response = http::Response::builder()
+                .header("content-type", "application/grpc")
+                .header("grpc-status", "12")
+                .header("grpc-message", "unimplemented")
+                .body(())
+                .unwrap();
+            respond.send_response(response, true).unwrap();
+            return;
+        }
+    };
+
+    let response = http::Response::builder()
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+    let mut send = respond.send_response(response, false).unwrap();
+    send.send_data(reply, false).unwrap();
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("grpc-status", "0".parse().unwrap());
+    send.send_trailers(trailers).unwrap();
+}
+
+fn sandbox(id: &str, state: i32, created_at: i64) -> PodSandbox {
+    PodSandbox {
+        id: id.to_string(),
+        metadata: Some(PodSandboxMetadata {
+            name: "api-7f9c".to_string(),
+            namespace: "prod".to_string(),
+            ..Default::default()
+        }),
+        state,
+        created_at,
+        ..Default::default()
+    }
+}
+
+fn container(id: &str, sandbox: &str, name: &str, state: i32) -> Container {
+    Container {
+        id: id.to_string(),
+        pod_sandbox_id: sandbox.to_string(),
+        metadata: Some(ContainerMetadata {
+            name: name.to_string(),
+            attempt: 0,
+        }),
+        state,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_cri_resolves_namespace_pod_container() {
+    let client = CriClient::new(start_fake_cri("cri-pod"));
+
+    let id = client.resolve("prod/api-7f9c/app").await.unwrap();
+    assert_eq!(id, "c0ffee1234");
+    assert_eq!(client.get_pid(&id).await.unwrap(), 31337);
+
+    assert!(client.resolve("prod/api-7f9c/missing").await.is_err());
+    assert!(client.resolve("staging/api-7f9c/app").await.is_err());
+}
+
+#[tokio::test]
+async fn test_cri_resolves_container_id_prefix() {
+    let client = CriClient::new(start_fake_cri("cri-prefix"));
+
+    assert_eq!(client.resolve("c0ffee5").await.unwrap(), "c0ffee5678");
+
+    let err = client.resolve("c0ffee").await.unwrap_err();
+    assert!(err.to_string().contains("ambiguous"));
+    assert!(client.resolve("deadbeef").await.is_err());
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use crashcart::DockerClient;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// Start a fake Docker daemon on a unix socket that knows a single running
+/// container called `web`.
+fn start_fake_daemon(name: &str) -> PathBuf {
+    common::serve(name, handle_connection)
+}
+
+async fn handle_connection(mut stream: UnixStream) {
+    let Some((head, body)) = common::read_request(&mut stream).await else {
+        return;
+    };
+
+    let request_line = head.lines().next().unwrap().to_string();
+    let response = match request_line.as_str() {
+        "GET /_ping HTTP/1.1" => ok("OK"),
+        "GET /containers/web/json HTTP/1.1" => {
+            // Exercise chunked transfer decoding
+            let json = r#"{"Id":"abc123","State":{"Pid":4242}}"#;
+            format!(
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                json.len(),
+                json
+            )
+        }
+        "GET /containers/stopped/json HTTP/1.1" => ok(r#"{"Id":"def456","State":{"Pid":0}}"#),
+        "GET /containers/odd%2F..%3Fname/json HTTP/1.1" => ok(r#"{"Id":"0dd789","State":{"Pid":4343}}"#),
+        "POST /containers/web/exec HTTP/1.1" => {
+            let create: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(create["Cmd"][0], "cat");
+            ok(r#"{"Id":"exec1"}"#)
+        }
+        "POST /exec/exec1/start HTTP/1.1" => {
+            assert!(head.contains("Upgrade: tcp"));
+            stream
+                .write_all(b"HTTP/1.1 101 UPGRADED\r\nContent-Type: application/vnd.docker.raw-stream\r\nConnection: Upgrade\r\nUpgrade: tcp\r\n\r\n")
+                .await
+                .unwrap();
+
+            // Echo stdin back on stdout, then write to stderr
+            let mut input = Vec::new();
+            stream.read_to_end(&mut input).await.unwrap();
+            stream.write_all(&frame(1, &input)).await.unwrap();
+            stream.write_all(&frame(2, b"oops\n")).await.unwrap();
+            return;
+        }
+        "GET /exec/exec1/json HTTP/1.1" => ok(r#"{"Running":false,"ExitCode":3}"#),
+        _ => format!(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\n\r\n{}",
+            r#"{"message":"No such container"}"#.len(),
+            r#"{"message":"No such container"}"#
+        ),
+    };
+
+    stream.write_all(response.as_bytes()).await.unwrap();
+}
+
+fn ok(body: &str) -> String {
+    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn frame(stream_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![stream_type, 0, 0, 0];
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[tokio::test]
+async fn test_docker_api_inspect_and_pid() {
+    let client = DockerClient::new(start_fake_daemon("docker-pid"));
+
+    client.ping().await.unwrap();
+    let info = client.inspect("web").await.unwrap();
+    assert_eq!(info["Id"], "abc123");
+    assert_eq!(client.get_pid("web").await.unwrap(), 4242);
+
+    assert!(client.get_pid("stopped").await.is_err());
+    // Names stay one path segment
+    assert_eq!(client.inspect("odd/..?name").await.unwrap()["Id"], "0dd789");
+    let err = client.inspect("missing").await.unwrap_err();
+    assert!(err.to_string().contains("No such container"));
+}
+
+#[tokio::test]
+async fn test_docker_api_exec_demultiplexes_streams() {
+    let client = DockerClient::new(start_fake_daemon("docker-exec"));
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let code = client
+        .exec_with_io("web", &["cat".to_string()], false, &b"ping\n"[..], &mut stdout, &mut stderr)
+        .await
+        .unwrap();
+
+    assert_eq!(code, 3);
+    assert_eq!(stdout, b"ping\n");
+    assert_eq!(stderr, b"oops\n");
+}
+
+#[test]
+fn test_docker_client_from_env() {
+    std::env::set_var("DOCKER_HOST", "unix:///tmp/custom-docker.sock");
+    let client = DockerClient::from_env().unwrap();
+    assert_eq!(client.socket(), std::path::Path::new("/tmp/custom-docker.sock"));
+
+    std::env::set_var("DOCKER_HOST", "tcp://127.0.0.1:2375");
+    assert!(DockerClient::from_env().is_none());
+    std::env::remove_var("DOCKER_HOST");
+}
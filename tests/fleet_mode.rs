@@ -0,0 +1,112 @@
+use anyhow::anyhow;
+use crashcart::fleet::{self, CommandOutput, FleetResult};
+use crashcart::{ContainerInfo, ContainerRuntime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn replicas(count: u32) -> Vec<(ContainerRuntime, ContainerInfo)> {
+    (1..=count)
+        .map(|pid| {
+            let info = ContainerInfo {
+                name: format!("api-{}", pid),
+                ..Default::default()
+            };
+            (ContainerRuntime::Pid { pid }, info)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_run_each_bounds_concurrency() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    let results = fleet::run_each(replicas(12), 3, |runtime| {
+        let in_flight = in_flight.clone();
+        let peak = peak.clone();
+        async move {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let pid = runtime.get_pid().await?;
+            if pid == 5 {
+                return Err(anyhow!("mount failed"));
+            }
+            Ok(CommandOutput {
+                pid,
+                exit_code: Some(0),
+                stdout: format!("nameserver 10.0.0.{}\n", pid),
+                stderr: String::new(),
+            })
+        }
+    })
+    .await;
+
+    assert_eq!(peak.load(Ordering::SeqCst), 3);
+    assert_eq!(results.len(), 12);
+    assert_eq!(results[0].target, "pid://1");
+    assert_eq!(results[11].stdout, "nameserver 10.0.0.12\n");
+    assert!(!results[4].succeeded());
+    assert_eq!(results[4].error.as_deref(), Some("mount failed"));
+    assert_eq!(results.iter().filter(|r| r.succeeded()).count(), 11);
+}
+
+#[tokio::test]
+async fn test_run_each_reports_panicked_targets() {
+    let results = fleet::run_each(replicas(3), 2, |runtime| async move {
+        let pid = runtime.get_pid().await?;
+        if pid == 2 {
+            panic!("probe bug");
+        }
+        Ok(CommandOutput {
+            pid,
+            exit_code: Some(0),
+            ..Default::default()
+        })
+    })
+    .await;
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[1].name, "api-2");
+    assert!(!results[1].succeeded());
+    assert!(results[1].error.as_deref().unwrap().contains("panicked"), "{:?}", results[1].error);
+    assert!(results[0].succeeded() && results[2].succeeded());
+}
+
+#[test]
+fn test_fleet_table_rows() {
+    let results = vec![
+        FleetResult {
+            target: "docker://4f1b".to_string(),
+            name: "api-1".to_string(),
+            pid: Some(4242),
+            exit_code: Some(1),
+            stdout: "LISTEN 0 128 *:8080\nLISTEN 0 128 *:9090\n".to_string(),
+            stderr: "warning\n".to_string(),
+            error: None,
+        },
+        FleetResult {
+            target: "docker://9a7d".to_string(),
+            name: "api-2".to_string(),
+            pid: None,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: Some("not running".to_string()),
+        },
+    ];
+
+    assert_eq!(
+        fleet::table_rows(&results),
+        vec![
+            "TARGET         NAME   PID   EXIT  OUTPUT",
+            "docker://4f1b  api-1  4242  1     LISTEN 0 128 *:8080",
+            "                                  LISTEN 0 128 *:9090",
+            "                                  [stderr] warning",
+            "docker://9a7d  api-2  -     -     [error] not running",
+        ]
+    );
+}
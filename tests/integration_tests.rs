@@ -1,5 +1,38 @@
 use std::path::Path;
-use crashcart::{ContainerRuntime, ImageManager};
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use crashcart::{BackendRegistry, ContainerRuntime, ImageManager, RuntimeBackend};
+use serde_json::{json, Value};
+
+/// An in-house runtime that knows a single container called `svc-a`.
+struct InHouseBackend;
+
+#[async_trait]
+impl RuntimeBackend for InHouseBackend {
+    fn name(&self) -> &str {
+        "inhouse"
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        match target {
+            "svc-a" => Ok("inhouse-0001".to_string()),
+            _ => Err(anyhow!("unknown service")),
+        }
+    }
+
+    async fn resolve_pid(&self, _id: &str) -> Result<u32> {
+        Ok(4321)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<Value> {
+        Ok(json!({ "id": id }))
+    }
+}
 
 #[tokio::test]
 async fn test_container_detection_with_invalid_id() {
@@ -24,6 +57,39 @@ async fn test_container_detection_with_pid() {
     }
 }
 
+#[tokio::test]
+async fn test_container_detection_with_custom_backend() {
+    let mut registry = BackendRegistry::new();
+    registry.register(Arc::new(InHouseBackend));
+
+    let runtime = ContainerRuntime::detect_with(&registry, "svc-a").await.unwrap();
+    assert_eq!(runtime.runtime_name(), "inhouse");
+    assert_eq!(runtime.get_pid().await.unwrap(), 4321);
+    assert_eq!(runtime.inspect().await.unwrap()["id"], "inhouse-0001");
+    assert!(runtime.exec_command(&[]).await.is_err());
+
+    assert!(ContainerRuntime::detect_with(&registry, "svc-b").await.is_err());
+}
+
+#[tokio::test]
+async fn test_container_detection_with_scheme() {
+    let mut registry = BackendRegistry::new();
+    registry.register(Arc::new(InHouseBackend));
+
+    let runtime = ContainerRuntime::detect_with(&registry, "inhouse://svc-a").await.unwrap();
+    assert_eq!(runtime.runtime_name(), "inhouse");
+
+    match ContainerRuntime::detect_with(&registry, "pid://1234").await.unwrap() {
+        ContainerRuntime::Pid { pid } => assert_eq!(pid, 1234),
+        _ => panic!("Expected PID runtime"),
+    }
+
+    assert!(ContainerRuntime::detect_with(&registry, "pid://abc").await.is_err());
+
+    let err = ContainerRuntime::detect_with(&registry, "nosuchruntime://x").await.unwrap_err();
+    assert!(err.to_string().contains("Unknown target scheme"));
+}
+
 #[test]
 fn test_image_manager_with_nonexistent_file() {
     let result = ImageManager::new(Path::new("nonexistent.img"));
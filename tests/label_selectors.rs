@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use crashcart::{BackendRegistry, ContainerInfo, ContainerRuntime, ContainerSummary, RuntimeBackend, Selector};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A runtime with three labelled containers: two replicas of `api` in
+/// staging and the `web` service of a compose project.
+struct LabelledBackend;
+
+fn labels_of(id: &str) -> BTreeMap<String, String> {
+    let pairs: &[(&str, &str)] = match id {
+        "api-1" | "api-2" => &[("app", "api"), ("env", "staging")],
+        "shop-web-1" => &[
+            ("com.docker.compose.project", "shop"),
+            ("com.docker.compose.service", "web"),
+            ("env", "staging"),
+        ],
+        _ => &[],
+    };
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[async_trait]
+impl RuntimeBackend for LabelledBackend {
+    fn name(&self) -> &str {
+        "labelled"
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        Err(anyhow!("{} is not a name", target))
+    }
+
+    async fn resolve_pid(&self, _id: &str) -> Result<u32> {
+        Err(anyhow!("not needed"))
+    }
+
+    async fn inspect(&self, _id: &str) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    async fn info(&self, id: &str) -> Result<ContainerInfo> {
+        Ok(ContainerInfo {
+            runtime: self.name().to_string(),
+            id: id.to_string(),
+            labels: labels_of(id),
+            ..Default::default()
+        })
+    }
+
+    async fn list(&self) -> Result<Vec<ContainerSummary>> {
+        Ok(["api-1", "api-2", "shop-web-1"]
+            .iter()
+            .map(|id| ContainerSummary {
+                runtime: self.name().to_string(),
+                id: id.to_string(),
+                name: id.to_string(),
+                image: String::new(),
+                state: "running".to_string(),
+                pid: None,
+            })
+            .collect())
+    }
+}
+
+fn registry() -> BackendRegistry {
+    let mut registry = BackendRegistry::new();
+    registry.register(Arc::new(LabelledBackend));
+    registry
+}
+
+fn container_id(runtime: &ContainerRuntime) -> &str {
+    match runtime {
+        ContainerRuntime::Container { id, .. } => id,
+        _ => panic!("Expected a container"),
+    }
+}
+
+#[test]
+fn test_selector_parsing_and_matching() {
+    let selector: Selector = "app=api, env!=prod,tier".parse().unwrap();
+    assert_eq!(selector.to_string(), "app=api,env!=prod,tier");
+
+    let mut labels = labels_of("api-1");
+    assert!(!selector.matches(&labels));
+    labels.insert("tier".to_string(), "backend".to_string());
+    assert!(selector.matches(&labels));
+
+    assert!("".parse::<Selector>().is_err());
+    assert!("=api".parse::<Selector>().is_err());
+    assert!(Selector::parse_compose("shop").is_err());
+}
+
+#[tokio::test]
+async fn test_select_single_container() {
+    let selector: Selector = "app=api,env=staging".parse().unwrap();
+    let err = ContainerRuntime::select_with(&registry(), &selector).await.unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("matches 2 containers"), "{}", message);
+    assert!(message.contains("labelled://api-1") && message.contains("labelled://api-2"));
+
+    let selector: Selector = "env=staging,app!=api".parse().unwrap();
+    let runtime = ContainerRuntime::select_with(&registry(), &selector).await.unwrap();
+    assert_eq!(container_id(&runtime), "shop-web-1");
+
+    let selector: Selector = "app=missing".parse().unwrap();
+    assert!(ContainerRuntime::select_with(&registry(), &selector).await.is_err());
+}
+
+#[tokio::test]
+async fn test_compose_target() {
+    let runtime = ContainerRuntime::detect_with(&registry(), "compose://shop/web").await.unwrap();
+    assert_eq!(runtime.runtime_name(), "labelled");
+    assert_eq!(container_id(&runtime), "shop-web-1");
+
+    assert!(ContainerRuntime::detect_with(&registry(), "compose://shop/db").await.is_err());
+}
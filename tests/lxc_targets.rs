@@ -0,0 +1,118 @@
+mod common;
+
+use crashcart::lxc::{LxcBackend, LxdBackend};
+use crashcart::RuntimeBackend;
+use common::{fake_cgroup, fake_host, fake_process};
+use std::fs;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+
+/// Start a stand-in LXD daemon that knows a running instance `web` in the
+/// default project, `db` in the `infra` project and a stopped `old`.
+fn start_fake_lxd(name: &str) -> PathBuf {
+    common::serve(name, handle_connection)
+}
+
+async fn handle_connection(mut stream: UnixStream) {
+    let Some((head, _)) = common::read_request(&mut stream).await else {
+        return;
+    };
+    let request_line = head.lines().next().unwrap().to_string();
+    let (status, body) = match request_line.as_str() {
+        "GET /1.0 HTTP/1.1" => (200, sync(r#"{"api_version":"1.0"}"#)),
+        "GET /1.0/instances/web/state HTTP/1.1" => (200, sync(r#"{"status":"Running","pid":7001}"#)),
+        "GET /1.0/instances/db/state?project=infra HTTP/1.1" => {
+            (200, sync(r#"{"status":"Running","pid":7002}"#))
+        }
+        "GET /1.0/instances/odd%3Fname/state?project=a%26b HTTP/1.1" => {
+            (200, sync(r#"{"status":"Running","pid":7003}"#))
+        }
+        "GET /1.0/instances/old/state HTTP/1.1" => (200, sync(r#"{"status":"Stopped","pid":0}"#)),
+        "GET /1.0/instances?recursion=2&all-projects=true HTTP/1.1" => (
+            200,
+            sync(
+                r#"[{"name":"web","project":"default","status":"Running","config":{"image.description":"Ubuntu 24.04"},"state":{"pid":7001}},
+                    {"name":"db","project":"infra","status":"Running","config":{},"state":{"pid":7002}}]"#,
+            ),
+        ),
+        _ => (404, r#"{"type":"error","error":"Instance not found","error_code":404}"#.to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await.unwrap();
+}
+
+fn sync(metadata: &str) -> String {
+    format!(r#"{{"type":"sync","status_code":200,"metadata":{}}}"#, metadata)
+}
+
+#[tokio::test]
+async fn test_lxd_instance_pid() {
+    let socket = start_fake_lxd("lxd-instance");
+    let backend = LxdBackend::with_socket("lxd", &socket);
+
+    assert!(backend.is_available().await);
+    assert_eq!(backend.detect("web").await.unwrap(), "web");
+    assert_eq!(backend.resolve_pid("web").await.unwrap(), 7001);
+    assert_eq!(backend.resolve_pid("infra/db").await.unwrap(), 7002);
+
+    let stopped = backend.resolve_pid("old").await.unwrap_err();
+    assert!(stopped.to_string().contains("not running"));
+    let missing = backend.detect("missing").await.unwrap_err();
+    assert!(missing.to_string().contains("Instance not found"));
+
+    // Names can't walk out of the instance's path or add to the query
+    let err = backend.resolve_pid("infra/../web").await.unwrap_err();
+    assert!(err.to_string().contains("Invalid instance name"), "{}", err);
+    assert_eq!(backend.resolve_pid("a&b/odd?name").await.unwrap(), 7003);
+
+    let instances = backend.list().await.unwrap();
+    assert_eq!(instances.len(), 2);
+    assert_eq!(instances[0].image, "Ubuntu 24.04");
+    assert_eq!(instances[1].id, "infra/db");
+    assert_eq!(instances[1].pid, Some(7002));
+
+    fs::remove_file(socket).unwrap();
+}
+
+#[tokio::test]
+async fn test_lxd_unavailable_without_socket() {
+    let socket = common::temp_path("lxd-missing").with_extension("sock");
+    let backend = LxdBackend::with_socket("incus", socket);
+    assert!(!backend.is_available().await);
+}
+
+#[tokio::test]
+async fn test_lxc_payload_init_pid() {
+    let (base, cgroup_root, proc_root) = fake_host("lxc-payload");
+    let run_dir = base.join("run-lxc");
+    fs::create_dir_all(&run_dir).unwrap();
+
+    // The monitor sits in its own cgroup next to the payload
+    fake_process(&proc_root, 800, 1, "800");
+    fake_process(&proc_root, 801, 800, "801 1");
+    fake_process(&proc_root, 802, 801, "802 40");
+    fake_cgroup(&cgroup_root, "lxc.monitor.build", &[800]);
+    fake_cgroup(&cgroup_root, "lxc.payload.build", &[802, 801]);
+    fake_cgroup(&cgroup_root, "lxc.payload.build/init.scope", &[]);
+
+    let backend = LxcBackend::with_roots(&cgroup_root, &proc_root, &run_dir);
+    assert!(backend.is_available().await);
+    assert_eq!(backend.detect("build").await.unwrap(), "build");
+    assert_eq!(backend.resolve_pid("build").await.unwrap(), 801);
+    let err = backend.resolve_pid("..").await.unwrap_err();
+    assert!(err.to_string().contains("Invalid LXC container name"), "{}", err);
+
+    let containers = backend.list().await.unwrap();
+    assert_eq!(containers.len(), 1);
+    assert_eq!(containers[0].name, "build");
+    assert_eq!(containers[0].pid, Some(801));
+
+    fs::remove_dir_all(base).unwrap();
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use crashcart::{ImageManager, MountManager};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn mounts(pid: u32) -> Vec<String> {
+    std::fs::read_to_string(format!("/proc/{}/mounts", pid))
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1).map(str::to_string))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_target_root_stays_out_of_peer_namespaces() {
+    if unsafe { libc::geteuid() } != 0 || !Path::new("/usr/sbin/mkfs.ext4").exists() {
+        eprintln!("Needs root and mkfs.ext4, skipping");
+        return;
+    }
+
+    // An image with a /target directory for the target's root
+    let dir = common::temp_path("propagation");
+    std::fs::create_dir_all(dir.join("contents/target")).unwrap();
+    let image = dir.join("crashcart.img");
+    let status = Command::new("mkfs.ext4")
+        .args(["-q", "-d"])
+        .arg(dir.join("contents"))
+        .arg(&image)
+        .arg("4M")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // A target whose mounts are all shared, and a peer namespace copied
+    // from it, like a container sharing /dev with its sidecar
+    let mut target = Command::new("unshare")
+        .args(["-m", "--propagation", "private", "sh", "-c"])
+        .arg("mount --make-rshared / && unshare -m --propagation unchanged sleep 30 & echo $!; exec sleep 30")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    std::io::BufRead::read_line(&mut std::io::BufReader::new(target.stdout.take().unwrap()), &mut line).unwrap();
+    let peer: u32 = line.trim().parse().unwrap();
+    let namespace = |pid: u32| std::fs::read_link(format!("/proc/{}/ns/mnt", pid)).ok();
+    while namespace(peer) == namespace(target.id()) {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let mount_manager = MountManager::new();
+    let mut image_manager = ImageManager::new(&image).unwrap();
+    mount_manager.mount(target.id(), &mut image_manager).await.unwrap();
+    let target_mounts = mounts(target.id());
+    let peer_mounts = mounts(peer);
+    mount_manager.unmount(target.id(), &mut image_manager).await.unwrap();
+    let _ = Command::new("kill").arg(peer.to_string()).status();
+    target.kill().unwrap();
+    target.wait().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(target_mounts.iter().any(|path| path == "/dev/crashcart/target"));
+    assert!(!peer_mounts.iter().any(|path| path.starts_with("/dev/crashcart/target")), "{:?}", peer_mounts);
+}
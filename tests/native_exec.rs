@@ -0,0 +1,122 @@
+mod common;
+
+use crashcart::exec::{self, ExecSpec};
+use crashcart::info::IdMap;
+use crashcart::namespace::{TargetNamespaces, UserNamespace};
+use crashcart::Namespace;
+
+fn shell(script: &str) -> ExecSpec {
+    ExecSpec::new(vec!["sh".to_string(), "-c".to_string(), script.to_string()])
+}
+
+#[test]
+fn test_namespace_names_and_order() {
+    let names: Vec<String> = Namespace::ALL.iter().map(|ns| ns.to_string()).collect();
+    assert_eq!(names, ["user", "cgroup", "ipc", "uts", "net", "pid", "mnt", "time"]);
+
+    assert_eq!("mount".parse::<Namespace>().unwrap(), Namespace::Mnt);
+    assert_eq!(" net".parse::<Namespace>().unwrap(), Namespace::Net);
+    assert!("network-ish".parse::<Namespace>().is_err());
+}
+
+#[test]
+fn test_own_namespaces_are_not_rejoined() {
+    let namespaces = TargetNamespaces::open(std::process::id(), &Namespace::ALL).unwrap();
+    assert_eq!(namespaces.namespaces().count(), 0);
+
+    assert!(TargetNamespaces::open(u32::MAX >> 1, &Namespace::ALL).is_err());
+}
+
+#[tokio::test]
+async fn test_output_and_exit_status() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Dropping privileges needs root, skipping");
+        return;
+    }
+
+    let spec = shell("echo out; echo err >&2; echo $CRASHCART_TEST_VAR; exit 3").env("CRASHCART_TEST_VAR", "set");
+    let output = exec::output(std::process::id(), &spec).await.unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "out\nset\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "err\n");
+}
+
+#[tokio::test]
+async fn test_killed_command_reports_signal() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Dropping privileges needs root, skipping");
+        return;
+    }
+
+    let output = exec::output(std::process::id(), &shell("kill -KILL $$")).await.unwrap();
+    assert_eq!(output.status.code(), None);
+    assert_eq!(exec::exit_code(output.status), 128 + 9);
+}
+
+#[tokio::test]
+async fn test_missing_program_is_an_error() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Dropping privileges needs root, skipping");
+        return;
+    }
+
+    let spec = ExecSpec::new(vec!["/nonexistent/crashcart-tool".to_string()]);
+    let err = exec::output(std::process::id(), &spec).await.unwrap_err();
+    assert!(err.to_string().contains("Failed to execute /nonexistent/crashcart-tool"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_starts_in_working_directory() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Dropping privileges needs root, skipping");
+        return;
+    }
+
+    let dir = common::temp_path("cwd");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut spec = shell("pwd -P");
+    spec.cwd = Some(dir.clone());
+    let output = exec::output(std::process::id(), &spec).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), dir.canonicalize().unwrap().to_str().unwrap());
+
+    // A directory that has gone is not an error
+    std::fs::remove_dir(&dir).unwrap();
+    assert!(exec::output(std::process::id(), &spec).await.unwrap().status.success());
+}
+
+#[test]
+fn test_user_namespace_credentials() {
+    let host = UserNamespace::parse("         0          0 4294967295\n", "         0          0 4294967295\n");
+    assert!(host.is_initial());
+
+    // Rootless podman: container root is the user, the rest come from subuid
+    let rootless = UserNamespace::parse(
+        "         0       1000          1\n         1     100000      65536\n",
+        "         0       1000          1\n         1     100000      65536\n",
+    );
+    assert!(!rootless.is_initial());
+    assert_eq!(rootless.credentials(), Some((0, 0)));
+    assert_eq!(rootless.host_root(), Some((1000, 1000)));
+    assert_eq!(IdMap::to_host(&rootless.uid_map, 33), Some(100032));
+    assert_eq!(IdMap::from_host(&rootless.uid_map, 100032), Some(33));
+    assert_eq!(IdMap::to_host(&rootless.uid_map, 70000), None);
+
+    // A namespace that leaves root unmapped runs tools as its lowest ID
+    let unprivileged = UserNamespace::parse("1000 231072 65536\n", "1000 231072 65536\n");
+    assert_eq!(unprivileged.credentials(), Some((1000, 1000)));
+    assert_eq!(unprivileged.host_root(), None);
+}
+
+#[test]
+fn test_namespace_selection() {
+    use Namespace::*;
+
+    assert_eq!(Namespace::selection(&[], &[]), Namespace::ALL);
+    assert_eq!(Namespace::selection(&[Pid, Net], &[]), [Net, Pid]);
+    assert_eq!(
+        Namespace::selection(&[], &[Mnt, Time]),
+        [User, Cgroup, Ipc, Uts, Net, Pid]
+    );
+    assert_eq!(Namespace::selection(&[Net, Mnt], &[Mnt]), [Net]);
+}
@@ -0,0 +1,61 @@
+use crashcart::exec::{self, ExecSpec};
+use crashcart::privileges::PrivilegeProfile;
+
+const CAP_NET_ADMIN: u64 = 1 << 12;
+const CAP_NET_RAW: u64 = 1 << 13;
+const CAP_SYS_PTRACE: u64 = 1 << 19;
+const CAP_SYS_ADMIN: u64 = 1 << 21;
+
+#[test]
+fn test_profiles() {
+    assert_eq!(PrivilegeProfile::default(), PrivilegeProfile::Observe);
+    assert_eq!("network".parse::<PrivilegeProfile>().unwrap(), PrivilegeProfile::Network);
+    assert_eq!(PrivilegeProfile::Full.to_string(), "full");
+    assert!("admin".parse::<PrivilegeProfile>().is_err());
+
+    assert_eq!(PrivilegeProfile::Observe.capabilities(), CAP_SYS_PTRACE | CAP_NET_RAW);
+    let network = PrivilegeProfile::Network.capabilities();
+    assert_eq!(network & (CAP_SYS_PTRACE | CAP_NET_RAW | CAP_NET_ADMIN), CAP_SYS_PTRACE | CAP_NET_RAW | CAP_NET_ADMIN);
+    assert_eq!(network & CAP_SYS_ADMIN, 0);
+    assert_ne!(PrivilegeProfile::Full.capabilities() & CAP_SYS_ADMIN, 0);
+
+    // Full keeps every capability the kernel has, named or not
+    let last_cap: u32 = std::fs::read_to_string("/proc/sys/kernel/cap_last_cap").unwrap().trim().parse().unwrap();
+    assert_eq!(PrivilegeProfile::Full.capabilities(), u64::MAX >> (63 - last_cap));
+}
+
+#[tokio::test]
+async fn test_observe_profile_limits_the_command() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Dropping privileges needs root, skipping");
+        return;
+    }
+
+    let spec = ExecSpec::new(vec![
+        "grep".to_string(),
+        "-E".to_string(),
+        "^(CapEff|CapBnd|NoNewPrivs)".to_string(),
+        "/proc/self/status".to_string(),
+    ]);
+    let output = exec::output(std::process::id(), &spec).await.unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "CapEff:\t0000000000082000\nCapBnd:\t0000000000082000\nNoNewPrivs:\t1\n"
+    );
+}
+
+#[tokio::test]
+async fn test_full_profile_keeps_the_bounding_set() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Dropping privileges needs root, skipping");
+        return;
+    }
+
+    let bounding = |status: &str| status.lines().find(|line| line.starts_with("CapBnd:")).map(str::to_string);
+    let ours = bounding(&std::fs::read_to_string("/proc/self/status").unwrap());
+
+    let mut spec = ExecSpec::new(vec!["cat".to_string(), "/proc/self/status".to_string()]);
+    spec.privileges = PrivilegeProfile::Full;
+    let output = exec::output(std::process::id(), &spec).await.unwrap();
+    assert_eq!(bounding(&String::from_utf8_lossy(&output.stdout)), ours);
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use crashcart::recording::{Cast, Recorder, Recording, SessionInfo};
+use crashcart::Config;
+use regex::Regex;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+fn session() -> SessionInfo {
+    SessionInfo {
+        target: "docker://4f1b0c5e9a7d".to_string(),
+        runtime: "docker".to_string(),
+        user: "alice".to_string(),
+        image_digest: Some("sha256:ab12".to_string()),
+        command: vec!["bash".to_string()],
+    }
+}
+
+#[test]
+fn test_recorder_writes_asciicast() {
+    let dir = common::temp_path("recording");
+    let recording = Recording {
+        path: dir.join("nested/session.cast"),
+        info: session(),
+        input: true,
+    };
+
+    let mut recorder = Recorder::create(&recording, (40, 120)).unwrap();
+    recorder.output(b"$ ");
+    recorder.input(b"ls\r");
+    // A character split across two reads is recorded once whole
+    recorder.output(b"caf\xc3");
+    recorder.output(b"\xa9\r\n");
+    recorder.resize(50, 132);
+    recorder.marker("bob (#2) joined, watching");
+    drop(recorder);
+
+    let cast = Cast::load(&recording.path).unwrap();
+    assert_eq!((cast.header.version, cast.header.width, cast.header.height), (2, 120, 40));
+    assert_eq!(cast.header.crashcart, Some(session()));
+    let events: Vec<(&str, &str)> = cast.events.iter().map(|e| (e.kind.as_str(), e.data.as_str())).collect();
+    assert_eq!(events, [("o", "$ "), ("i", "ls\r"), ("o", "caf"), ("o", "é\r\n"), ("r", "132x50"), ("m", "bob (#2) joined, watching")]);
+    assert!(cast.events.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_recorder_leaves_out_input_and_keeps_files() {
+    let dir = common::temp_path("recording-input");
+    let recording = Recording::in_dir(&dir, session(), 4242);
+    assert_ne!(Recording::in_dir(&dir, session(), 4242).path, recording.path);
+
+    recording.check().unwrap();
+    let mut recorder = Recorder::create(&recording, (24, 80)).unwrap();
+    recorder.output(b"Password: ");
+    recorder.input(b"hunter2\r");
+    drop(recorder);
+    let cast = Cast::load(&recording.path).unwrap();
+    let events: Vec<(&str, &str)> = cast.events.iter().map(|e| (e.kind.as_str(), e.data.as_str())).collect();
+    assert_eq!(events, [("o", "Password: ")]);
+
+    // An existing recording is never overwritten
+    assert!(Recorder::create(&recording, (24, 80)).is_err());
+    let err = recording.check().unwrap_err();
+    assert!(err.to_string().contains("already exists"), "{}", err);
+    assert_eq!(Cast::load(&recording.path).unwrap().events.len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_redacted_replay() {
+    let mut cast = Cast::parse(
+        r#"{"version": 2, "width": 80, "height": 24}
+[0.1, "o", "token=abc"]
+[0.2, "o", "123 ok\r\n"]
+[0.3, "i", "export TOKEN=abc123\r"]
+[5.0, "o", "done"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(cast.header.crashcart, None);
+
+    cast.redact(&[Regex::new(r"abc\d+").unwrap()]);
+    let data: Vec<&str> = cast.events.iter().map(|e| e.data.as_str()).collect();
+    assert_eq!(data, ["token=***", "*** ok\r\n", "export TOKEN=******\r", "done"]);
+
+    let mut out = Vec::new();
+    let started = std::time::Instant::now();
+    cast.play(&mut out, 100.0, Duration::from_millis(10)).await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(String::from_utf8(out).unwrap(), "token=****** ok\r\ndone");
+
+    assert!(Cast::parse(r#"{"version": 1, "width": 80, "height": 24}"#).is_err());
+}
+
+#[test]
+fn test_recording_config() {
+    let config: Config = serde_json::from_str(r#"{"recording": {"always": true}}"#).unwrap();
+    assert!(config.recording.always);
+    assert_eq!(config.recording.dir, PathBuf::from("/var/log/crashcart"));
+
+    let recording = Recording::in_dir(&config.recording.dir, session(), 4242);
+    let name = recording.path.file_name().unwrap().to_string_lossy().into_owned();
+    assert!(name.ends_with("-docker-4242.cast"), "{}", name);
+}
+
+#[test]
+fn test_config_override_keeps_enforced_recording() {
+    let dir = common::temp_path("recording-config");
+    std::fs::create_dir_all(&dir).unwrap();
+    let host = dir.join("host.json");
+    let user = dir.join("user.json");
+    std::fs::write(&user, r#"{"cache": {"enabled": false}, "recording": {"always": false, "dir": "/tmp"}}"#).unwrap();
+
+    // Without a host config the override is used as is
+    let config = Config::load_override(&user, &host).unwrap();
+    assert!(!config.cache.enabled);
+    assert!(!config.recording.always);
+
+    std::fs::write(&host, r#"{"recording": {"always": true, "dir": "/var/log/audit"}}"#).unwrap();
+    let config = Config::load_override(&user, &host).unwrap();
+    assert!(!config.cache.enabled);
+    assert!(config.recording.always);
+    assert_eq!(config.recording.dir, PathBuf::from("/var/log/audit"));
+
+    let err = Config::load_override(&dir.join("missing.json"), &host).unwrap_err();
+    assert!(err.to_string().contains("does not exist"), "{}", err);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_record_input_needs_record() {
+    let output = Command::new(env!("CARGO_BIN_EXE_crashcart"))
+        .args(["--record-input", "pid://1"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--record <FILE>"));
+}
+
+#[test]
+fn test_piped_session_is_recorded() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Running a command in a namespace needs root, skipping");
+        return;
+    }
+
+    let dir = common::temp_path("recording-piped");
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.json");
+    let contents = serde_json::json!({"cache": {"enabled": false}, "recording": {"always": true, "dir": dir.join("casts")}});
+    std::fs::write(&config, contents.to_string()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crashcart"))
+        .env("CRASHCART_CONFIG", &config)
+        .args(["--no-ns", "mnt", &format!("pid://{}", std::process::id()), "--", "sh", "-c", "echo out; echo err >&2"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"out\n");
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with("err\n"));
+
+    let casts: Vec<PathBuf> = std::fs::read_dir(dir.join("casts")).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(casts.len(), 1);
+    let cast = Cast::load(&casts[0]).unwrap();
+    let mut output: Vec<&str> = cast.events.iter().filter(|e| e.kind == "o").map(|e| e.data.as_str()).collect();
+    output.sort();
+    assert_eq!(output, ["err\n", "out\n"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use crashcart::{BackendRegistry, ContainerRuntime, ResolutionCache};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Install a fake `crashcart-resolver-svc` plugin and point crashcart at it.
+fn install_plugin() -> PathBuf {
+    let dir = common::temp_path("resolvers");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let plugin = dir.join("crashcart-resolver-svc");
+    std::fs::write(
+        &plugin,
+        r#"#!/bin/sh
+[ "$1" = "resolve" ] || exit 2
+read target
+case "$target" in
+  svc://checkout/1)
+    echo "{\"pid\": 4242, \"runtime\": \"acme\", \"metadata\": {\"target\": \"$target\"}, \"exec\": [\"sh\", \"-c\", \"exit 7\"]}" ;;
+  svc://checkout/docker)
+    echo "{\"pid\": $PPID, \"runtime\": \"docker\", \"exec\": [\"sh\", \"-c\", \"exit 7\"]}" ;;
+  svc://checkout/stopped)
+    echo '{"pid": 0}' ;;
+  svc://checkout/hung)
+    exec sleep 30 ;;
+  *)
+    echo "unknown instance $target" >&2; exit 1 ;;
+esac
+"#,
+    )
+    .unwrap();
+    std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    std::env::set_var("CRASHCART_RESOLVER_DIR", &dir);
+    dir
+}
+
+#[tokio::test]
+async fn test_resolver_plugin_target() {
+    let dir = install_plugin();
+
+    let runtime = ContainerRuntime::detect("svc://checkout/1").await.unwrap();
+    assert_eq!(runtime.runtime_name(), "acme");
+    assert_eq!(runtime.get_pid().await.unwrap(), 4242);
+    assert_eq!(runtime.inspect().await.unwrap()["target"], "svc://checkout/1");
+    assert_eq!(runtime.exec_command(&["true".to_string()]).await.unwrap(), 7);
+
+    // A plugin naming a registered runtime is still not cached, so the
+    // next attach doesn't go to that runtime with the plugin's target
+    let registry = BackendRegistry::default();
+    let cache = ResolutionCache::new(dir.join("cache"));
+    let runtime = ContainerRuntime::detect_cached(&registry, &cache, "svc://checkout/docker").await.unwrap();
+    assert_eq!(runtime.runtime_name(), "docker");
+    assert!(cache.lookup("svc://checkout/docker").is_none());
+    let runtime = ContainerRuntime::detect_cached(&registry, &cache, "svc://checkout/docker").await.unwrap();
+    assert_eq!(runtime.exec_command(&["true".to_string()]).await.unwrap(), 7);
+
+    let err = ContainerRuntime::detect("svc://checkout/9").await.unwrap_err();
+    assert!(format!("{:#}", err).contains("unknown instance"));
+    let err = ContainerRuntime::detect("svc://checkout/stopped").await.unwrap_err();
+    assert!(format!("{:#}", err).contains("returned PID 0"), "{:#}", err);
+
+    // A plugin that hangs is given up on after the probe timeout
+    let mut registry = BackendRegistry::default();
+    registry.set_timeout(Duration::from_millis(200));
+    let started = Instant::now();
+    let err = ContainerRuntime::detect_with(&registry, "svc://checkout/hung").await.unwrap_err();
+    assert!(err.to_string().contains("did not answer"), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
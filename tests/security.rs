@@ -0,0 +1,83 @@
+use crashcart::exec::{self, ExecSpec};
+use crashcart::security::{parse_apparmor_profile, BpfInstruction, SecurityContext};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+// BPF_RET | BPF_K, SECCOMP_RET_ALLOW
+const ALLOW_ALL: BpfInstruction = BpfInstruction {
+    code: 0x06,
+    jt: 0,
+    jf: 0,
+    k: 0x7fff_0000,
+};
+
+#[test]
+fn test_parse_apparmor_profile() {
+    assert_eq!(parse_apparmor_profile("docker-default (enforce)\n").as_deref(), Some("docker-default"));
+    assert_eq!(parse_apparmor_profile("cri-containerd.apparmor.d (complain)").as_deref(), Some("cri-containerd.apparmor.d"));
+    assert_eq!(parse_apparmor_profile("/usr/sbin/nginx\n").as_deref(), Some("/usr/sbin/nginx"));
+    assert_eq!(parse_apparmor_profile("unconfined\n"), None);
+    assert_eq!(parse_apparmor_profile(""), None);
+}
+
+#[tokio::test]
+async fn test_security_context_is_copied() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Reading seccomp filters needs root, skipping");
+        return;
+    }
+
+    let mut target = Command::new("sleep");
+    target.arg("30").stdin(Stdio::null());
+    unsafe {
+        target.pre_exec(|| {
+            let mut filter = [libc::sock_filter {
+                code: ALLOW_ALL.code,
+                jt: ALLOW_ALL.jt,
+                jf: ALLOW_ALL.jf,
+                k: ALLOW_ALL.k,
+            }];
+            let program = libc::sock_fprog {
+                len: 1,
+                filter: filter.as_mut_ptr(),
+            };
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &program as *const libc::sock_fprog) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut target = target.spawn().unwrap();
+    let pid = target.id();
+
+    let context = match SecurityContext::of_process(pid).await {
+        Ok(context) => context,
+        Err(e) if format!("{:#}", e).contains("CONFIG_CHECKPOINT_RESTORE") => {
+            eprintln!("Kernel can't export seccomp filters, skipping: {:#}", e);
+            target.kill().unwrap();
+            target.wait().unwrap();
+            return;
+        }
+        Err(e) => panic!("{:#}", e),
+    };
+    assert_eq!(context.seccomp_filters, vec![vec![ALLOW_ALL]]);
+    assert!(context.no_new_privs);
+    // The target is left running
+    assert!(target.try_wait().unwrap().is_none());
+
+    let mut spec = ExecSpec::new(vec![
+        "grep".to_string(),
+        "-E".to_string(),
+        "^(NoNewPrivs|Seccomp):".to_string(),
+        "/proc/self/status".to_string(),
+    ]);
+    spec.namespaces.clear();
+    spec.security = Some(context);
+    let output = exec::output(pid, &spec).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "NoNewPrivs:\t1\nSeccomp:\t2\n");
+
+    target.kill().unwrap();
+    target.wait().unwrap();
+}
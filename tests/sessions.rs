@@ -0,0 +1,332 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use crashcart::session::{self, DetachKeys, Frame, Participant, Role, SessionRecord};
+use crashcart::SessionStore;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+/// crashcart keeping its sessions in `dir`, with the resolution cache off.
+fn crashcart(dir: &Path) -> Command {
+    let config = dir.with_extension("json");
+    let contents = serde_json::json!({"cache": {"enabled": false}, "sessions": {"dir": dir}});
+    std::fs::write(&config, contents.to_string()).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_crashcart"));
+    command.env("CRASHCART_CONFIG", config);
+    command
+}
+
+fn record(id: &str, pid: u32, supervisor: u32) -> SessionRecord {
+    SessionRecord {
+        id: id.to_string(),
+        target: format!("pid://{}", pid),
+        pid,
+        command: vec!["bash".to_string(), "-i".to_string()],
+        started: Utc::now(),
+        supervisor,
+        supervisor_start_ticks: 8800,
+        image: None,
+        loop_device: None,
+        clients: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn test_frames_round_trip() {
+    let frames = [
+        Frame::Data(b"ls\r\x00\xff".to_vec()),
+        Frame::Resize(50, 132),
+        Frame::Exit(-1),
+        Frame::Notice("attached from another terminal".to_string()),
+        Frame::Data(Vec::new()),
+    ];
+    let stream: Vec<u8> = frames.iter().flat_map(Frame::encode).collect();
+
+    let mut reader = stream.as_slice();
+    for frame in &frames {
+        assert_eq!(Frame::read(&mut reader).await.unwrap().as_ref(), Some(frame));
+    }
+    assert_eq!(Frame::read(&mut reader).await.unwrap(), None);
+
+    // A resize must carry exactly two sizes
+    assert!(Frame::read(&mut &b"r\0\0\0\x02\0\x18"[..]).await.is_err());
+    assert!(Frame::read(&mut &b"?\0\0\0\0"[..]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_handoff_frames_round_trip() {
+    let frames = [
+        Frame::Hello(Role::Watch, "bob".to_string()),
+        Frame::Hello(Role::Control, "alice".to_string()),
+        Frame::Handoff("#2".to_string()),
+        Frame::Notice("bob (#2) left".to_string()),
+        Frame::Error("Nobody called eve is attached".to_string()),
+    ];
+    let stream: Vec<u8> = frames.iter().flat_map(Frame::encode).collect();
+
+    let mut reader = stream.as_slice();
+    for frame in &frames {
+        assert_eq!(Frame::read(&mut reader).await.unwrap().as_ref(), Some(frame));
+    }
+    assert_eq!(Frame::read(&mut reader).await.unwrap(), None);
+
+    // A hello must name a known role
+    assert!(Frame::read(&mut &b"h\0\0\0\x01x"[..]).await.is_err());
+}
+
+#[test]
+fn test_detach_keys() {
+    let mut keys = DetachKeys::default();
+    assert_eq!(keys.feed(b"ls\r"), (b"ls\r".to_vec(), false));
+
+    // ^P on its own waits for the next byte
+    assert_eq!(keys.feed(b"a\x10"), (b"a".to_vec(), false));
+    assert_eq!(keys.feed(b"\x10b"), (b"\x10\x10b".to_vec(), false));
+
+    // The sequence split across reads still detaches, dropping the rest
+    assert_eq!(keys.feed(b"x\x10"), (b"x".to_vec(), false));
+    assert_eq!(keys.feed(b"\x11ignored"), (Vec::new(), true));
+}
+
+#[test]
+fn test_store_lists_live_sessions() {
+    let dir = common::temp_path("sessions");
+    let proc_root = dir.join("proc");
+    let store = SessionStore::with_proc_root(dir.join("sessions"), &proc_root);
+    assert!(store.list().is_empty());
+
+    // Supervisor 4242 is alive, 4343 has gone
+    std::fs::create_dir_all(proc_root.join("4242")).unwrap();
+    std::fs::write(
+        proc_root.join("4242/stat"),
+        "4242 (crashcart) S 1 4242 4242 0 -1 4194560 0 0 0 0 0 0 0 0 20 0 1 0 8800 0 0",
+    )
+    .unwrap();
+    let mut older = record("ab12cd34", 100, 4242);
+    older.started = Utc::now() - Duration::minutes(5);
+    older.clients = vec![
+        Participant {
+            number: 1,
+            name: "alice".to_string(),
+            driving: false,
+        },
+        Participant {
+            number: 3,
+            name: "bob".to_string(),
+            driving: true,
+        },
+    ];
+    for session in [record("ab98ef76", 200, 4242), older, record("0badc0de", 100, 4343)] {
+        std::fs::create_dir_all(dir.join("sessions").join(&session.id)).unwrap();
+        store.save(&session).unwrap();
+    }
+    // Still starting, so neither listed nor removed
+    std::fs::create_dir_all(dir.join("sessions/5eed5eed")).unwrap();
+
+    let ids: Vec<String> = store.list().into_iter().map(|s| s.id).collect();
+    assert_eq!(ids, ["ab12cd34", "ab98ef76"]);
+    assert!(!dir.join("sessions/0badc0de").exists());
+    assert!(dir.join("sessions/5eed5eed").exists());
+
+    assert_eq!(store.find("ab98").unwrap().pid, 200);
+    assert!(store.find("ab").is_err());
+    assert!(store.find("0bad").is_err());
+    assert_eq!(store.on_target(100).len(), 1);
+
+    let rows = session::table_rows(&store.list(), Utc::now());
+    assert_eq!(rows[0], "ID        TARGET     AGE  CLIENTS            COMMAND");
+    assert_eq!(rows[1], "ab12cd34  pid://100  5m   #1 alice, #3 bob*  bash -i");
+    assert_eq!(rows[2], "ab98ef76  pid://200  0s   -                  bash -i");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_image_holds() {
+    let dir = common::temp_path("holds");
+    let store = SessionStore::new(&dir);
+    assert!(!store.image_in_use(100));
+
+    let hold = store.hold(100).unwrap();
+    assert!(store.image_in_use(100));
+    assert!(!store.image_in_use(200));
+    drop(hold);
+    assert!(!store.image_in_use(100));
+
+    // A hold left by a crashcart that has gone doesn't count
+    std::fs::write(
+        dir.join("holds").join("100.1"),
+        r#"{"pid": 100, "holder": 1, "holder_start_ticks": 1}"#,
+    )
+    .unwrap();
+    assert!(!store.image_in_use(100));
+    assert!(!dir.join("holds").join("100.1").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_detached_session_outlives_crashcart() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Starting a session in the target's namespaces needs root, skipping");
+        return;
+    }
+
+    // Without a terminal crashcart prints the session ID and exits
+    let dir = common::temp_path("detached");
+    let output = crashcart(&dir)
+        .args(["--no-ns", "mnt", "--detach", &format!("pid://{}", std::process::id()), "--", "sleep", "2"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let id = String::from_utf8(output.stdout).unwrap().trim().to_string();
+
+    let store = SessionStore::new(&dir);
+    let session = store.find(&id).unwrap();
+    assert_eq!(session.command, ["sleep", "2"]);
+    assert_eq!(session.pid, std::process::id());
+
+    std::thread::sleep(std::time::Duration::from_secs(4));
+    assert!(store.find(&id).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_file(dir.with_extension("json")).unwrap();
+}
+
+async fn hello(socket: &Path, role: Role, name: &str) -> (OwnedReadHalf, OwnedWriteHalf) {
+    let (reader, mut writer) = UnixStream::connect(socket).await.unwrap().into_split();
+    Frame::Hello(role, name.to_string()).write(&mut writer).await.unwrap();
+    (reader, writer)
+}
+
+/// Output until `text` shows up, failing on anything but data and notices.
+async fn read_until(reader: &mut OwnedReadHalf, text: &str) -> String {
+    let mut seen = String::new();
+    while !seen.contains(text) {
+        match tokio::time::timeout(std::time::Duration::from_secs(5), Frame::read(reader)).await {
+            Ok(Ok(Some(Frame::Data(data)))) => seen.push_str(&String::from_utf8_lossy(&data)),
+            Ok(Ok(Some(Frame::Notice(notice)))) => seen.push_str(&format!("[{}]", notice)),
+            other => panic!("Expected {:?}, got {:?} after {:?}", text, other, seen),
+        }
+    }
+    seen
+}
+
+#[tokio::test]
+async fn test_watchers_and_handoff() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Starting a session in the target's namespaces needs root, skipping");
+        return;
+    }
+
+    let script = "read first; echo \"first:$first\"; read second; echo \"second:$second\"; exit 4";
+    let dir = common::temp_path("shared");
+    let output = crashcart(&dir)
+        .args(["--no-ns", "mnt", "--share", &format!("pid://{}", std::process::id()), "--", "sh", "-c", script])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let id = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    let socket = dir.join(&id).join("socket");
+
+    let store = SessionStore::new(&dir);
+    let (mut alice, mut alice_writer) = hello(&socket, Role::Drive, "alice").await;
+    while store.find(&id).unwrap().clients.is_empty() {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let (mut bob, mut bob_writer) = hello(&socket, Role::Watch, "bob").await;
+    read_until(&mut bob, "alice (#1) drives]").await;
+    read_until(&mut alice, "[bob (#2) joined, watching]").await;
+
+    // Asking to drive while alice does only gets to watch, and watchers
+    // can't hand over control
+    let (mut carol, mut carol_writer) = hello(&socket, Role::Drive, "carol").await;
+    read_until(&mut carol, "alice (#1) drives]").await;
+    read_until(&mut alice, "[carol (#3) joined, watching]").await;
+    Frame::Handoff("carol".to_string()).write(&mut carol_writer).await.unwrap();
+    loop {
+        match Frame::read(&mut carol).await.unwrap() {
+            Some(Frame::Error(text)) => break assert_eq!(text, "Only the driver can hand over control"),
+            Some(Frame::Data(_) | Frame::Notice(_)) => {}
+            other => panic!("Expected an error, got {:?}", other),
+        }
+    }
+    drop((carol, carol_writer));
+    read_until(&mut alice, "[carol (#3) left]").await;
+
+    // Only the driver's input reaches the command
+    Frame::Data(b"ignored\r".to_vec()).write(&mut bob_writer).await.unwrap();
+    Frame::Data(b"one\r".to_vec()).write(&mut alice_writer).await.unwrap();
+    assert!(!read_until(&mut bob, "first:one").await.contains("ignored"));
+
+    let drivers: Vec<String> = store.find(&id).unwrap().clients.into_iter().map(|c| format!("{}", c)).collect();
+    assert_eq!(drivers, ["alice (#1)", "bob (#2)"]);
+    assert!(store.handoff(&store.find(&id).unwrap(), "eve").await.is_err());
+    let confirmation = store.handoff(&store.find(&id).unwrap(), "bob").await.unwrap();
+    assert_eq!(confirmation, format!("bob (#2) has control of session {}", id));
+    read_until(&mut bob, "[You have control]").await;
+
+    Frame::Data(b"ignored\r".to_vec()).write(&mut alice_writer).await.unwrap();
+    Frame::Data(b"two\r".to_vec()).write(&mut bob_writer).await.unwrap();
+    assert!(!read_until(&mut alice, "second:two").await.contains("ignored"));
+    for reader in [&mut alice, &mut bob] {
+        loop {
+            match Frame::read(reader).await.unwrap() {
+                Some(Frame::Exit(code)) => break assert_eq!(code, 4),
+                Some(_) => {}
+                None => panic!("No exit code"),
+            }
+        }
+    }
+
+    // Joins, leaves and handoffs stay in the session's log
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let log = std::fs::read_to_string(dir.join(format!("{}.log", id))).unwrap();
+    assert!(log.contains("bob (#2) joined, watching"));
+    assert!(log.contains("bob (#2) has control, handed over by"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_file(dir.with_extension("json")).unwrap();
+}
+
+#[tokio::test]
+async fn test_unread_input_does_not_stall_output() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Starting a session in the target's namespaces needs root, skipping");
+        return;
+    }
+
+    let dir = common::temp_path("unread");
+    let script = "stty raw -echo; sleep 1; echo done; exit 5";
+    let output = crashcart(&dir)
+        .args(["--no-ns", "mnt", "--share", &format!("pid://{}", std::process::id()), "--", "sh", "-c", script])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let id = String::from_utf8(output.stdout).unwrap().trim().to_string();
+
+    // A paste far bigger than the terminal holds, which the command never reads
+    let (mut alice, mut alice_writer) = hello(&dir.join(&id).join("socket"), Role::Drive, "alice").await;
+    for _ in 0..64 {
+        Frame::Data(vec![b'x'; 16 * 1024]).write(&mut alice_writer).await.unwrap();
+    }
+    read_until(&mut alice, "done").await;
+    loop {
+        match tokio::time::timeout(std::time::Duration::from_secs(5), Frame::read(&mut alice)).await {
+            Ok(Ok(Some(Frame::Exit(code)))) => break assert_eq!(code, 5),
+            Ok(Ok(Some(_))) => {}
+            other => panic!("No exit code, got {:?}", other),
+        }
+    }
+
+    // The supervisor cleans up rather than writing the rest of the paste
+    while dir.join(&id).exists() {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_file(dir.with_extension("json")).unwrap();
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use crashcart::systemd::{MachineBackend, SystemdBackend};
+use crashcart::RuntimeBackend;
+use common::{fake_cgroup, fake_host, fake_process};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+#[tokio::test]
+async fn test_systemd_unit_main_pid() {
+    let (base, cgroup_root, proc_root) = fake_host("systemd-unit");
+    // nginx master forked two workers
+    fake_process(&proc_root, 300, 1, "300");
+    fake_process(&proc_root, 301, 300, "301");
+    fake_process(&proc_root, 302, 300, "302");
+    fake_cgroup(&cgroup_root, "system.slice/nginx.service", &[301, 300, 302]);
+    fake_process(&proc_root, 400, 1, "400");
+    fake_cgroup(&cgroup_root, "user.slice/user-1000.slice/user@1000.service/app.slice/worker.service", &[400]);
+
+    // systemd knows nginx's main PID but has none for the user unit
+    let systemctl = base.join("systemctl");
+    fs::write(
+        &systemctl,
+        "#!/bin/sh\n[ \"$5\" = nginx.service ] && echo 302 || echo 0\n",
+    )
+    .unwrap();
+    fs::set_permissions(&systemctl, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let backend = SystemdBackend::with_roots(&cgroup_root, &proc_root).with_systemctl(&systemctl);
+    assert_eq!(backend.detect("nginx").await.unwrap(), "nginx.service");
+    assert_eq!(backend.resolve_pid("nginx.service").await.unwrap(), 302);
+    assert_eq!(backend.inspect("nginx.service").await.unwrap()["pid"], 302);
+    assert_eq!(backend.resolve_pid("worker.service").await.unwrap(), 400);
+    assert!(backend.detect("missing").await.is_err());
+
+    // Only names with a unit type are probed for without a scheme
+    assert!(backend.claims("nginx.service"));
+    assert!(!backend.claims("nginx"));
+
+    // Without systemctl the oldest process in the unit's cgroup is used
+    let backend = SystemdBackend::with_roots(&cgroup_root, &proc_root).with_systemctl(base.join("missing"));
+    assert_eq!(backend.resolve_pid("nginx.service").await.unwrap(), 300);
+
+    fs::remove_dir_all(base).unwrap();
+}
+
+#[tokio::test]
+async fn test_nspawn_machine_leader_pid() {
+    let (base, cgroup_root, proc_root) = fake_host("systemd-machine");
+    // systemd-nspawn itself and the container's init in the payload group
+    fake_process(&proc_root, 500, 1, "500");
+    fake_process(&proc_root, 501, 500, "501 1");
+    fake_process(&proc_root, 502, 501, "502 20");
+    fake_cgroup(&cgroup_root, "machine.slice/systemd-nspawn@web\\x2d1.service", &[500]);
+    fake_cgroup(&cgroup_root, "machine.slice/systemd-nspawn@web\\x2d1.service/payload", &[501, 502]);
+
+    let backend = MachineBackend::with_roots(&cgroup_root, &proc_root);
+    assert!(backend.is_available().await);
+    assert_eq!(backend.resolve_pid("web-1").await.unwrap(), 501);
+
+    let machines = backend.list().await.unwrap();
+    assert_eq!(machines.len(), 1);
+    assert_eq!(machines[0].name, "web-1");
+    assert_eq!(machines[0].pid, Some(501));
+
+    // systemd escapes each byte of a non-ASCII name
+    fake_process(&proc_root, 600, 1, "600 1");
+    fake_cgroup(&cgroup_root, "machine.slice/machine-caf\\xc3\\xa9.scope", &[600]);
+    assert_eq!(backend.resolve_pid("café").await.unwrap(), 600);
+    let machines = backend.list().await.unwrap();
+    assert!(machines.iter().any(|m| m.name == "café" && m.pid == Some(600)), "{:?}", machines);
+
+    fs::remove_dir_all(base).unwrap();
+}
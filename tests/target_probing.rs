@@ -0,0 +1,167 @@
+mod common;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use crashcart::{BackendRegistry, Config, ContainerRuntime, ResolutionCache, RuntimeBackend};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A backend that knows one container, `web`, and takes `delay` to answer.
+struct SlowBackend {
+    name: &'static str,
+    delay: Duration,
+    detects: AtomicUsize,
+}
+
+impl SlowBackend {
+    fn new(name: &'static str, delay: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            delay,
+            detects: AtomicUsize::new(0),
+        })
+    }
+}
+
+#[async_trait]
+impl RuntimeBackend for SlowBackend {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, target: &str) -> Result<String> {
+        self.detects.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        match target {
+            "web" => Ok(format!("{}-web", self.name)),
+            _ => Err(anyhow!("no container {}", target)),
+        }
+    }
+
+    async fn resolve_pid(&self, _id: &str) -> Result<u32> {
+        Ok(std::process::id())
+    }
+
+    async fn inspect(&self, _id: &str) -> Result<Value> {
+        Ok(Value::Null)
+    }
+}
+
+fn container_id(runtime: &ContainerRuntime) -> &str {
+    match runtime {
+        ContainerRuntime::Container { id, .. } => id,
+        _ => panic!("Expected a container"),
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = common::temp_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test]
+async fn test_hung_backend_times_out() {
+    let mut registry = BackendRegistry::new();
+    registry
+        .register(SlowBackend::new("hung", Duration::from_secs(30)))
+        .register(SlowBackend::new("quick", Duration::ZERO))
+        .set_backend_timeout("hung", Duration::from_millis(100));
+
+    let started = Instant::now();
+    let runtime = ContainerRuntime::detect_with(&registry, "web").await.unwrap();
+    assert_eq!(container_id(&runtime), "quick-web");
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let err = ContainerRuntime::detect_with(&registry, "hung://web").await.unwrap_err();
+    assert!(format!("{:#}", err).contains("did not answer"), "{:#}", err);
+}
+
+#[tokio::test]
+async fn test_probes_run_concurrently_in_priority_order() {
+    let mut registry = BackendRegistry::new();
+    registry
+        .register(SlowBackend::new("first", Duration::from_millis(300)))
+        .register(SlowBackend::new("second", Duration::from_millis(300)))
+        .register(SlowBackend::new("third", Duration::from_millis(300)));
+
+    let started = Instant::now();
+    let runtime = ContainerRuntime::detect_with(&registry, "web").await.unwrap();
+    assert_eq!(container_id(&runtime), "first-web");
+    assert!(started.elapsed() < Duration::from_millis(850));
+}
+
+#[tokio::test]
+async fn test_cached_resolution_skips_probing() {
+    let dir = temp_dir("cache");
+    let backend = SlowBackend::new("slow", Duration::from_millis(50));
+    let mut registry = BackendRegistry::new();
+    registry.register(backend.clone());
+    let cache = ResolutionCache::new(&dir);
+
+    let runtime = ContainerRuntime::detect_cached(&registry, &cache, "web").await.unwrap();
+    assert_eq!(runtime.to_string(), "slow://slow-web");
+    assert_eq!(backend.detects.load(Ordering::SeqCst), 1);
+
+    let entry = cache.lookup("web").unwrap();
+    assert_eq!((entry.runtime.as_str(), entry.id.as_str()), ("slow", "slow-web"));
+    assert_eq!(entry.pid, std::process::id());
+
+    let runtime = ContainerRuntime::detect_cached(&registry, &cache, "web").await.unwrap();
+    assert_eq!(runtime.to_string(), "slow://slow-web");
+    assert_eq!(runtime.get_pid().await.unwrap(), std::process::id());
+    assert_eq!(backend.detects.load(Ordering::SeqCst), 1);
+
+    cache.remove("web").unwrap();
+    assert!(cache.lookup("web").is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_stale_cache_entry_is_ignored() {
+    let dir = temp_dir("cache-stale");
+    let proc_root = dir.join("proc");
+    let stat = proc_root.join("4242/stat");
+    std::fs::create_dir_all(stat.parent().unwrap()).unwrap();
+    let stat_line = |ticks: u64| {
+        format!("4242 (my app) S 1 4242 4242 0 -1 4194560 0 0 0 0 0 0 0 0 20 0 1 0 {} 0 0", ticks)
+    };
+
+    std::fs::write(&stat, stat_line(8800)).unwrap();
+    let cache = ResolutionCache::with_proc_root(dir.join("cache"), &proc_root);
+    cache.store("api", "docker", "4f1b", 4242).unwrap();
+    assert_eq!(cache.lookup("api").unwrap().start_ticks, 8800);
+
+    // Same PID, different process
+    std::fs::write(&stat, stat_line(9100)).unwrap();
+    assert!(cache.lookup("api").is_none());
+
+    std::fs::remove_file(&stat).unwrap();
+    assert!(cache.store("api", "docker", "4f1b", 4242).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_config_defaults_and_overrides() {
+    let config = Config::default();
+    assert_eq!(config.probe.timeout(), Duration::from_secs(3));
+    assert!(config.cache.enabled);
+    assert_eq!(config.cache.dir, PathBuf::from("/run/crashcart"));
+
+    let config: Config = serde_json::from_str(
+        r#"{"probe": {"timeout_ms": 500, "backend_timeouts_ms": {"cri": 5000}}, "cache": {"enabled": false}}"#,
+    )
+    .unwrap();
+    let registry = BackendRegistry::from_config(&config);
+    assert_eq!(registry.timeout_for("docker"), Duration::from_millis(500));
+    assert_eq!(registry.timeout_for("cri"), Duration::from_secs(5));
+    assert!(!config.cache.enabled);
+    assert_eq!(config.cache.dir, PathBuf::from("/run/crashcart"));
+}
@@ -0,0 +1,82 @@
+use crashcart::exec::{self, ExecSpec};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn crashcart(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_crashcart"));
+    command.args(["--no-ns", "mnt", &format!("pid://{}", std::process::id()), "--"]).args(args);
+    command
+}
+
+#[test]
+fn test_exec_flags_without_terminal() {
+    // cargo test doesn't give tests a terminal
+    if !crashcart::terminal::stdin_is_tty() {
+        assert_eq!(crashcart::terminal::exec_flags(), "-i");
+    }
+}
+
+#[test]
+fn test_piped_output_is_byte_exact() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Running a command in a namespace needs root, skipping");
+        return;
+    }
+
+    let output = crashcart(&["printf", r"\000\r\n\377"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\0\r\n\xff");
+}
+
+#[test]
+fn test_terminal_gets_its_own_pty() {
+    if unsafe { libc::geteuid() } != 0 || !Path::new("/usr/bin/script").exists() {
+        eprintln!("Needs root and script(1), skipping");
+        return;
+    }
+
+    // script(1) gives crashcart a terminal; the command should see a
+    // different one, with crashcart's exit code passed through
+    let inner = "[ \"$(tty)\" != \"$OUTER_TTY\" ] && echo own-pty; exit 3";
+    let command = format!(
+        "OUTER_TTY=$(tty) {} --no-ns mnt pid://{} -- sh -c '{}' 2>/dev/null",
+        env!("CARGO_BIN_EXE_crashcart"),
+        std::process::id(),
+        inner
+    );
+    let output = Command::new("script")
+        .args(["-eqc", &command, "/dev/null"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("own-pty\r\n"));
+}
+
+#[tokio::test]
+async fn test_signals_are_forwarded_only_while_a_command_runs() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Running a command in a namespace needs root, skipping");
+        return;
+    }
+
+    let handler = |signal| {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe { libc::sigaction(signal, std::ptr::null(), &mut action) };
+        action.sa_sigaction
+    };
+    let spec = ExecSpec::new(vec!["sh".to_string(), "-c".to_string(), "sleep 0.5; kill -TERM $CRASHCART; exec sleep 5".to_string()])
+        .env("CRASHCART", &std::process::id().to_string());
+
+    // SIGTERM reaches the command instead of crashcart, every time
+    for _ in 0..2 {
+        let output = exec::output(std::process::id(), &spec).await.unwrap();
+        assert_eq!(exec::exit_code(output.status), 128 + libc::SIGTERM);
+        assert_eq!(handler(libc::SIGTERM), libc::SIG_DFL);
+        assert_eq!(handler(libc::SIGINT), libc::SIG_DFL);
+    }
+}